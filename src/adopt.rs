@@ -0,0 +1,106 @@
+use dusa_collection_utils::log;
+use dusa_collection_utils::log::LogLevel;
+use nix::{
+    sys::signal::{self, Signal},
+    unistd::{getpgid, Pid},
+};
+use std::{fs, path::Path};
+
+use crate::{
+    child::{child_command, pid_file},
+    config::{AppSpecificConfig, Runtime},
+};
+
+/// A child left running by an earlier runner. It isn't ours to wait on, so
+/// liveness is checked with signal 0 and no resource metrics are available.
+pub struct AdoptedChild {
+    pid: Pid,
+}
+
+impl AdoptedChild {
+    pub fn pid(&self) -> u32 {
+        self.pid.as_raw() as u32
+    }
+
+    pub fn running(&self) -> bool {
+        signal::kill(self.pid, None).is_ok()
+    }
+
+    pub fn kill(&self) -> Result<(), nix::Error> {
+        signal::kill(self.pid, Signal::SIGKILL)
+    }
+}
+
+/// Looks for a child an earlier runner left behind for this instance and
+/// returns it along with the port it serves on, if the process behind the pid
+/// file is alive, leads its own process group and runs the expected command.
+pub fn adopt_child(settings: &AppSpecificConfig, app_name: &str, index: u16) -> Option<(AdoptedChild, u16)> {
+    // A leftover container is removed and started fresh instead
+    if !settings.adopt_running_child || settings.runtime == Runtime::Container {
+        return None;
+    }
+
+    let path = pid_file(app_name, index);
+    let pid: i32 = fs::read_to_string(&path).ok()?.trim().parse().ok()?;
+    let pid = Pid::from_raw(pid);
+
+    if pid.as_raw() <= 0 || signal::kill(pid, None).is_err() {
+        return None;
+    }
+
+    // Every child is spawned as a group leader, a recycled pid most likely isn't
+    if getpgid(Some(pid)).ok()? != pid {
+        log!(LogLevel::Debug, "Pid {} from {} doesn't lead its process group, not adopting it", pid, path);
+        return None;
+    }
+
+    let proc_dir = Path::new("/proc").join(pid.to_string());
+    let cmdline = fs::read(proc_dir.join("cmdline")).ok()?;
+    if !matches_command(settings, &split_nul(&cmdline)) {
+        log!(LogLevel::Debug, "Pid {} from {} runs something else, not adopting it", pid, path);
+        return None;
+    }
+
+    let port: u16 = fs::read(proc_dir.join("environ"))
+        .ok()
+        .and_then(|environ| {
+            split_nul(&environ)
+                .iter()
+                .find_map(|variable| variable.strip_prefix("PORT=").and_then(|port| port.parse().ok()))
+        })
+        .or_else(|| settings.instance_port(index).ok())?;
+
+    log!(LogLevel::Info, "Adopting child {} (instance {} on port {}) left by a previous runner", pid, index, port);
+    Some((AdoptedChild { pid }, port))
+}
+
+fn split_nul(data: &[u8]) -> Vec<String> {
+    data.split(|byte| *byte == 0)
+        .filter(|part| !part.is_empty())
+        .map(|part| String::from_utf8_lossy(part).into_owned())
+        .collect()
+}
+
+/// Package managers rewrite their process title, so the match is loose: the
+/// program has to show up and so does the last argument (usually the script).
+fn matches_command(settings: &AppSpecificConfig, cmdline: &[String]) -> bool {
+    let command = child_command(settings);
+    let command = command.as_std();
+
+    let program = match Path::new(command.get_program()).file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => return false,
+    };
+
+    let words: Vec<&str> = cmdline.iter().flat_map(|part| part.split_whitespace()).collect();
+    let program_found = words
+        .iter()
+        .any(|word| Path::new(word).file_name().is_some_and(|name| name.to_string_lossy() == program));
+
+    let last_arg_found = match command.get_args().last() {
+        Some(arg) => words.iter().any(|word| *word == arg.to_string_lossy()),
+        None => true,
+    };
+
+    program_found && last_arg_found
+}
@@ -0,0 +1,177 @@
+use artisan_middleware::config::AppConfig;
+use colored::Colorize;
+use nix::unistd::{access, AccessFlags};
+use std::{
+    env,
+    net::TcpListener,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config::{application_state_path, config_file, specific_configs, AppSpecificConfig, LogDestination, Runtime},
+    dryrun::{redacted, secret_values},
+};
+
+/// Exit codes of `check`
+pub const CHECK_OK: i32 = 0;
+pub const CHECK_FAILED: i32 = 1;
+pub const CHECK_UNLOADABLE: i32 = 2;
+
+/// Loads both configs, validates them against the host and prints the
+/// effective settings with their secrets blanked. Returns the exit code for `ais_generic check`.
+pub fn run_check() -> i32 {
+    if let Err(err) = AppConfig::new() {
+        println!("{} AppConfig doesn't load: {}", "error:".red().bold(), err);
+        return CHECK_UNLOADABLE;
+    }
+
+    let applications = match specific_configs() {
+        Ok(applications) => applications,
+        Err(err) => {
+            println!("{} {} doesn't load: {}", "error:".red().bold(), config_file().display(), err);
+            return CHECK_UNLOADABLE;
+        }
+    };
+
+    let mut problems: Vec<String> = Vec::new();
+    let mut claimed_ports: Vec<u16> = Vec::new();
+    for settings in &applications {
+        // Settings come back decrypted, secrets are blanked like in --dry-run,
+        // also in the escaped form Debug prints them in
+        let secrets: Vec<String> = secret_values(settings)
+            .into_iter()
+            .flat_map(|secret| [format!("{:?}", secret).trim_matches('"').to_owned(), secret])
+            .collect();
+        println!("{}", redacted(format!("{:#?}", settings), &secrets));
+        println!();
+
+        let mut found: Vec<String> = Vec::new();
+        check_directory(&mut found, "monitor_path", Path::new(&settings.monitor_path), AccessFlags::R_OK | AccessFlags::X_OK);
+        check_directory(&mut found, "project_path", Path::new(&settings.project_path), AccessFlags::R_OK | AccessFlags::W_OK | AccessFlags::X_OK);
+
+        let state_path = application_state_path(settings);
+        if let Some(state_dir) = state_path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            // A configured state directory is created on start, it only has to be creatable
+            match settings.state.directory().is_some() && !state_dir.exists() {
+                true => check_creatable(&mut found, "state directory", state_dir),
+                false => check_directory(&mut found, "state directory", state_dir, AccessFlags::W_OK),
+            }
+        }
+        for route in [&settings.logging.runner, &settings.logging.child] {
+            if let Some(file) = route.file.as_ref().filter(|_| route.destination == Some(LogDestination::File)) {
+                let file = Path::new(file);
+                match file.exists() {
+                    true if access(file, AccessFlags::W_OK).is_err() => found.push(format!("logging file {} isn't writable", file.display())),
+                    true => (),
+                    false => check_creatable(&mut found, "logging file", file),
+                }
+            }
+        }
+        for root in &settings.watch_roots {
+            check_directory(&mut found, "watch_roots", Path::new(&root.path), AccessFlags::R_OK | AccessFlags::X_OK);
+        }
+
+        for program in required_programs(settings) {
+            if find_program(&program).is_none() {
+                found.push(format!("{} isn't installed or not on PATH", program));
+            }
+        }
+
+        for port in ports(settings) {
+            if claimed_ports.contains(&port) {
+                found.push(format!("port {} is already used by another application", port));
+            } else if let Err(err) = TcpListener::bind(("127.0.0.1", port)) {
+                found.push(format!("port {} isn't available: {}", port, err));
+            }
+            claimed_ports.push(port);
+        }
+
+        // Problems of [[applications]] entries say which one they belong to
+        match &settings.name {
+            Some(name) => problems.extend(found.into_iter().map(|problem| format!("{}: {}", name, problem))),
+            None => problems.extend(found),
+        }
+    }
+
+    if problems.is_empty() {
+        println!("{} configuration and environment look good", "ok:".green().bold());
+        return CHECK_OK;
+    }
+
+    for problem in &problems {
+        println!("{} {}", "problem:".red().bold(), problem);
+    }
+    CHECK_FAILED
+}
+
+fn check_directory(problems: &mut Vec<String>, setting: &str, path: &Path, flags: AccessFlags) {
+    if !path.is_dir() {
+        problems.push(format!("{} {} isn't a directory", setting, path.display()));
+        return;
+    }
+
+    if access(path, flags).is_err() {
+        problems.push(format!("{} {} lacks the needed permissions", setting, path.display()));
+    }
+}
+
+/// The closest existing ancestor has to let the path be created
+fn check_creatable(problems: &mut Vec<String>, setting: &str, path: &Path) {
+    match path.ancestors().find(|ancestor| ancestor.exists()) {
+        Some(ancestor) if access(ancestor, AccessFlags::W_OK | AccessFlags::X_OK).is_ok() => (),
+        _ => problems.push(format!("{} {} can't be created", setting, path.display())),
+    }
+}
+
+/// Executables the runner will start for these settings
+fn required_programs(settings: &AppSpecificConfig) -> Vec<String> {
+    let mut programs = Vec::new();
+
+    match settings.runtime {
+        Runtime::Container => programs.push(settings.container.engine.program().to_owned()),
+        Runtime::Process => {
+            programs.push(settings.package_manager().program().to_owned());
+            if let Some(command) = &settings.command {
+                programs.push(command.clone());
+            }
+            if let Some(command) = settings.one_shot_command.as_ref().filter(|_| settings.build_steps.is_empty()) {
+                programs.push(command.clone());
+            }
+        }
+    }
+
+    if settings.use_systemd_scope {
+        programs.push(String::from("systemd-run"));
+    }
+    if settings.remote.host.is_some() {
+        programs.push(String::from("ssh"));
+    }
+    if settings.git_head_trigger {
+        programs.push(String::from("git"));
+    }
+
+    programs
+}
+
+/// Every port a child may be handed
+fn ports(settings: &AppSpecificConfig) -> Vec<u16> {
+    let mut ports: Vec<u16> = (0..settings.instances.max(1))
+        .filter_map(|index| settings.instance_port(index).ok())
+        .collect();
+    if let Some(alternate) = settings.zero_downtime.alternate_port.filter(|_| settings.zero_downtime.enabled) {
+        ports.push(alternate);
+    }
+    ports
+}
+
+fn find_program(program: &str) -> Option<PathBuf> {
+    if program.contains('/') {
+        return Some(PathBuf::from(program)).filter(|path| path.is_file());
+    }
+
+    env::var_os("PATH").and_then(|paths| {
+        env::split_paths(&paths)
+            .map(|dir| dir.join(program))
+            .find(|candidate| candidate.is_file())
+    })
+}
@@ -1,94 +1,708 @@
 use artisan_middleware::{
-    common::{log_error, update_state, wind_down_state},
+    common::{log_error, update_state},
     process_manager::{spawn_complex_process, SupervisedChild},
+    resource_monitor::Metrics,
     state_persistence::AppState,
 };
 use dusa_collection_utils::{errors::ErrorArrayItem, log, types::PathType};
 use dusa_collection_utils::log::LogLevel;
-use std::{ffi::c_int, fs, process::Stdio};
-use tokio::process::Command;
+use nix::{
+    sys::{
+        signal::{self, Signal},
+        wait::{waitid, Id, WaitPidFlag, WaitStatus},
+    },
+    unistd::{Pid, Uid},
+};
+use std::{
+    ffi::c_int,
+    fmt, fs,
+    path::{Path, PathBuf},
+    process::{Output, Stdio},
+    time::Duration,
+};
+use tokio::{
+    process::Command,
+    time::{sleep, timeout, Instant},
+};
+
+use crate::{
+    adopt::AdoptedChild,
+    cleanup::register_artifact,
+    config::{AppSpecificConfig, Runtime},
+    container::{self, container_name, remove_container, run_command},
+    health::{wait_for_free_port, wait_for_port, HealthChecker},
+    logs::{capture_child_output, write_build_log},
+    restart::CrashTracker,
+    sandbox::{apply_identity, apply_limits, apply_priority, child_cgroup, CgroupTarget},
+    secrets,
+    stdin::attach_stdin,
+};
+
+/// The process an instance supervises, either spawned by this runner or
+/// adopted from an earlier one that went away while the child kept running.
+pub enum ChildHandle {
+    Spawned(SupervisedChild),
+    Adopted(AdoptedChild),
+    Blocked(u16), // Not started, something else listens on the port
+}
+
+impl ChildHandle {
+    pub async fn running(&self) -> bool {
+        match self {
+            ChildHandle::Spawned(child) => child.clone().await.running().await,
+            ChildHandle::Adopted(child) => child.running(),
+            ChildHandle::Blocked(_) => false,
+        }
+    }
+
+    pub async fn get_pid(&self) -> Result<u32, ErrorArrayItem> {
+        match self {
+            ChildHandle::Spawned(child) => child.get_pid().await,
+            ChildHandle::Adopted(child) => Ok(child.pid()),
+            ChildHandle::Blocked(port) => Err(port_in_use(*port)),
+        }
+    }
+
+    pub async fn kill(&mut self) -> Result<(), ErrorArrayItem> {
+        match self {
+            ChildHandle::Spawned(child) => child.kill().await,
+            ChildHandle::Adopted(child) => child.kill().map_err(|err| {
+                ErrorArrayItem::new(dusa_collection_utils::errors::Errors::GeneralError, err.to_string())
+            }),
+            ChildHandle::Blocked(_) => Ok(()),
+        }
+    }
+
+    pub async fn get_metrics(&self) -> Result<Metrics, ErrorArrayItem> {
+        match self {
+            ChildHandle::Spawned(child) => child.get_metrics().await,
+            ChildHandle::Adopted(_) => Err(ErrorArrayItem::new(
+                dusa_collection_utils::errors::Errors::GeneralError,
+                "No metrics for an adopted child until it is restarted".to_string(),
+            )),
+            ChildHandle::Blocked(port) => Err(port_in_use(*port)),
+        }
+    }
+}
+
+fn port_in_use(port: u16) -> ErrorArrayItem {
+    ErrorArrayItem::new(
+        dusa_collection_utils::errors::Errors::GeneralError,
+        format!("Port {} is in use, the child isn't started until it is free", port),
+    )
+}
+
+/// How a child process ended
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChildExit {
+    Code(i32),
+    Signal(Signal),
+}
+
+impl ChildExit {
+    pub fn is_clean(&self) -> bool {
+        *self == ChildExit::Code(0)
+    }
+}
+
+impl fmt::Display for ChildExit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChildExit::Code(code) => write!(f, "exit code {}", code),
+            ChildExit::Signal(signal) => write!(f, "signal {}", signal),
+        }
+    }
+}
+
+/// Looks at the exit status of an exited child without reaping it, so the
+/// supervisor can still collect it afterwards.
+fn peek_exit_status(pid: u32) -> Option<ChildExit> {
+    let flags = WaitPidFlag::WEXITED | WaitPidFlag::WNOHANG | WaitPidFlag::WNOWAIT;
+
+    match waitid(Id::Pid(Pid::from_raw(pid as i32)), flags) {
+        Ok(WaitStatus::Exited(_, code)) => Some(ChildExit::Code(code)),
+        Ok(WaitStatus::Signaled(_, signal, _)) => Some(ChildExit::Signal(signal)),
+        _ => None,
+    }
+}
 
-use crate::config::AppSpecificConfig;
+/// Default `port`, handed to the first child through `PORT`
+pub const DEFAULT_PORT: u16 = 3080;
 
+/// One supervised copy of the child. Cluster mode runs `instances` of these
+/// side by side, each on its own port.
+pub struct Instance {
+    pub index: u16,
+    pub port: u16,
+    pub child: ChildHandle,
+    pub crash_tracker: CrashTracker,
+    pub health_checker: Option<HealthChecker>,
+    pub last_exit: Option<ChildExit>,
+    pub cpu_over_since: Option<Instant>, // Start of the current stretch above max_cpu_usage
+}
+
+impl Instance {
+    pub fn new(settings: &AppSpecificConfig, index: u16, port: u16, child: ChildHandle) -> Self {
+        Self {
+            index,
+            port,
+            child,
+            crash_tracker: CrashTracker::new(settings),
+            health_checker: HealthChecker::new(&settings.health_check, port),
+            last_exit: None,
+            cpu_over_since: None,
+        }
+    }
+
+    /// Remembers how the child exited if it already did. Has to run before
+    /// `running()`, which reaps the child and takes the status with it.
+    pub async fn observe_exit(&mut self) {
+        if let Ok(pid) = self.child.get_pid().await {
+            if let Some(exit) = peek_exit_status(pid) {
+                self.last_exit = Some(exit);
+            }
+        }
+    }
+
+    /// Moves the instance to a new port, the health check follows it
+    pub fn set_port(&mut self, settings: &AppSpecificConfig, port: u16) {
+        self.port = port;
+        self.health_checker = HealthChecker::new(&settings.health_check, port);
+    }
+}
+
+/// Pid file of an instance, the first instance keeps the historical name
+pub fn pid_file(app_name: &str, index: u16) -> PathType {
+    match index {
+        0 => PathType::Content(format!("/tmp/.{}_pg.pid", app_name)),
+        _ => PathType::Content(format!("/tmp/.{}_pg.{}.pid", app_name, index)),
+    }
+}
+
+/// Spawns one instance on `port`. A child that can't be spawned is an error
+/// for its application only, the caller decides what becomes of it.
 pub async fn create_child(
     mut state: &mut AppState,
     state_path: &PathType,
     settings: &AppSpecificConfig,
-) -> SupervisedChild {
+    index: u16,
+    port: u16,
+) -> Result<ChildHandle, ErrorArrayItem> {
     log!(LogLevel::Trace, "Creating child process...");
 
-    let mut command = Command::new("npm");
+    // Another process on the port would only make the child crash on startup, over and over
+    if !wait_for_free_port(port).await {
+        let error_item = port_in_use(port);
+        log!(LogLevel::Error, "Instance {}: {}", index, error_item);
+        state.data = format!("Instance {} waiting for port {}", index, port);
+        log_error(&mut state, error_item, &state_path).await;
+        return Ok(ChildHandle::Blocked(port));
+    }
 
-    command
-        .args(&["--prefix", &settings.clone().project_path, "run", "start"]) // Updated to run "build" instead of "start"
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .env("NODE_ENV", "production") // Set NODE_ENV=production
-        .env("PORT", "3080"); // Set PORT=3000
+    let app_name = state.config.app_name.to_string();
+    if settings.runtime == Runtime::Container {
+        remove_container(settings, &container_name(&app_name, port)).await;
+    }
+
+    let cgroup = child_cgroup(&settings.limits, state.config.max_ram_usage as u64, index);
+    let (mut command, cgroup) = instance_command(settings, &app_name, port, cgroup);
 
+    if !capture_child_output(&mut command, settings, &app_name) {
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    }
+
+    attach_stdin(&mut command, &settings.stdin, settings.name.as_deref(), index);
+    apply_limits(&mut command, &settings.limits, cgroup);
+    apply_priority(&mut command, &settings.priority);
+
+    // A scope drops privileges through systemd-run, which has to talk to systemd as root first
+    let identity = match settings.use_systemd_scope {
+        true => Ok(()),
+        false => apply_identity(&mut command, settings),
+    };
+
+    if let Err(err) = identity {
+        log!(LogLevel::Error, "Refusing to spawn the child: {}", err);
+        return Err(ErrorArrayItem::new(dusa_collection_utils::errors::Errors::GeneralError, err));
+    }
+
+    if let Err(err) = run_hook(settings, "pre_start_hook", &settings.pre_start_hook, Some(port)).await {
+        log!(LogLevel::Warn, "{}", err);
+        let error_item = ErrorArrayItem::new(dusa_collection_utils::errors::Errors::GeneralError, err);
+        log_error(&mut state, error_item, &state_path).await;
+    }
 
     match spawn_complex_process(command, false, true).await { //TODO change this back
         Ok(spawned_child) => {
             // initialize monitor loop.
             spawned_child.monitor_usage().await;
             // read the pid from the state
-            let pid: u32 = match spawned_child.get_pid().await {
-                Ok(xid) => xid,
-                Err(_) => {
-                    let error_item = ErrorArrayItem::new(
-                        dusa_collection_utils::errors::Errors::InputOutput,
-                        "No pid for supervised child".to_owned(),
-                    );
-                    log_error(state, error_item, &state_path).await;
-                    wind_down_state(state, &state_path).await;
-                    std::process::exit(100);
+            // Without a pid file the child couldn't be adopted or told apart, it isn't kept
+            let pid_file: PathType = pid_file(&state.config.app_name, index);
+            let saved = match spawned_child.get_pid().await {
+                Ok(pid) => {
+                    register_artifact(&pid_file);
+                    fs::write(&pid_file, pid.to_string())
+                        .map_err(|err| format!("Couldn't write pid file {}: {}", pid_file, err))
                 }
+                Err(_) => Err(String::from("No pid for supervised child")),
             };
 
-            // save the pid somewhere
-            let pid_file: PathType =
-                PathType::Content(format!("/tmp/.{}_pg.pid", state.config.app_name));
-
-            if let Err(error) = fs::write(pid_file, pid.to_string()) {
-                let error_ref = error.get_ref().unwrap_or_else(|| {
-                    log!(LogLevel::Trace, "{:?}", error);
-                    std::process::exit(100);
-                });
-
-                let error_item = ErrorArrayItem::new(
-                    dusa_collection_utils::errors::Errors::InputOutput,
-                    error_ref.to_string(),
-                );
-                log_error(&mut state, error_item, &state_path).await;
-                wind_down_state(&mut state, &state_path).await;
-                std::process::exit(100);
+            if let Err(message) = saved {
+                log!(LogLevel::Error, "{}", message);
+                let mut child = ChildHandle::Spawned(spawned_child);
+                if let Err(err) = stop_child(&mut child, settings).await {
+                    log!(LogLevel::Warn, "Failed to stop the child without a pid file: {}", err);
+                }
+                return Err(ErrorArrayItem::new(dusa_collection_utils::errors::Errors::InputOutput, message));
             }
             log!(LogLevel::Info, "Child process spawned, pid info saved");
 
+            let spawned_child = ChildHandle::Spawned(spawned_child);
+
+            if settings.readiness.enabled {
+                let port: u16 = settings.readiness.port.unwrap_or(port);
+                let limit = Duration::from_secs(settings.readiness.timeout_seconds);
+
+                if wait_for_port(&spawned_child, &settings.readiness.host, port, limit).await {
+                    log!(LogLevel::Info, "Child is accepting connections on port {}", port);
+                } else {
+                    let error_item = ErrorArrayItem::new(
+                        dusa_collection_utils::errors::Errors::GeneralError,
+                        format!(
+                            "Child did not accept connections on port {} within {}s",
+                            port, settings.readiness.timeout_seconds
+                        ),
+                    );
+                    log_error(&mut state, error_item, &state_path).await;
+                }
+            }
+
             if let Ok(metrics) = spawned_child.get_metrics().await {
                 update_state(&mut state, &state_path, Some(metrics)).await;
             }
-            return spawned_child;
+            Ok(spawned_child)
         }
         Err(error) => {
-            log_error(&mut state, error, &state_path).await;
-            wind_down_state(&mut state, &state_path).await;
-            std::process::exit(100);
+            log!(LogLevel::Error, "Failed to spawn the child: {}", error);
+            Err(error)
+        }
+    }
+}
+
+/// Waits out `respawn_delay_seconds` between stopping a child and spawning its
+/// successor, so ports get released and downstream checks settle.
+pub async fn respawn_cooldown(settings: &AppSpecificConfig) {
+    if settings.respawn_delay_seconds == 0 {
+        return;
+    }
+
+    log!(LogLevel::Debug, "Waiting {}s before spawning the child", settings.respawn_delay_seconds);
+    sleep(Duration::from_secs(settings.respawn_delay_seconds)).await;
+}
+
+/// Zero downtime restart: spawns the replacement on `port` and only stops
+/// `current` once the replacement accepts connections. If it never does, the
+/// replacement is stopped instead and `current` keeps serving.
+pub async fn replace_child(
+    state: &mut AppState,
+    state_path: &PathType,
+    settings: &AppSpecificConfig,
+    current: &mut ChildHandle,
+    index: u16,
+    port: u16,
+) -> Option<ChildHandle> {
+    log!(LogLevel::Info, "Starting replacement child on port {}", port);
+    let mut replacement = match create_child(state, state_path, settings, index, port).await {
+        Ok(replacement) => replacement,
+        Err(err) => {
+            log_error(state, err, state_path).await;
+            return None;
+        }
+    };
+    let limit = Duration::from_secs(settings.readiness.timeout_seconds);
+
+    if !wait_for_port(&replacement, &settings.readiness.host, port, limit).await {
+        let error_item = ErrorArrayItem::new(
+            dusa_collection_utils::errors::Errors::GeneralError,
+            format!("Replacement child never became ready on port {}, keeping the current one", port),
+        );
+        log_error(state, error_item, state_path).await;
+
+        if let Err(err) = stop_child(&mut replacement, settings).await {
+            log_error(state, err, state_path).await;
+        }
+        return None;
+    }
+
+    log!(LogLevel::Info, "Replacement ready on port {}, stopping the previous child", port);
+    if let Err(err) = stop_child(current, settings).await {
+        log!(LogLevel::Error, "Failed to stop the previous child: {}", err);
+        log_error(state, err, state_path).await;
+    }
+
+    if let Some(port_file) = &settings.zero_downtime.port_file {
+        if let Err(err) = fs::write(port_file, port.to_string()) {
+            log!(LogLevel::Warn, "Failed to write the active port to {}: {}", port_file, err);
+        }
+    }
+
+    Some(replacement)
+}
+
+/// Restarts every instance one at a time so the others keep serving. A single
+/// instance swaps ports when zero downtime is configured, in cluster mode each
+/// instance has to accept connections again before the next one goes down.
+/// An instance that can't be spawned again is returned as an error.
+pub async fn rolling_restart(
+    state: &mut AppState,
+    state_path: &PathType,
+    settings: &AppSpecificConfig,
+    instances: &mut [Instance],
+) -> Result<(), ErrorArrayItem> {
+    let instances_count = instances.len();
+
+    for instance in instances.iter_mut() {
+        let swap_port = match instances_count {
+            1 => settings.zero_downtime.next_port(settings.port, instance.port),
+            _ => None,
+        };
+
+        match swap_port {
+            Some(next_port) => {
+                if let Some(replacement) = replace_child(state, state_path, settings, &mut instance.child, instance.index, next_port).await {
+                    instance.child = replacement;
+                    instance.set_port(settings, next_port);
+                    instance.crash_tracker.reset();
+                    log!(LogLevel::Info, "New child process serving on port {}.", instance.port);
+                }
+            },
+            None => {
+                log!(LogLevel::Info, "Killing the child (instance {})", instance.index);
+
+                match stop_child(&mut instance.child, settings).await {
+                    Ok(_) => {
+                        // creating new child
+                        respawn_cooldown(settings).await;
+                        instance.child = create_child(state, state_path, settings, instance.index, instance.port).await?;
+                        instance.crash_tracker.reset();
+                        log!(LogLevel::Info, "New child process spawned.");
+                    },
+                    Err(error) => {
+                        log!(LogLevel::Error, "Failed to wait for child process termination: {}", error);
+                        log_error(state, error, state_path).await;
+                    },
+                }
+
+                // Don't take the next instance down until this one serves again
+                if instances_count > 1 {
+                    let limit = Duration::from_secs(settings.readiness.timeout_seconds);
+                    if !wait_for_port(&instance.child, &settings.readiness.host, instance.port, limit).await {
+                        let message = format!("Instance {} never became ready on port {}, halting the rolling restart", instance.index, instance.port);
+                        log!(LogLevel::Error, "{}", message);
+                        log_error(state, ErrorArrayItem::new(dusa_collection_utils::errors::Errors::GeneralError, message), state_path).await;
+                        break;
+                    }
+                }
+            },
+        }
+    }
+
+    Ok(())
+}
+
+/// The command of the instance on `port` with its working directory and
+/// environment, and the cgroup still to be applied along with the limits.
+/// Nothing is prepared or spawned yet.
+pub fn instance_command(
+    settings: &AppSpecificConfig,
+    app_name: &str,
+    port: u16,
+    cgroup: Option<CgroupTarget>,
+) -> (Command, Option<CgroupTarget>) {
+    let (mut command, cgroup) = match (settings.runtime, settings.use_systemd_scope) {
+        // The limits are handed to the engine, they'd only confine its client here
+        (Runtime::Container, _) => (
+            run_command(settings, &container_name(app_name, port), port, &environment_keys(settings), cgroup.as_ref()),
+            None,
+        ),
+        // systemd moves the child into its scope, the limits become scope properties instead
+        (Runtime::Process, true) => (scoped_command(settings, child_command(settings), cgroup.as_ref()), None),
+        (Runtime::Process, false) => (child_command(settings), cgroup),
+    };
+
+    // Own process group so npm and the node grandchild can be signalled together
+    command.process_group(0);
+
+    if let Some(working_dir) = settings.working_dir() {
+        command.current_dir(working_dir);
+    }
+
+    let port_value = port.to_string();
+    apply_environment(&mut command, settings, &[("NODE_ENV", settings.node_env.as_str()), ("PORT", &port_value)]);
+    (command, cgroup)
+}
+
+/// Builds the supervised command, falling back to `<package manager> run <start_script>`
+/// when no command is configured. `extra_args` go last either way.
+pub fn child_command(settings: &AppSpecificConfig) -> Command {
+    let mut command = match &settings.command {
+        Some(program) => {
+            let mut command = Command::new(program);
+            command.args(&settings.args);
+            command
+        }
+        None => script_command(settings, &settings.start_script),
+    };
+
+    command.args(&settings.extra_args);
+    command
+}
+
+/// Wraps the child command in `systemd-run --scope` so systemd tracks the
+/// child's cgroup and accounting. systemd-run execs the command itself, the
+/// pid and process group stay the ones the runner spawned.
+fn scoped_command(settings: &AppSpecificConfig, inner: Command, cgroup: Option<&CgroupTarget>) -> Command {
+    let mut command = Command::new("systemd-run");
+    command.args(["--scope", "--quiet", "--collect"]);
+
+    if !Uid::effective().is_root() {
+        command.arg("--user");
+    }
+
+    if let Some(user) = &settings.run_as_user {
+        command.arg(format!("--uid={}", user));
+    }
+    if let Some(group) = &settings.run_as_group {
+        command.arg(format!("--gid={}", group));
+    }
+
+    if let Some(target) = cgroup {
+        if let Some(mb) = target.memory_mb {
+            command.arg(format!("--property=MemoryMax={}M", mb));
+        }
+        if let Some(percent) = target.cpu_percent {
+            command.arg(format!("--property=CPUQuota={}%", percent));
+        }
+    }
+
+    let inner = inner.as_std();
+    command.arg("--").arg(inner.get_program()).args(inner.get_args());
+    command
+}
+
+/// Builds `<manager> <dir flag> <project_path> run <script>` for the configured package manager
+fn script_command(settings: &AppSpecificConfig, script: &str) -> Command {
+    let manager = settings.package_manager();
+    let mut command = Command::new(manager.program());
+    command.args([manager.directory_flag(), &settings.project_path, "run", script]);
+    command
+}
+
+/// Layers the environment for spawned processes: the runner's own environment
+/// (unless `inherit_env` is off), the built in defaults, the project's env file
+/// and finally the `env` table.
+fn apply_environment(command: &mut Command, settings: &AppSpecificConfig, defaults: &[(&str, &str)]) {
+    if !settings.inherit_env {
+        command.env_clear();
+    }
+
+    for (key, value) in defaults {
+        command.env(key, value);
+    }
+
+    // Read on every spawn so edits are picked up when the child restarts
+    for (key, value) in read_env_file(settings) {
+        command.env(key, value);
+    }
+
+    for (key, value) in &settings.env {
+        log!(LogLevel::Trace, "Setting {} for the child environment", key);
+        if let Some(value) = resolve_env_value(key, value) {
+            command.env(key, value);
+        }
+    }
+}
+
+/// Names of the variables `apply_environment` sets on top of the inherited
+/// environment, forwarded into containers by name
+fn environment_keys(settings: &AppSpecificConfig) -> Vec<String> {
+    let mut keys: Vec<String> = vec![String::from("NODE_ENV")];
+    keys.extend(read_env_file(settings).into_iter().map(|(key, _)| key));
+    keys.extend(settings.env.keys().cloned());
+    keys.sort();
+    keys.dedup();
+    keys
+}
+
+/// Resolves `${file:<path>}` and `${env:<name>}` references so secrets stay
+/// out of the Config file. Runs on every spawn, a rotated secret is picked up
+/// on the next restart. Unresolvable references leave the variable unset.
+fn resolve_env_value(key: &str, value: &str) -> Option<String> {
+    match secrets::interpolate(value) {
+        Ok(value) => Some(value),
+        Err(err) => {
+            log!(LogLevel::Warn, "{} {}, leaving it unset", key, err);
+            None
+        }
+    }
+}
+
+fn read_env_file(settings: &AppSpecificConfig) -> Vec<(String, String)> {
+    let path = settings.env_file_path();
+    if !path.exists() {
+        log!(LogLevel::Trace, "No env file at {}", path.display());
+        return Vec::new();
+    }
+
+    let entries = match dotenvy::from_path_iter(&path) {
+        Ok(entries) => entries,
+        Err(err) => {
+            log!(LogLevel::Warn, "Failed to read env file {}: {}", path.display(), err);
+            return Vec::new();
+        }
+    };
+
+    entries
+        .filter_map(|entry| match entry {
+            Ok(pair) => Some(pair),
+            Err(err) => {
+                log!(LogLevel::Warn, "Skipping invalid line in {}: {}", path.display(), err);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Why a one shot build didn't succeed, a timeout is reported separately from
+/// a build that ran and failed. `log` points at the saved build output.
+#[derive(Debug)]
+pub enum OneShotError {
+    Failed { message: String, log: Option<PathBuf> },
+    TimedOut(Duration),
+}
+
+impl OneShotError {
+    pub fn build_log(&self) -> Option<&Path> {
+        match self {
+            OneShotError::Failed { log, .. } => log.as_deref(),
+            OneShotError::TimedOut(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for OneShotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OneShotError::Failed { message, log: Some(log) } => write!(f, "{} (full output in {})", message, log.display()),
+            OneShotError::Failed { message, log: None } => write!(f, "{}", message),
+            OneShotError::TimedOut(limit) => write!(f, "Oneshot process timed out after {}s and was killed", limit.as_secs()),
+        }
+    }
+}
+
+/// Runs `one_shot_command` when configured, `<package manager> run <build_script>` otherwise
+fn one_shot_command(settings: &AppSpecificConfig) -> Command {
+    match &settings.one_shot_command {
+        Some(program) => {
+            let mut command = Command::new(program);
+            command.args(&settings.one_shot_args);
+            command
         }
+        None => script_command(settings, &settings.build_script),
     }
 }
- 
-pub async fn run_one_shot_process(settings: &AppSpecificConfig) -> Result<(), String> {
-    // Set the environment variable NODE_ENV to "production"
-    let output = Command::new("npm")
-        .arg("--prefix")
-        .arg(settings.clone().project_path)
-        .arg("run")
-        .arg("build")
-        .env("NODE_ENV", "production") 
-        .output()
-        .await
-        .map_err(|err| format!("Failed to execute npm run build: {}", err))?;
+
+/// The commands of the one shot build in order, one per `build_steps` entry
+/// when those are set. Empty for a prebuilt container image.
+pub fn build_commands(settings: &AppSpecificConfig) -> Vec<Command> {
+    if settings.runtime == Runtime::Container {
+        return container::build_command(settings).into_iter().collect();
+    }
+
+    if settings.build_steps.is_empty() {
+        return vec![one_shot_command(settings)];
+    }
+
+    settings
+        .build_steps
+        .iter()
+        .map(|step| {
+            let mut command = Command::new("sh");
+            command.arg("-c").arg(&step.run);
+            command
+        })
+        .collect()
+}
+
+pub async fn run_one_shot_process(settings: &AppSpecificConfig) -> Result<(), OneShotError> {
+    let limit: Option<Duration> = settings.build_timeout_seconds.map(Duration::from_secs);
+    let mut commands = build_commands(settings);
+
+    if settings.runtime == Runtime::Container || settings.build_steps.is_empty() {
+        return match commands.pop() {
+            Some(command) => run_build_step(settings, command, limit).await,
+            None => {
+                log!(LogLevel::Debug, "Using the prebuilt image {}", container::image_tag(settings));
+                Ok(())
+            }
+        };
+    }
+
+    let total = settings.build_steps.len();
+    for (position, (step, command)) in settings.build_steps.iter().zip(commands).enumerate() {
+        log!(LogLevel::Info, "Build step {}/{}: {}", position + 1, total, step.run);
+
+        let step_limit = step.timeout_seconds.map(Duration::from_secs).or(limit);
+        match run_build_step(settings, command, step_limit).await {
+            Ok(_) => (),
+            Err(err) if step.allow_failure => {
+                log!(LogLevel::Warn, "Build step {} failed but is allowed to: {}", step.run, err);
+            }
+            Err(err) => {
+                log!(LogLevel::Error, "Build step {}/{} ({}) failed, skipping the rest", position + 1, total, step.run);
+                return Err(err);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Gives a build or install command the build environment and working directory
+pub fn prepare_build_step(settings: &AppSpecificConfig, command: &mut Command) {
+    apply_environment(command, settings, &[("NODE_ENV", settings.node_env.as_str())]);
+
+    if let Some(working_dir) = settings.working_dir() {
+        command.current_dir(working_dir);
+    }
+}
+
+/// Runs a single one shot command with the build environment, limits and timeout
+async fn run_build_step(settings: &AppSpecificConfig, mut command: Command, limit: Option<Duration>) -> Result<(), OneShotError> {
+    prepare_build_step(settings, &mut command);
+    apply_limits(&mut command, &settings.limits, None);
+    apply_priority(&mut command, &settings.priority);
+
+    let description = {
+        let std_command = command.as_std();
+        std::iter::once(std_command.get_program())
+            .chain(std_command.get_args())
+            .map(|part| part.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    let spawn_error = |err: std::io::Error| {
+        OneShotError::Failed {
+            message: format!("Failed to execute {}: {}", description, err),
+            log: None,
+        }
+    };
+
+    let output = match output_with_timeout(command, limit).await.map_err(spawn_error)? {
+        Some(output) => output,
+        None => return Err(OneShotError::TimedOut(limit.unwrap_or_default())),
+    };
 
     let stdout = String::from_utf8_lossy(&output.stdout);
     let stderr = String::from_utf8_lossy(&output.stderr);
@@ -96,17 +710,247 @@ pub async fn run_one_shot_process(settings: &AppSpecificConfig) -> Result<(), St
     log!(LogLevel::Debug, "Standard Out: {}", stdout);
     log!(LogLevel::Debug, "Standard Err: {}", stderr);
 
+    let build_log = write_build_log(&settings.build_logs, output.status.success(), &stdout, &stderr);
+
+    if !output.status.success() {
+        return Err(OneShotError::Failed {
+            message: format!("Oneshot process failed: {}", stderr),
+            log: build_log,
+        });
+    }
+
+    Ok(())
+}
+
+/// Runs the command in its own process group and collects its output. When
+/// `limit` runs out the whole group is killed and `None` is returned.
+async fn output_with_timeout(mut command: Command, limit: Option<Duration>) -> std::io::Result<Option<Output>> {
+    command
+        .process_group(0)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    let process = command.spawn()?;
+    let pid: Option<u32> = process.id();
+
+    let limit = match limit {
+        Some(limit) => limit,
+        None => return process.wait_with_output().await.map(Some),
+    };
+
+    match timeout(limit, process.wait_with_output()).await {
+        Ok(output) => output.map(Some),
+        Err(_) => {
+            log!(LogLevel::Warn, "Process {:?} exceeded {}s, killing it", pid, limit.as_secs());
+            if let Some(pid) = pid {
+                let _ = signal::killpg(Pid::from_raw(pid as i32), Signal::SIGKILL);
+            }
+            Ok(None)
+        }
+    }
+}
+
+/// Runs a `pre_start_hook` / `post_stop_hook` or a watch rule's hook through
+/// `sh -c` with the child's environment. Failures are logged and returned,
+/// they never stop the lifecycle.
+pub async fn run_hook(settings: &AppSpecificConfig, name: &str, hook: &Option<String>, port: Option<u16>) -> Result<(), String> {
+    let hook = match hook {
+        Some(hook) => hook,
+        None => return Ok(()),
+    };
+
+    log!(LogLevel::Debug, "Running {}: {}", name, hook);
+    let command = hook_command(settings, name, hook, port);
+
+    let limit = Duration::from_secs(settings.hook_timeout_seconds);
+    let output = match output_with_timeout(command, Some(limit)).await {
+        Ok(Some(output)) => output,
+        Ok(None) => return Err(format!("{} timed out after {}s", name, limit.as_secs())),
+        Err(err) => return Err(format!("Failed to run {}: {}", name, err)),
+    };
+
+    log!(LogLevel::Debug, "{} stdout: {}", name, String::from_utf8_lossy(&output.stdout));
+    log!(LogLevel::Debug, "{} stderr: {}", name, String::from_utf8_lossy(&output.stderr));
+
     if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(format!("Oneshot process failed: {}", stderr));
+        return Err(format!(
+            "{} exited with {}: {}",
+            name,
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    Ok(())
+}
+
+/// `sh -c <hook>` with the child's environment and `ARTISAN_HOOK` set to `name`
+pub fn hook_command(settings: &AppSpecificConfig, name: &str, hook: &str, port: Option<u16>) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(hook);
+
+    let port_value = port.map(|port| port.to_string());
+    let mut defaults: Vec<(&str, &str)> = vec![("NODE_ENV", settings.node_env.as_str()), ("ARTISAN_HOOK", name)];
+    if let Some(port_value) = &port_value {
+        defaults.push(("PORT", port_value));
+    }
+    apply_environment(&mut command, settings, &defaults);
+
+    if let Some(working_dir) = settings.working_dir() {
+        command.current_dir(working_dir);
+    }
+    command
+}
+
+/// Runs the one shot build, retrying with backoff so a registry blip doesn't
+/// take the service down. Once every attempt failed the state is marked as
+/// failed and false is returned, the caller is expected to give up.
+pub async fn run_build(state: &mut AppState, state_path: &PathType, settings: &AppSpecificConfig) -> bool {
+    let attempts: u32 = settings.build_retries + 1;
+    let mut backoff = Duration::from_secs(settings.build_retry_backoff_seconds);
+    let max_backoff = Duration::from_secs(settings.build_retry_backoff_max_seconds);
+
+    for attempt in 1..=attempts {
+        let err = match run_one_shot_process(settings).await {
+            Ok(_) => return true,
+            Err(err) => err,
+        };
+
+        if attempt < attempts {
+            log!(
+                LogLevel::Warn,
+                "One-shot attempt {}/{} failed: {}, retrying in {}s",
+                attempt,
+                attempts,
+                err,
+                backoff.as_secs()
+            );
+            sleep(backoff).await;
+            backoff = backoff.saturating_mul(2).min(max_backoff);
+            continue;
+        }
+
+        log!(LogLevel::Error, "One-shot process failed after {} attempts: {}", attempts, err);
+        let error_item = ErrorArrayItem::new(dusa_collection_utils::errors::Errors::GeneralError, err.to_string());
+        log_error(state, error_item, state_path).await;
+        state.is_active = false;
+        state.data = match err.build_log() {
+            Some(path) => format!("Failed: one-shot build, output in {}", path.display()),
+            None => String::from("Failed: one-shot build"),
+        };
+        update_state(state, state_path, None).await;
+    }
+
+    false
+}
+
+/// Reinstalls the dependencies after a manifest or lockfile changed, with
+/// `install_command` or the package manager's clean install. A failure is
+/// recorded but leaves the running child alone.
+pub async fn run_install(state: &mut AppState, state_path: &PathType, settings: &AppSpecificConfig) -> bool {
+    let command = install_command(settings);
+
+    log!(LogLevel::Info, "Dependencies changed, reinstalling them");
+    let limit: Option<Duration> = settings.build_timeout_seconds.map(Duration::from_secs);
+    let err = match run_build_step(settings, command, limit).await {
+        Ok(_) => return true,
+        Err(err) => err,
+    };
+
+    log!(LogLevel::Error, "Dependency install failed: {}", err);
+    let error_item = ErrorArrayItem::new(dusa_collection_utils::errors::Errors::GeneralError, err.to_string());
+    log_error(state, error_item, state_path).await;
+    state.data = String::from("Failed: dependency install, the previous child keeps running");
+    update_state(state, state_path, None).await;
+    false
+}
+
+/// `install_command` through `sh -c`, or the package manager's clean install
+pub fn install_command(settings: &AppSpecificConfig) -> Command {
+    match &settings.install_command {
+        Some(install) => {
+            let mut command = Command::new("sh");
+            command.arg("-c").arg(install);
+            command
+        }
+        None => {
+            let manager = settings.package_manager();
+            let mut command = Command::new(manager.program());
+            command.args([manager.directory_flag(), &settings.project_path]);
+            command.args(manager.install_args());
+            command
+        }
+    }
+}
+
+/// Asks the child's process group to exit with `stop_signal`, escalating to SIGKILL
+/// for the whole group once the configured grace period runs out. The
+/// `post_stop_hook` runs once the group is gone.
+pub async fn stop_child(
+    child: &mut ChildHandle,
+    settings: &AppSpecificConfig,
+) -> Result<(), ErrorArrayItem> {
+    let result = stop_process_group(child, settings).await;
+
+    if let Err(err) = run_hook(settings, "post_stop_hook", &settings.post_stop_hook, None).await {
+        log!(LogLevel::Warn, "{}", err);
+    }
+
+    result
+}
+
+async fn stop_process_group(
+    child: &mut ChildHandle,
+    settings: &AppSpecificConfig,
+) -> Result<(), ErrorArrayItem> {
+    let pid: u32 = match child.get_pid().await {
+        Ok(pid) => pid,
+        Err(_) => return child.kill().await,
+    };
+
+    // The child leads its own group, so the pgid is the pid
+    let group = Pid::from_raw(pid as i32);
+
+    let stop_signal = settings.stop_signal.signal();
+    log!(LogLevel::Trace, "Sending {} to process group {}", stop_signal, group);
+    if let Err(err) = signal::killpg(group, stop_signal) {
+        log!(LogLevel::Warn, "Failed to send {} to process group {}: {}", stop_signal, group, err);
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(settings.shutdown_grace_seconds);
+    while Instant::now() < deadline {
+        // Checking the child first reaps it, otherwise it keeps the group alive as a zombie
+        if !child.running().await && !group_alive(group) {
+            log!(LogLevel::Info, "Process group {} exited after {}", group, stop_signal);
+            return Ok(());
+        }
+        sleep(Duration::from_millis(250)).await;
+    }
+
+    log!(
+        LogLevel::Warn,
+        "Process group {} still running after {}s, killing it",
+        group,
+        settings.shutdown_grace_seconds
+    );
+
+    if let Err(err) = signal::killpg(group, Signal::SIGKILL) {
+        log!(LogLevel::Debug, "Failed to send SIGKILL to process group {}: {}", group, err);
+    }
+
+    if child.running().await {
+        return child.kill().await;
     }
 
     Ok(())
 }
 
+fn group_alive(group: Pid) -> bool {
+    signal::killpg(group, None).is_ok()
+}
+
 pub fn _get_pid(state: &mut AppState) -> Result<c_int, ErrorArrayItem>{
-    let pid_file: PathType =
-    PathType::Content(format!("/tmp/.{}_pg.pid", state.config.app_name));
+    let pid_file: PathType = pid_file(&state.config.app_name, 0);
 
 
     let data = match fs::read_to_string(pid_file) {
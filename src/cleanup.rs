@@ -0,0 +1,105 @@
+use dusa_collection_utils::log;
+use dusa_collection_utils::log::LogLevel;
+use nix::{sys::signal, unistd::Pid};
+use std::{
+    fs, io, panic,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    thread,
+};
+
+use crate::child::pid_file;
+
+/// Files the runner created and has to take with it, pid files and sockets
+static ARTIFACTS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Marks a file for removal when the runner goes away
+pub fn register_artifact<P: AsRef<Path>>(path: P) {
+    let path = path.as_ref().to_path_buf();
+    let mut artifacts = ARTIFACTS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if !artifacts.contains(&path) {
+        artifacts.push(path);
+    }
+}
+
+/// Removes every registered artifact, safe to call more than once. A pid file
+/// whose process is still alive is kept so the next runner can adopt it.
+pub fn cleanup_artifacts() {
+    let mut artifacts = ARTIFACTS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    for path in artifacts.drain(..) {
+        if owner_alive(&path) {
+            log!(LogLevel::Debug, "Keeping {}, its process is still running", path.display());
+            continue;
+        }
+
+        match fs::remove_file(&path) {
+            Ok(_) => log!(LogLevel::Trace, "Removed {}", path.display()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+            Err(err) => log!(LogLevel::Warn, "Couldn't remove {}: {}", path.display(), err),
+        }
+    }
+}
+
+/// `std::process::exit` skips destructors, every exit after startup goes through here instead
+pub fn exit(code: i32) -> ! {
+    cleanup_artifacts();
+    std::process::exit(code)
+}
+
+/// Cleans up when dropped, held by main so returning from it cleans up as well
+pub struct CleanupGuard;
+
+impl Drop for CleanupGuard {
+    fn drop(&mut self) {
+        cleanup_artifacts();
+    }
+}
+
+/// Cleans up when the main thread panics. Panics in other threads and tasks
+/// don't take the runner down, so they leave the artifacts alone.
+pub fn install_panic_hook() {
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if thread::current().name() == Some("main") {
+            cleanup_artifacts();
+        }
+        previous(info);
+    }));
+}
+
+/// Removes pid files left behind by an earlier runner whose process is gone
+pub fn remove_stale_pid_files(app_name: &str, instances: u16) {
+    for index in 0..instances {
+        let path = pid_file(app_name, index);
+
+        if !path.exists() || owner_alive(&path) {
+            continue;
+        }
+
+        log!(LogLevel::Debug, "Removing stale pid file {}", path);
+        if let Err(err) = fs::remove_file(&path) {
+            log!(LogLevel::Warn, "Couldn't remove stale pid file {}: {}", path, err);
+        }
+    }
+}
+
+/// Removes the pid file of an instance that was stopped for good
+pub fn remove_pid_file(app_name: &str, index: u16) {
+    let path = pid_file(app_name, index);
+    if let Err(err) = fs::remove_file(&path) {
+        if err.kind() != io::ErrorKind::NotFound {
+            log!(LogLevel::Warn, "Couldn't remove pid file {}: {}", path, err);
+        }
+    }
+}
+
+/// True when the file holds the pid of a running process, false for anything else
+fn owner_alive(path: &Path) -> bool {
+    let pid: i32 = match fs::read_to_string(path) {
+        Ok(data) => data.trim().parse().unwrap_or_default(),
+        Err(_) => return false,
+    };
+
+    pid > 0 && signal::kill(Pid::from_raw(pid), None).is_ok()
+}
@@ -0,0 +1,63 @@
+use dusa_collection_utils::log;
+use dusa_collection_utils::log::LogLevel;
+use std::{env, path::PathBuf, sync::OnceLock};
+
+/// Command line arguments, parsed once on first use
+#[derive(Debug, Default)]
+pub struct CliArgs {
+    pub command: Option<CliCommand>,
+    pub config: Option<PathBuf>, // --config <path>
+    pub env: Option<String>,     // --env <profile>
+    pub keyfile: Option<PathBuf>, // --keyfile <path>
+    pub dry_run: bool,           // --dry-run
+    pub strict: bool,            // --strict
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CliCommand {
+    Check,   // Validate the configuration and environment, then exit
+    Encrypt, // Encrypt a value from stdin for the Config file, then exit
+    Init,    // Write a commented sample Config, then exit
+    Schema,  // Print the JSON Schema of the Config file, then exit
+}
+
+static ARGS: OnceLock<CliArgs> = OnceLock::new();
+
+pub fn cli_args() -> &'static CliArgs {
+    ARGS.get_or_init(|| parse(env::args().skip(1)))
+}
+
+fn parse(mut args: impl Iterator<Item = String>) -> CliArgs {
+    let mut parsed = CliArgs::default();
+
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) => (flag.to_owned(), Some(value.to_owned())),
+            None => (arg.clone(), None),
+        };
+
+        match flag.as_str() {
+            "--config" => match inline.or_else(|| args.next()) {
+                Some(path) => parsed.config = Some(PathBuf::from(path)),
+                None => log!(LogLevel::Warn, "--config needs a path, using the default Config file"),
+            },
+            "--env" => match inline.or_else(|| args.next()) {
+                Some(profile) => parsed.env = Some(profile),
+                None => log!(LogLevel::Warn, "--env needs a profile name, using the shared settings"),
+            },
+            "--keyfile" => match inline.or_else(|| args.next()) {
+                Some(path) => parsed.keyfile = Some(PathBuf::from(path)),
+                None => log!(LogLevel::Warn, "--keyfile needs a path, using the default keyfile"),
+            },
+            "--dry-run" => parsed.dry_run = true,
+            "--strict" => parsed.strict = true,
+            "check" if parsed.command.is_none() => parsed.command = Some(CliCommand::Check),
+            "encrypt" if parsed.command.is_none() => parsed.command = Some(CliCommand::Encrypt),
+            "init" if parsed.command.is_none() => parsed.command = Some(CliCommand::Init),
+            "schema" if parsed.command.is_none() => parsed.command = Some(CliCommand::Schema),
+            _ => log!(LogLevel::Warn, "Ignoring unknown argument {}", arg),
+        }
+    }
+
+    parsed
+}
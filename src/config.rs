@@ -2,13 +2,30 @@ use artisan_middleware::{
     common::update_state, config::AppConfig, state_persistence::{AppState, StatePersistence}, timestamp::current_timestamp, version::{aml_version, str_to_version}
 };
 use colored::Colorize;
-use config::{Config, ConfigError, File};
+use config::{builder::DefaultState, Config, ConfigBuilder, ConfigError, Environment, File, FileFormat, Map, Source, Value, ValueKind};
 use dusa_collection_utils::{
-    log::{set_log_level, LogLevel}, stringy::Stringy, types::PathType, version::{SoftwareVersion, Version, VersionCode},
+    errors::{ErrorArrayItem, Errors}, log::{set_log_level, LogLevel}, stringy::Stringy, types::PathType, version::{SoftwareVersion, Version, VersionCode},
 };
 use dusa_collection_utils::log;
-use serde::Deserialize;
-use std::fmt;
+use nix::sys::signal::Signal;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    child::DEFAULT_PORT,
+    cleanup::register_artifact,
+    cli::cli_args,
+    fields::{config_types, struct_fields, FieldType, TypeKind},
+    migrate::{migrate, CONFIG_VERSION},
+    reload::reload_history_path,
+    rules::rule_glob,
+    secrets::resolve_secrets,
+    threshold::pending_changes_path,
+};
 
 pub fn get_config() -> AppConfig {
     let mut config: AppConfig = match AppConfig::new() {
@@ -43,64 +60,975 @@ pub fn get_config() -> AppConfig {
     config
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct AppSpecificConfig {
+    #[serde(default = "default_config_version")]
+    pub config_version: u64, // Schema version, older files are migrated on load
+    #[serde(default)]
+    pub name: Option<String>, // Set by [[applications]] entries, names their state, pid files and logs
+    #[serde(default)]
+    pub strict: bool, // Refuse unknown keys in app_specific instead of warning about them, also --strict
     pub interval_seconds: u32,
     pub monitor_path: String,
     pub project_path: String,
     pub changes_needed: i32,
+    #[serde(default)]
+    pub settle_seconds: u64, // Quiet time required after the threshold before restarting
+    #[serde(default)]
+    pub change_thresholds: Vec<ChangeThreshold>, // Subtrees with their own changes_needed
+    #[serde(default)]
+    pub watch_rules: Vec<WatchRule>, // Named rules with their own threshold and action
     pub ignored_subdirs: Vec<String>, // Add ignored subdirectories as strings
+    #[serde(default)]
+    pub watch_roots: Vec<WatchRootConfig>, // Watched alongside monitor_path
+    #[serde(default)]
+    pub deploy_marker: Option<String>, // Touching this file below a watch root deploys right away
+    #[serde(default)]
+    pub restart_trigger: Option<String>, // Touching this file forces a rebuild and restart
+    #[serde(default)]
+    pub git_head_trigger: bool, // Deploy when HEAD moves instead of counting file events
+    #[serde(default)]
+    pub remote: RemoteWatchConfig,
+    #[serde(default)]
+    pub watch_socket: Option<String>, // Unix socket for changing watch roots and ignores at runtime
+    #[serde(default)]
+    pub include_regex: Option<String>, // Only matching paths count as changes
+    #[serde(default)]
+    pub exclude_regex: Option<String>,
+    #[serde(default)]
+    pub respect_gitignore: bool, // Skip events for paths the project's .gitignore files ignore
+    #[serde(default = "default_event_kinds")]
+    pub event_kinds: Vec<EventKindFilter>, // Kinds of events that count as a change
+    #[serde(default)]
+    pub new_files_only: bool, // Only files created or moved in count, for drop folders
+    #[serde(default)]
+    pub content_hash: bool, // Drop events whose files still hash the same
+    #[serde(default)]
+    pub watcher: WatcherBackend,
+    #[serde(default = "default_poll_interval_seconds")]
+    pub poll_interval_seconds: u64, // Scan interval of the polling backend
+    #[serde(default)]
+    pub follow_symlinks: bool, // Also watch where symlinks leading out of the roots point
+    #[serde(default)]
+    pub max_watch_depth: Option<usize>, // Directory levels watched below each root
+    #[serde(default)]
+    pub max_watched_dirs: Option<usize>, // Directories watched across all roots
+    #[serde(default = "default_true")]
+    pub reload_on_config_change: bool, // Reload like SIGHUP when the Config file changes
+    #[serde(default = "default_config_debounce_ms")]
+    pub config_debounce_ms: u64, // Quiet period before a changed Config file is read
+    #[serde(default = "default_storm_threshold")]
+    pub storm_threshold: usize, // Events per second above which a burst is one bulk change
+    #[serde(default = "default_true")]
+    pub editor_dedup: bool, // Count an editor's temp file save as one change of the target file
+    #[serde(default = "default_debounce_ms")]
+    pub debounce_ms: u64, // Events closer together than this count as one change
+    #[serde(default)]
+    pub command: Option<String>, // Executable to supervise, defaults to npm run start
+    #[serde(default)]
+    pub args: Vec<String>, // Arguments for the command above
+    #[serde(default)]
+    pub extra_args: Vec<String>, // Appended to the start command, e.g. ["--", "--inspect=9229"]
+    #[serde(default)]
+    pub working_dir: Option<String>, // Relative paths are resolved against project_path
+    #[serde(default)]
+    pub package_manager: Option<PackageManager>, // Detected from lockfiles when unset
+    #[serde(default = "default_build_script")]
+    pub build_script: String, // Script run by the one shot
+    #[serde(default)]
+    pub one_shot_command: Option<String>, // Executable run as the one shot instead of build_script
+    #[serde(default)]
+    pub one_shot_args: Vec<String>, // Arguments for the one shot command above
+    #[serde(default = "default_start_script")]
+    pub start_script: String, // Script run as the child when no command is set
+    #[serde(default)]
+    pub build_timeout_seconds: Option<u64>, // Kills a one shot build that runs longer than this
+    #[serde(default)]
+    pub build_steps: Vec<BuildStep>, // Run in order instead of build_script when set
+    #[serde(default = "default_true")]
+    pub install_on_dependency_change: bool, // Reinstall when package.json or a lockfile changes
+    #[serde(default)]
+    pub install_command: Option<String>, // Replaces the package manager's clean install
+    #[serde(default = "default_build_retries")]
+    pub build_retries: u32, // Extra attempts after a failed one shot build
+    #[serde(default = "default_build_retry_backoff_seconds")]
+    pub build_retry_backoff_seconds: u64, // Delay before the first retry, doubled per attempt
+    #[serde(default = "default_build_retry_backoff_max_seconds")]
+    pub build_retry_backoff_max_seconds: u64, // Ceiling for the delay above, apart from the child's restart backoff
+    #[serde(default)]
+    pub node_env: NodeEnv, // NODE_ENV of the build, the child and its hooks
+    #[serde(default)]
+    pub env: BTreeMap<String, String>, // Overrides inherited and default variables
+    #[serde(default = "default_true")]
+    pub inherit_env: bool, // Pass the runner's own environment through
+    #[serde(default = "default_env_file")]
+    pub env_file: String, // Relative paths are resolved against project_path
+    #[serde(default)]
+    pub run_as_user: Option<String>, // Drop the child to this user when the runner is root
+    #[serde(default)]
+    pub run_as_group: Option<String>, // Defaults to the user's primary group
+    #[serde(default = "default_true")]
+    pub adopt_running_child: bool, // Take over a child a crashed runner left behind instead of spawning a second one
+    #[serde(default = "default_startup_grace_seconds")]
+    pub startup_grace_seconds: u64, // Health checks and limits leave a freshly spawned child alone this long
+    #[serde(default)]
+    pub stop_signal: StopSignal, // Sent to the child's process group to ask it to exit
+    #[serde(default = "default_shutdown_grace_seconds", alias = "stop_grace_seconds")]
+    pub shutdown_grace_seconds: u64, // Time between stop_signal and SIGKILL
+    #[serde(default)]
+    pub respawn_delay_seconds: u64, // Cool-down between stopping the child and spawning the next one
+    #[serde(default)]
+    pub pre_start_hook: Option<String>, // Shell command run before every spawn of the child
+    #[serde(default)]
+    pub post_stop_hook: Option<String>, // Shell command run after the child was stopped
+    #[serde(default = "default_hook_timeout_seconds")]
+    pub hook_timeout_seconds: u64,
+    #[serde(default = "default_restart_backoff_seconds")]
+    pub restart_backoff_seconds: u64, // First delay after a crash, doubled per consecutive crash
+    #[serde(default = "default_restart_backoff_max_seconds")]
+    pub restart_backoff_max_seconds: u64, // Ceiling for the delay above
+    #[serde(default = "default_restart_backoff_reset_seconds")]
+    pub restart_backoff_reset_seconds: u64, // Uptime after which the crash streak is forgotten
+    #[serde(default)]
+    pub max_restarts: Option<u32>, // Crashes tolerated within restart_window_seconds, unlimited when unset
+    #[serde(default)]
+    pub restart_policy: RestartPolicy,
+    #[serde(default = "default_restart_window_seconds")]
+    pub restart_window_seconds: u64,
+    #[serde(default)]
+    pub max_ram_mb: Option<u64>, // Replaces the middleware's max_ram_usage for this application
+    #[serde(default)]
+    pub max_cpu_percent: Option<u32>, // Replaces the middleware's max_cpu_usage, 0 disables the check
+    #[serde(default = "default_cpu_limit_window_seconds")]
+    pub cpu_limit_window_seconds: u64, // How long max_cpu_usage has to be exceeded before it counts
+    #[serde(default)]
+    pub on_limit_exceeded: LimitAction, // What happens to a child over max_ram_usage or max_cpu_usage
+    #[serde(default = "default_error_log_max_entries")]
+    pub error_log_max_entries: usize, // Distinct errors kept in the state, the oldest go first
+    #[serde(default)]
+    pub error_log_max_age_seconds: Option<u64>, // Errors older than this are dropped, kept until pushed out when unset
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    #[serde(default)]
+    pub child_logs: ChildLogConfig,
+    #[serde(default)]
+    pub build_logs: BuildLogConfig,
+    #[serde(default)]
+    pub stdin: StdinConfig,
+    #[serde(default)]
+    pub readiness: ReadinessConfig,
+    #[serde(default)]
+    pub health_check: HealthCheckConfig,
+    #[serde(default)]
+    pub disk_usage: DiskUsageConfig,
+    #[serde(default)]
+    pub state: StateConfig,
+    #[serde(default = "default_instances")]
+    pub instances: u16, // Copies of the child, each gets port + its index
+    #[serde(default = "default_port")]
+    pub port: u16, // PORT of the first instance
+    #[serde(default)]
+    pub runtime: Runtime,
+    #[serde(default)]
+    pub container: ContainerConfig,
+    #[serde(default)]
+    pub use_systemd_scope: bool, // Launch the child through systemd-run --scope
+    #[serde(default)]
+    pub restart_schedule: Option<String>, // Cron expression in UTC, e.g. "0 3 * * *" for nightly
+    #[serde(default)]
+    pub zero_downtime: ZeroDowntimeConfig,
+    #[serde(default)]
+    pub priority: PriorityConfig,
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    #[serde(skip)]
+    pub secret_keys: Vec<String>, // Fields resolved from secret references or encrypted, never logged
+}
+
+/// Scheduling priority for the one shot build and the child
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct PriorityConfig {
+    pub nice: Option<i32>,          // -20 (highest) to 19 (lowest)
+    pub io_class: Option<IoClass>,  // ionice scheduling class
+    pub io_priority: Option<u8>,    // 0 (highest) to 7 within realtime and best-effort
+}
+
+/// How file changes are detected
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum WatcherBackend {
+    #[default]
+    Auto,   // inotify, polling on network filesystems or when inotify fails
+    Native,
+    Poll,
+}
+
+/// Watcher event kinds that can count towards `changes_needed`
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKindFilter {
+    Create,
+    Modify,   // Content writes
+    Rename,
+    Remove,
+    Metadata, // chmod, chown, timestamps
+    Access,
+}
+
+/// How the child is run
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Runtime {
+    #[default]
+    Process,
+    Container,
+}
+
+/// Image and engine used when `runtime = "container"`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct ContainerConfig {
+    pub engine: ContainerEngine,
+    pub image: Option<String>,      // Tag to build, or the prebuilt image to run
+    pub build: bool,                // Build the image from project_path in the one shot
+    pub dockerfile: Option<String>,
+    pub container_port: Option<u16>, // Port the app listens on inside, defaults to the host port
+    pub run_args: Vec<String>,      // Extra flags for `run`, e.g. volumes
+}
+
+impl Default for ContainerConfig {
+    fn default() -> Self {
+        Self {
+            engine: ContainerEngine::Docker,
+            image: None,
+            build: true,
+            dockerfile: None,
+            container_port: None,
+            run_args: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ContainerEngine {
+    Docker,
+    Podman,
+}
+
+impl ContainerEngine {
+    pub fn program(&self) -> &'static str {
+        match self {
+            ContainerEngine::Docker => "docker",
+            ContainerEngine::Podman => "podman",
+        }
+    }
+}
+
+/// Action taken against a child that broke `max_ram_usage` or `max_cpu_usage`
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum LimitAction {
+    #[default]
+    Log,
+    Restart,
+    KillAndFail,
+}
+
+/// When a child that went away gets started again, systemd style
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    #[default]
+    Always,
+    OnFailure, // Everything but exit code 0
+    Never,
+}
+
+impl RestartPolicy {
+    pub fn restarts(&self, clean_exit: bool) -> bool {
+        match self {
+            RestartPolicy::Always => true,
+            RestartPolicy::OnFailure => !clean_exit,
+            RestartPolicy::Never => false,
+        }
+    }
+}
+
+impl fmt::Display for RestartPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let policy = match self {
+            RestartPolicy::Always => "always",
+            RestartPolicy::OnFailure => "on-failure",
+            RestartPolicy::Never => "never",
+        };
+        write!(f, "{}", policy)
+    }
+}
+
+/// Signal a child is asked to exit with, many Node servers shut down
+/// gracefully on SIGINT or SIGUSR2 rather than SIGTERM
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum StopSignal {
+    #[default]
+    SigTerm,
+    SigInt,  // What Ctrl+C sends
+    SigQuit,
+    SigHup,
+    SigUsr1,
+    SigUsr2, // nodemon's restart signal
+}
+
+impl StopSignal {
+    pub fn signal(&self) -> Signal {
+        match self {
+            StopSignal::SigTerm => Signal::SIGTERM,
+            StopSignal::SigInt => Signal::SIGINT,
+            StopSignal::SigQuit => Signal::SIGQUIT,
+            StopSignal::SigHup => Signal::SIGHUP,
+            StopSignal::SigUsr1 => Signal::SIGUSR1,
+            StopSignal::SigUsr2 => Signal::SIGUSR2,
+        }
+    }
+}
+
+/// Mode the project is built and run in, handed over as `NODE_ENV`
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeEnv {
+    #[default]
+    Production,
+    Development,
+    Test,
+}
+
+impl NodeEnv {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NodeEnv::Production => "production",
+            NodeEnv::Development => "development",
+            NodeEnv::Test => "test",
+        }
+    }
+}
+
+impl fmt::Display for NodeEnv {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum IoClass {
+    Realtime,
+    BestEffort,
+    Idle,
+}
+
+/// Blue/green restarts on change triggers: the replacement alternates between
+/// the primary port and `alternate_port` and must accept connections before the
+/// old child is stopped.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct ZeroDowntimeConfig {
+    pub enabled: bool,
+    pub alternate_port: Option<u16>,
+    pub port_file: Option<String>, // Receives the active port for a reverse proxy to follow
+}
+
+impl ZeroDowntimeConfig {
+    /// The port the replacement should use, None when swaps aren't possible
+    pub fn next_port(&self, primary_port: u16, active_port: u16) -> Option<u16> {
+        if !self.enabled {
+            return None;
+        }
+
+        match self.alternate_port {
+            Some(alternate) if alternate == active_port => Some(primary_port),
+            Some(alternate) => Some(alternate),
+            None => {
+                log!(LogLevel::Warn, "Zero downtime restarts need an alternate_port, restarting in place");
+                None
+            }
+        }
+    }
+}
+
+/// Periodic HTTP check, the child is restarted after `unhealthy_threshold` failures in a row
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct HealthCheckConfig {
+    pub url: Option<String>, // Disabled when unset
+    pub interval_seconds: u64,
+    pub timeout_seconds: u64,
+    pub unhealthy_threshold: u32,
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            interval_seconds: 30,
+            timeout_seconds: 5,
+            unhealthy_threshold: 3,
+        }
+    }
+}
+
+/// Source tree on another host, compared over SSH every `interval_seconds`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct RemoteWatchConfig {
+    pub host: Option<String>, // e.g. deploy@build-01, disabled when unset
+    pub path: String,
+    pub interval_seconds: u64,
+    pub ignored_subdirs: Vec<String>,
+    pub ssh_args: Vec<String>,
+}
+
+impl Default for RemoteWatchConfig {
+    fn default() -> Self {
+        Self {
+            host: None,
+            path: String::new(),
+            interval_seconds: 30,
+            ignored_subdirs: vec![String::from("node_modules"), String::from(".git")],
+            ssh_args: Vec::new(),
+        }
+    }
+}
+
+/// Periodic size and free space check of the monitored and project paths
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct DiskUsageConfig {
+    pub max_size_mb: Option<u64>, // Combined size of monitor_path and project_path
+    pub min_free_mb: Option<u64>, // Free space left on the disks holding them
+    pub interval_seconds: u64,
+    pub block_builds: bool, // Hold off rebuilds while a threshold is crossed
+}
+
+impl DiskUsageConfig {
+    pub fn enabled(&self) -> bool {
+        self.max_size_mb.is_some() || self.min_free_mb.is_some()
+    }
+}
+
+impl Default for DiskUsageConfig {
+    fn default() -> Self {
+        Self {
+            max_size_mb: None,
+            min_free_mb: None,
+            interval_seconds: 300,
+            block_builds: false,
+        }
+    }
+}
+
+/// Where the AppState of the runner is persisted
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct StateConfig {
+    pub backend: StateBackend,
+    pub directory: Option<String>, // Replaces the directory of the default state file, the name stays
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum StateBackend {
+    #[default]
+    File,   // Survives restarts and reboots
+    Tmpfs,  // Survives restarts, gone after a reboot
+    Memory, // Starts fresh and is removed when the runner exits
+}
+
+/// RAM backed on every Linux, where the tmpfs and memory backends keep state
+pub const TMPFS_STATE_DIR: &str = "/dev/shm/artisan";
+
+impl StateConfig {
+    /// Directory the state file goes to, `None` keeps the default one
+    pub fn directory(&self) -> Option<PathBuf> {
+        match (self.backend, &self.directory) {
+            (_, Some(directory)) => Some(PathBuf::from(directory)),
+            (StateBackend::File, None) => None,
+            (StateBackend::Tmpfs | StateBackend::Memory, None) => Some(PathBuf::from(TMPFS_STATE_DIR)),
+        }
+    }
+}
+
+/// TCP probe that has to succeed before a spawned child counts as up
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct ReadinessConfig {
+    pub enabled: bool,
+    pub host: String,
+    pub port: Option<u16>, // Defaults to the port handed to the child
+    pub timeout_seconds: u64,
+}
+
+impl Default for ReadinessConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: String::from("127.0.0.1"),
+            port: None,
+            timeout_seconds: 30,
+        }
+    }
+}
+
+/// A subtree counted separately from the rest of the watched tree
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ChangeThreshold {
+    pub path: String, // Relative to the watch root, or absolute
+    pub changes_needed: i32,
+}
+
+/// Changes below `path` matching `pattern`, counted apart from everything else
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct WatchRule {
+    pub name: String,
+    pub path: String, // Relative to the watch root, or absolute and watched on its own
+    #[serde(default)]
+    pub pattern: Option<String>, // Glob relative to path, e.g. "**/*.css"
+    #[serde(default = "default_rule_changes_needed")]
+    pub changes_needed: i32,
+    #[serde(default)]
+    pub action: WatchAction,
+    #[serde(default)]
+    pub hook: Option<String>, // Run through sh -c by the hook action
+}
+
+/// What a watch rule does once its threshold is reached
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum WatchAction {
+    #[default]
+    Restart, // Install if dependencies changed, rebuild and restart the child
+    Rebuild, // Run the build only, the child keeps running
+    Install, // Reinstall the dependencies only
+    Hook, // Run the rule's hook
+}
+
+impl fmt::Display for WatchAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let action = match self {
+            WatchAction::Restart => "restart",
+            WatchAction::Rebuild => "rebuild",
+            WatchAction::Install => "install",
+            WatchAction::Hook => "hook",
+        };
+        write!(f, "{}", action)
+    }
+}
+
+/// An extra directory watched next to `monitor_path`, with its own ignores
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct WatchRootConfig {
+    pub path: String,
+    #[serde(default)]
+    pub ignored_subdirs: Vec<String>,
+}
+
+/// Where the runner's own logs and the child's output go, each on its own
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct LoggingConfig {
+    pub runner: LogRoute, // For the whole process, read from the first application
+    pub child: LogRoute,
+}
+
+/// One log stream's destination, level and format
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct LogRoute {
+    pub destination: Option<LogDestination>, // Left as it is when unset
+    pub file: Option<String>,                // Written with destination = "file"
+    pub level: Option<LogLevelName>,         // The runner's log_level, stderr only for the child from warn on
+    pub format: LogFormat,
+    pub max_size_mb: u64,   // Rotate the file once it grows past this
+    pub max_age_hours: u64, // Rotate the file once it is older than this
+    pub retain: usize,      // Rotated files kept
+}
+
+impl Default for LogRoute {
+    fn default() -> Self {
+        Self {
+            destination: None,
+            file: None,
+            level: None,
+            format: LogFormat::Text,
+            max_size_mb: 10,
+            max_age_hours: 24,
+            retain: 5,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogDestination {
+    Stdout,   // The runner's original stdout
+    Journald, // The systemd journal, each line with its priority
+    File,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json, // One object per line with time, app, source, level and message
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevelName {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevelName {
+    pub fn level(&self) -> LogLevel {
+        match self {
+            LogLevelName::Error => LogLevel::Error,
+            LogLevelName::Warn => LogLevel::Warn,
+            LogLevelName::Info => LogLevel::Info,
+            LogLevelName::Debug => LogLevel::Debug,
+            LogLevelName::Trace => LogLevel::Trace,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogLevelName::Error => "error",
+            LogLevelName::Warn => "warn",
+            LogLevelName::Info => "info",
+            LogLevelName::Debug => "debug",
+            LogLevelName::Trace => "trace",
+        }
+    }
+
+    /// syslog priority, as the journal takes it
+    pub fn priority(&self) -> u8 {
+        match self {
+            LogLevelName::Error => 3,
+            LogLevelName::Warn => 4,
+            LogLevelName::Info => 6,
+            LogLevelName::Debug | LogLevelName::Trace => 7,
+        }
+    }
+}
+
+/// Where the child's stdout and stderr are written, disabled without a directory or tail socket
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct ChildLogConfig {
+    pub directory: Option<String>,
+    pub max_size_mb: u64,   // Rotate once a file grows past this
+    pub max_age_hours: u64, // Rotate once a file is older than this
+    pub retain: usize,      // Rotated files kept per stream
+    pub tail_socket: Option<String>, // Unix socket streaming live output
+}
+
+impl Default for ChildLogConfig {
+    fn default() -> Self {
+        Self {
+            directory: None,
+            max_size_mb: 10,
+            max_age_hours: 24,
+            retain: 5,
+            tail_socket: None,
+        }
+    }
+}
+
+/// Keeps the child's stdin open so the runner can feed it admin commands
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct StdinConfig {
+    pub enabled: bool,
+    pub socket: Option<String>, // Unix socket whose lines are written to the child's stdin
+}
+
+/// One command of the one shot pipeline, run through `sh -c`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct BuildStep {
+    pub run: String,
+    #[serde(default)]
+    pub timeout_seconds: Option<u64>, // Falls back to build_timeout_seconds
+    #[serde(default)]
+    pub allow_failure: bool, // Keep going with the next step when this one fails
+}
+
+/// Where the output of every one shot build is kept
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct BuildLogConfig {
+    pub directory: Option<String>,
+    pub retain: usize, // Newest build logs kept, older ones are removed
+}
+
+impl Default for BuildLogConfig {
+    fn default() -> Self {
+        Self {
+            directory: None,
+            retain: 10,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PackageManager {
+    Npm,
+    Pnpm,
+    Yarn,
+    Bun,
+}
+
+const LOCKFILES: [(&str, PackageManager); 5] = [
+    ("pnpm-lock.yaml", PackageManager::Pnpm),
+    ("yarn.lock", PackageManager::Yarn),
+    ("bun.lockb", PackageManager::Bun),
+    ("bun.lock", PackageManager::Bun),
+    ("package-lock.json", PackageManager::Npm),
+];
+
+impl PackageManager {
+    pub fn program(&self) -> &'static str {
+        match self {
+            PackageManager::Npm => "npm",
+            PackageManager::Pnpm => "pnpm",
+            PackageManager::Yarn => "yarn",
+            PackageManager::Bun => "bun",
+        }
+    }
+
+    /// The flag each manager uses to run against another directory
+    pub fn directory_flag(&self) -> &'static str {
+        match self {
+            PackageManager::Npm => "--prefix",
+            PackageManager::Pnpm => "--dir",
+            PackageManager::Yarn | PackageManager::Bun => "--cwd",
+        }
+    }
+
+    /// Arguments for a clean install that sticks to the lockfile
+    pub fn install_args(&self) -> &'static [&'static str] {
+        match self {
+            PackageManager::Npm => &["ci"],
+            PackageManager::Pnpm | PackageManager::Yarn | PackageManager::Bun => &["install", "--frozen-lockfile"],
+        }
+    }
+
+    /// Whether `path` is a `package.json` or a lockfile of any manager
+    pub fn is_manifest(path: &Path) -> bool {
+        path.file_name().is_some_and(|name| {
+            name == "package.json" || LOCKFILES.iter().any(|(lockfile, _)| name == *lockfile)
+        })
+    }
+
+    /// Picks the manager based on the lockfile present in the project
+    pub fn detect(project_path: &Path) -> Option<PackageManager> {
+        LOCKFILES
+            .iter()
+            .find(|(file, _)| project_path.join(file).exists())
+            .map(|(_, manager)| *manager)
+    }
+}
+
+impl fmt::Display for PackageManager {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.program())
+    }
+}
+
+fn default_event_kinds() -> Vec<EventKindFilter> {
+    vec![
+        EventKindFilter::Create,
+        EventKindFilter::Modify,
+        EventKindFilter::Rename,
+        EventKindFilter::Remove,
+    ]
+}
+
+fn default_rule_changes_needed() -> i32 {
+    1
+}
+
+fn default_config_version() -> u64 {
+    CONFIG_VERSION
+}
+
+fn default_poll_interval_seconds() -> u64 {
+    2
+}
+
+fn default_config_debounce_ms() -> u64 {
+    1000
+}
+
+fn default_storm_threshold() -> usize {
+    1000
+}
+
+fn default_debounce_ms() -> u64 {
+    500
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_startup_grace_seconds() -> u64 {
+    10
+}
+
+fn default_shutdown_grace_seconds() -> u64 {
+    10
+}
+
+fn default_hook_timeout_seconds() -> u64 {
+    30
+}
+
+fn default_restart_backoff_seconds() -> u64 {
+    1
+}
+
+fn default_restart_backoff_max_seconds() -> u64 {
+    300
+}
+
+fn default_restart_backoff_reset_seconds() -> u64 {
+    60
+}
+
+fn default_restart_window_seconds() -> u64 {
+    600
+}
+
+fn default_error_log_max_entries() -> usize {
+    3
+}
+
+fn default_cpu_limit_window_seconds() -> u64 {
+    30
+}
+
+fn default_port() -> u16 {
+    DEFAULT_PORT
+}
+
+fn default_instances() -> u16 {
+    1
+}
+
+fn default_env_file() -> String {
+    String::from(".env")
+}
+
+fn default_build_retries() -> u32 {
+    3
+}
+
+fn default_build_retry_backoff_seconds() -> u64 {
+    5
+}
+
+fn default_build_retry_backoff_max_seconds() -> u64 {
+    300
+}
+
+fn default_build_script() -> String {
+    String::from("build")
+}
+
+fn default_start_script() -> String {
+    String::from("start")
+}
+
+/// Proactive resource limits applied to the child and the one shot build before they start
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct LimitsConfig {
+    pub address_space_mb: Option<u64>, // RLIMIT_AS
+    pub nofile: Option<u64>,           // RLIMIT_NOFILE
+    pub nproc: Option<u64>,            // RLIMIT_NPROC, counted per user not per child
+    pub core: Option<u64>,             // RLIMIT_CORE, 0 disables core dumps
+    pub cgroup: Option<String>,        // cgroup v2 group to create or join, relative to /sys/fs/cgroup
+    pub memory_mb: Option<u64>,        // memory.max of the cgroup
+    pub cpu_percent: Option<u32>,      // cpu.max of the cgroup, 100 is one full core
+    pub oom_score_adj: Option<i32>,    // -1000 to 1000, higher gets killed first
 }
 
 #[allow(dead_code)]
 impl AppSpecificConfig {
-    pub fn safe_path(&self) -> PathType {
-        let self_cloned = self.clone();
-        let path = PathType::Content(self_cloned.monitor_path);
-        if !path.exists() {
-            log!(LogLevel::Error, "The path {} doesn't exist", path);
-            std::process::exit(0)
-        } else {
-            match path.canonicalize() {
-                Ok(canon_path) => PathType::PathBuf(canon_path),
-                Err(e) => {
-                    log!(
-                        LogLevel::Error,
-                        "Failed to canonicalize path: {}, using default: {}",
-                        e,
-                        path
-                    );
-                    path
-                }
+    /// The canonical `monitor_path`, an error when it doesn't exist
+    pub fn safe_path(&self) -> Result<PathType, ErrorArrayItem> {
+        existing_path("monitor_path", &self.monitor_path)
+    }
+
+    /// The canonical `project_path`, an error when it doesn't exist
+    pub fn project_path(&self) -> Result<PathType, ErrorArrayItem> {
+        existing_path("project_path", &self.project_path)
+    }
+
+    /// Port of instance `index`, each instance gets `port` plus its index
+    pub fn instance_port(&self, index: u16) -> Result<u16, ErrorArrayItem> {
+        self.port.checked_add(index).ok_or_else(|| {
+            ErrorArrayItem::new(
+                Errors::GeneralError,
+                format!("Instance {} has no port, {} + {} is past {}", index, self.port, index, u16::MAX),
+            )
+        })
+    }
+
+    /// The configured package manager, falling back to lockfile detection and then npm
+    pub fn package_manager(&self) -> PackageManager {
+        if let Some(manager) = self.package_manager {
+            return manager;
+        }
+
+        match PackageManager::detect(Path::new(&self.project_path)) {
+            Some(manager) => {
+                log!(LogLevel::Trace, "Detected package manager {} from lockfile", manager);
+                manager
             }
+            None => PackageManager::Npm,
         }
     }
 
-    pub fn project_path(&self) -> PathType {
-        let self_cloned = self.clone();
-        let path = PathType::Content(self_cloned.project_path);
-        if !path.exists() {
-            log!(LogLevel::Error, "The path {} doesn't exist", path);
-            std::process::exit(0)
+    /// Directory the one shot and the child run from, when configured
+    pub fn working_dir(&self) -> Option<PathBuf> {
+        let path = PathBuf::from(self.working_dir.as_ref()?);
+        if path.is_absolute() {
+            Some(path)
         } else {
-            match path.canonicalize() {
-                Ok(canon_path) => PathType::PathBuf(canon_path),
-                Err(e) => {
-                    log!(
-                        LogLevel::Error,
-                        "Failed to canonicalize path: {}, using default: {}",
-                        e,
-                        path
-                    );
-                    path
-                }
-            }
+            Some(Path::new(&self.project_path).join(path))
+        }
+    }
+
+    /// Location of the env file, relative paths live inside the project
+    pub fn env_file_path(&self) -> PathBuf {
+        let path = PathBuf::from(&self.env_file);
+        if path.is_absolute() {
+            path
+        } else {
+            Path::new(&self.project_path).join(path)
         }
     }
 
     /// Converts ignored_subdirs strings into PathType objects relative to the monitor_path
-    pub fn ignored_paths(&self) -> Option<Vec<PathType>> {
-        let base_path = self.safe_path(); // Canonicalize the monitor path
+    pub fn ignored_paths(&self) -> Result<Option<Vec<PathType>>, ErrorArrayItem> {
+        let base_path = self.safe_path()?; // Canonicalize the monitor path
         
         let sub_dirs: Vec<PathType> = self.ignored_subdirs
             .iter()
@@ -108,21 +1036,635 @@ impl AppSpecificConfig {
             .collect();
 
         if sub_dirs.is_empty() {
-            return None
+            return Ok(None)
+        }
+
+        return Ok(Some(sub_dirs))
+    }
+}
+
+/// Canonicalizes a configured path, keeping it as written when that fails
+fn existing_path(setting: &str, path: &str) -> Result<PathType, ErrorArrayItem> {
+    let path = PathType::Content(path.to_owned());
+    if !path.exists() {
+        return Err(ErrorArrayItem::new(
+            Errors::InputOutput,
+            format!("The {} {} doesn't exist", setting, path),
+        ));
+    }
+
+    match path.canonicalize() {
+        Ok(canon_path) => Ok(PathType::PathBuf(canon_path)),
+        Err(e) => {
+            log!(
+                LogLevel::Error,
+                "Failed to canonicalize path: {}, using default: {}",
+                e,
+                path
+            );
+            Ok(path)
         }
+    }
+}
 
-        return Some(sub_dirs)
+/// Where the specific config is read from: `--config <path>`, then the
+/// `ARTISAN_CONFIG` variable, then `Config` in the working directory
+pub fn config_file() -> PathBuf {
+    if let Some(path) = &cli_args().config {
+        return path.clone();
+    }
+
+    match std::env::var("ARTISAN_CONFIG") {
+        Ok(path) if !path.is_empty() => PathBuf::from(path),
+        _ => PathBuf::from("Config"),
     }
 }
 
-pub fn specific_config() -> Result<AppSpecificConfig, ConfigError> {
+/// The profile to apply: `--env <name>`, then the `ARTISAN_ENV` variable
+pub fn config_profile() -> Option<String> {
+    if let Some(profile) = &cli_args().env {
+        return Some(profile.clone());
+    }
+
+    std::env::var("ARTISAN_ENV").ok().filter(|profile| !profile.is_empty())
+}
+
+/// Layers `[app_specific.<profile>]` over the shared settings in the file.
+/// Nested tables are merged key by key, lists are replaced whole.
+fn profile_overrides(mut builder: ConfigBuilder<DefaultState>, profile: &str) -> Result<ConfigBuilder<DefaultState>, ConfigError> {
+    let file = builder.clone().build()?;
+    let table = match file.get_table(&format!("app_specific.{}", profile)) {
+        Ok(table) => table,
+        Err(ConfigError::NotFound(_)) => {
+            return Err(ConfigError::Message(format!("Profile {} isn't defined, add an [app_specific.{}] table", profile, profile)));
+        }
+        Err(err) => return Err(err),
+    };
+
+    let mut overrides = Vec::new();
+    flatten_table(String::from("app_specific"), table, &mut overrides);
+    for (key, value) in overrides {
+        builder = builder.set_override(key, value)?;
+    }
+
+    log!(LogLevel::Info, "Applied the {} profile", profile);
+    Ok(builder)
+}
+
+fn flatten_table(prefix: String, table: Map<String, Value>, overrides: &mut Vec<(String, Value)>) {
+    for (key, value) in table {
+        let key = format!("{}.{}", prefix, key);
+        match value.kind {
+            ValueKind::Table(table) => flatten_table(key, table, overrides),
+            _ => overrides.push((key, value)),
+        }
+    }
+}
+
+/// Fields given as comma separated lists in the environment
+const LIST_FIELDS: [&str; 8] = [
+    "ignored_subdirs",
+    "args",
+    "one_shot_args",
+    "extra_args",
+    "event_kinds",
+    "container.run_args",
+    "remote.ignored_subdirs",
+    "remote.ssh_args",
+];
+
+/// Layers `ARTISAN_<FIELD>` variables over the file, e.g. `ARTISAN_MONITOR_PATH`
+/// or `ARTISAN_HEALTH_CHECK__URL` for nested tables
+fn environment_overrides(mut builder: ConfigBuilder<DefaultState>) -> Result<ConfigBuilder<DefaultState>, ConfigError> {
+    let environment = LIST_FIELDS.iter().fold(
+        Environment::with_prefix("ARTISAN")
+            .prefix_separator("_")
+            .separator("__")
+            .try_parsing(true)
+            .list_separator(","),
+        |environment, field| environment.with_list_parse_key(field),
+    );
+
+    for (key, value) in environment.collect()? {
+        // ARTISAN_CONFIG and ARTISAN_ENV pick the file and profile themselves
+        if key == "config" || key == "env" {
+            continue;
+        }
+        builder = builder.set_override(format!("app_specific.{}", key), value)?;
+    }
+
+    Ok(builder)
+}
+
+/// Supported config formats by extension
+const CONFIG_FORMATS: [(&str, FileFormat); 4] = [
+    ("toml", FileFormat::Toml),
+    ("yaml", FileFormat::Yaml),
+    ("yml", FileFormat::Yaml),
+    ("json", FileFormat::Json),
+];
+
+/// The config file and its format. A path without an extension is looked
+/// up with each supported one, `None` when none of them exists.
+fn resolve_config_file(path: &Path) -> Result<Option<(PathBuf, FileFormat)>, ConfigError> {
+    if let Some(extension) = path.extension() {
+        let extension = extension.to_string_lossy().to_lowercase();
+        return match CONFIG_FORMATS.iter().find(|(known, _)| *known == extension) {
+            Some((_, format)) => Ok(Some((path.to_path_buf(), *format))),
+            None => Err(ConfigError::Message(format!(
+                "{} has an unsupported format, use .toml, .yaml, .yml or .json",
+                path.display()
+            ))),
+        };
+    }
+
+    let found: Vec<(PathBuf, FileFormat)> = CONFIG_FORMATS
+        .iter()
+        .map(|(extension, format)| (path.with_extension(extension), *format))
+        .filter(|(candidate, _)| candidate.is_file())
+        .collect();
+
+    if found.len() > 1 {
+        log!(
+            LogLevel::Warn,
+            "Found {} config files for {}, using {}",
+            found.len(),
+            path.display(),
+            found[0].0.display()
+        );
+    }
+
+    Ok(found.into_iter().next())
+}
+
+/// Directory of snippets merged over the config file, `Config.d` next to `Config`
+pub fn drop_in_dir(path: &Path) -> PathBuf {
+    path.with_extension("d")
+}
+
+/// The snippets in `dir` in name order, later ones winning. Hidden files and
+/// unsupported extensions, such as editor backups, are skipped.
+fn drop_in_files(dir: &Path) -> Vec<(PathBuf, FileFormat)> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut files: Vec<(PathBuf, FileFormat)> = Vec::new();
+    for path in entries.flatten().map(|entry| entry.path()).filter(|path| path.is_file()) {
+        if path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.')) {
+            continue;
+        }
+
+        let extension = path.extension().map(|extension| extension.to_string_lossy().to_lowercase());
+        match CONFIG_FORMATS.iter().find(|(known, _)| Some(*known) == extension.as_deref()) {
+            Some((_, format)) => files.push((path, *format)),
+            None => log!(LogLevel::Debug, "Skipping {}, it isn't .toml, .yaml, .yml or .json", path.display()),
+        }
+    }
+
+    files.sort_by(|(a, _), (b, _)| a.cmp(b));
+    files
+}
+
+/// Loads every application in the specific config: one per `[[applications]]`
+/// entry, each layered over the shared `[app_specific]` settings, or just the
+/// shared settings when there are no entries.
+pub fn specific_configs() -> Result<Vec<AppSpecificConfig>, ConfigError> {
+    load_applications(None)
+}
+
+/// Loads the applications, or only the `[[applications]]` entry named `only`.
+/// The other entries aren't validated then, their mistakes are only warned
+/// about so they can't hold the requested one back.
+fn load_applications(only: Option<&str>) -> Result<Vec<AppSpecificConfig>, ConfigError> {
+    let path = config_file();
     let mut builder = Config::builder();
-    builder = builder.add_source(File::with_name("Config").required(false));
 
-    let settings = builder.build()?;
-    let app_specific: AppSpecificConfig = settings.get("app_specific")?;
+    match resolve_config_file(&path)? {
+        Some((file, format)) => match migrate(&file, format)? {
+            Some((migrated, format)) => builder = builder.add_source(File::from_str(&migrated, format)),
+            None => builder = builder.add_source(File::from(file.as_path()).format(format)),
+        },
+        // An explicitly chosen file has to exist, the default one is optional
+        None if path != Path::new("Config") => {
+            return Err(ConfigError::Message(format!("Config file {} doesn't exist", path.display())));
+        }
+        None => (),
+    }
+
+    for (file, format) in drop_in_files(&drop_in_dir(&path)) {
+        log!(LogLevel::Debug, "Merging {}", file.display());
+        builder = builder.add_source(File::from(file.as_path()).format(format));
+    }
+
+    if let Some(profile) = config_profile() {
+        builder = profile_overrides(builder, &profile)?;
+    }
+
+    let applications = match builder.clone().build()?.get_array("applications") {
+        Ok(applications) => applications,
+        Err(ConfigError::NotFound(_)) => return Ok(vec![application_settings(builder, &path)?]),
+        Err(err) => return Err(err),
+    };
+
+    let mut loaded: Vec<AppSpecificConfig> = Vec::new();
+    let mut names: Vec<String> = Vec::new();
+    let mut problems = Vec::new();
+    let mut other_problems = Vec::new();
+
+    for (index, application) in applications.into_iter().enumerate() {
+        let Ok(table) = application.into_table() else {
+            other_problems.push(format!("applications[{}] isn't a table", index));
+            continue;
+        };
+
+        let name = match table.get("name").and_then(|name| name.clone().into_string().ok()) {
+            Some(name) if valid_application_name(&name) => name,
+            _ => {
+                other_problems.push(format!("applications[{}].name is missing, expected letters, digits, - and _", index));
+                continue;
+            }
+        };
+
+        let requested = only.is_none_or(|only| only == name);
+        if names.contains(&name) {
+            let problem = format!("applications.{} is defined twice", name);
+            match requested {
+                true => problems.push(problem),
+                false => other_problems.push(problem),
+            }
+            continue;
+        }
+        names.push(name.clone());
+        if !requested {
+            continue;
+        }
+
+        // Entries are applied like a profile, nested tables key by key and lists whole
+        let mut overrides = Vec::new();
+        flatten_table(String::from("app_specific"), table, &mut overrides);
+        let mut application = builder.clone();
+        for (key, value) in overrides {
+            application = application.set_override(key, value)?;
+        }
+
+        match application_settings(application, &path) {
+            Ok(settings) => loaded.push(settings),
+            Err(err) => problems.extend(err.to_string().lines().map(|problem| format!("applications.{}: {}", name, problem))),
+        }
+    }
+
+    match only {
+        None => problems.append(&mut other_problems),
+        Some(_) => {
+            for problem in other_problems {
+                log!(LogLevel::Warn, "{}", problem);
+            }
+        }
+    }
+
+    if only.is_none() && loaded.is_empty() && problems.is_empty() {
+        problems.push(String::from("applications is empty, add an entry or remove it"));
+    }
+
+    match problems.is_empty() {
+        true => Ok(loaded),
+        false => Err(ConfigError::Message(problems.join("\n"))),
+    }
+}
+
+/// The settings of the application `name`, `None` being the only one of a
+/// file without `[[applications]]`. Only that entry is validated.
+pub fn specific_config(name: Option<&str>) -> Result<AppSpecificConfig, ConfigError> {
+    load_applications(name)?
+        .into_iter()
+        .find(|settings| settings.name.as_deref() == name)
+        .ok_or_else(|| match name {
+            Some(name) => ConfigError::Message(format!("applications.{} is no longer defined", name)),
+            None => ConfigError::Message(String::from("The file defines [[applications]] now, restart the runner to supervise them")),
+        })
+}
+
+/// Applies the environment, then validates and reads `app_specific`
+fn application_settings(builder: ConfigBuilder<DefaultState>, path: &Path) -> Result<AppSpecificConfig, ConfigError> {
+    let settings = environment_overrides(builder)?.build()?;
+    let mut table = match settings.get_table("app_specific") {
+        Ok(table) => table,
+        Err(ConfigError::NotFound(_)) => {
+            return Err(ConfigError::Message(format!("{} has no [app_specific] table", path.display())));
+        }
+        Err(err) => return Err(err),
+    };
+
+    let mut problems = Vec::new();
+    let secret_keys = resolve_secrets(&mut table, "app_specific", &mut problems);
+    problems.extend(validate_fields(&table));
+
+    let mut unknown = Vec::new();
+    unknown_keys(&table, "AppSpecificConfig", "app_specific", true, &mut unknown);
+    let strict = cli_args().strict || table.get("strict").is_some_and(|strict| strict.clone().into_bool().unwrap_or(false));
+    match strict {
+        true => problems.extend(unknown),
+        false => {
+            for key in unknown {
+                log!(LogLevel::Warn, "{}, it is ignored", key);
+            }
+        }
+    }
+
+    let app_specific = AppSpecificConfig::deserialize(Value::new(None, ValueKind::Table(table)))
+        .map_err(|err| err.extend_with_key("app_specific"));
+    match app_specific {
+        Ok(mut app_specific) => {
+            app_specific.secret_keys = secret_keys
+                .iter()
+                .map(|key| key.trim_start_matches("app_specific.").to_owned())
+                .collect();
+            problems.extend(validate_values(&app_specific));
+            match problems.is_empty() {
+                true => Ok(app_specific),
+                false => Err(ConfigError::Message(problems.join("\n"))),
+            }
+        }
+        Err(err) => {
+            // serde stops at the first problem, which may be one already reported
+            if problems.is_empty() {
+                problems.push(err.to_string());
+            }
+            Err(ConfigError::Message(problems.join("\n")))
+        }
+    }
+}
+
+/// Names end up in file names and the app name, keep them plain
+fn valid_application_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// The AppConfig of one application. `[[applications]]` entries run as
+/// `<app_name>-<name>`, so their children, pid files and logs stay apart,
+/// and `max_ram_mb`, `max_cpu_percent` and the runner's log level replace
+/// the global ones.
+pub fn application_config(settings: &AppSpecificConfig) -> AppConfig {
+    let mut config = get_config();
+    if let Some(name) = &settings.name {
+        config.app_name = Stringy::from(format!("{}-{}", config.app_name, name));
+    }
+    if let Some(max_ram_mb) = settings.max_ram_mb {
+        config.max_ram_usage = max_ram_mb as usize;
+    }
+    if let Some(max_cpu_percent) = settings.max_cpu_percent {
+        config.max_cpu_usage = max_cpu_percent as usize;
+    }
+    if let Some(level) = settings.logging.runner.level {
+        config.log_level = level.level();
+    }
+    config
+}
+
+/// Where the state of one application is kept. `[[applications]]` entries
+/// each get their own file next to the default one, the memory backend adds
+/// the pid so no earlier state is ever picked up.
+pub fn application_state_path(settings: &AppSpecificConfig) -> PathType {
+    let mut path: PathBuf = StatePersistence::get_state_path(&get_config()).to_path_buf();
+    if let Some(directory) = settings.state.directory() {
+        path = directory.join(path.file_name().unwrap_or_default());
+    }
 
-    Ok(app_specific)
+    let mut tags: Vec<String> = Vec::new();
+    if let Some(name) = &settings.name {
+        tags.push(name.clone());
+    }
+    if settings.state.backend == StateBackend::Memory {
+        tags.push(std::process::id().to_string());
+    }
+    if tags.is_empty() {
+        return PathType::PathBuf(path);
+    }
+
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let tags = tags.join(".");
+    match path.extension() {
+        Some(extension) => PathType::PathBuf(path.with_file_name(format!("{}.{}.{}", stem, tags, extension.to_string_lossy()))),
+        None => PathType::PathBuf(path.with_file_name(format!("{}.{}", stem, tags))),
+    }
+}
+
+/// Creates the directory of a configured state location and has memory
+/// backed state removed on exit, along with its pending changes and reload
+/// history
+pub fn prepare_state_path(settings: &AppSpecificConfig, state_path: &Path) {
+    if settings.state.directory().is_some() {
+        if let Some(directory) = state_path.parent() {
+            if let Err(err) = fs::create_dir_all(directory) {
+                log!(LogLevel::Warn, "Couldn't create the state directory {}: {}", directory.display(), err);
+            }
+        }
+    }
+
+    if settings.state.backend == StateBackend::Memory {
+        register_artifact(state_path);
+        register_artifact(pending_changes_path(state_path));
+        register_artifact(reload_history_path(state_path));
+    }
+}
+
+/// Exit code for settings that don't load, `EX_CONFIG` from sysexits.h
+pub const EXIT_CONFIG: i32 = 78;
+
+enum FieldKind {
+    Integer,
+    Text,
+    TextList,
+}
+
+/// Bounds of `interval_seconds`, the supervision tick
+pub const MIN_INTERVAL_SECONDS: u32 = 1;
+pub const MAX_INTERVAL_SECONDS: u32 = 3600;
+
+/// Values that deserialize fine but can't work
+fn validate_values(settings: &AppSpecificConfig) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    if !(MIN_INTERVAL_SECONDS..=MAX_INTERVAL_SECONDS).contains(&settings.interval_seconds) {
+        problems.push(format!(
+            "app_specific.interval_seconds is {}, expected {} to {} seconds",
+            settings.interval_seconds, MIN_INTERVAL_SECONDS, MAX_INTERVAL_SECONDS
+        ));
+    }
+
+    if settings.max_ram_mb == Some(0) {
+        problems.push(String::from("app_specific.max_ram_mb is 0, expected at least 1 MB or leave it unset"));
+    }
+
+    let last_index = settings.instances.max(1) - 1;
+    if settings.instance_port(last_index).is_err() {
+        problems.push(format!(
+            "app_specific.port {} with {} instances runs past port {}, expected at most {}",
+            settings.port,
+            settings.instances,
+            u16::MAX,
+            u16::MAX - last_index
+        ));
+    }
+
+    // Every core at full load is 100% each
+    let cores = std::thread::available_parallelism().map_or(1, |cores| cores.get() as u32);
+    if let Some(max_cpu_percent) = settings.max_cpu_percent.filter(|percent| *percent > cores * 100) {
+        problems.push(format!(
+            "app_specific.max_cpu_percent is {}, this host has {} cores so at most {} can be reached",
+            max_cpu_percent,
+            cores,
+            cores * 100
+        ));
+    }
+
+    for (name, route) in [("runner", &settings.logging.runner), ("child", &settings.logging.child)] {
+        if route.destination == Some(LogDestination::File) && route.file.is_none() {
+            problems.push(format!("app_specific.logging.{}.destination is file, expected a path in logging.{}.file", name, name));
+        }
+    }
+
+    if settings.build_retry_backoff_max_seconds < settings.build_retry_backoff_seconds {
+        problems.push(format!(
+            "app_specific.build_retry_backoff_max_seconds is {}, expected at least build_retry_backoff_seconds ({})",
+            settings.build_retry_backoff_max_seconds, settings.build_retry_backoff_seconds
+        ));
+    }
+
+    if settings.one_shot_command.is_some() && !settings.build_steps.is_empty() {
+        problems.push(String::from("app_specific.one_shot_command and build_steps are both set, use one of them"));
+    }
+
+    for (index, rule) in settings.watch_rules.iter().enumerate() {
+        let key = format!("app_specific.watch_rules[{}]", index);
+        if rule.name.trim().is_empty() {
+            problems.push(format!("{}.name is empty, every rule needs a name", key));
+        } else if settings.watch_rules[..index].iter().any(|other| other.name == rule.name) {
+            problems.push(format!("{}.name {} is used by another rule", key, rule.name));
+        }
+        if rule.changes_needed < 1 {
+            problems.push(format!("{}.changes_needed is {}, expected at least 1", key, rule.changes_needed));
+        }
+        if let Some(Err(err)) = rule.pattern.as_deref().map(rule_glob) {
+            problems.push(format!("{}.pattern is invalid: {}", key, err));
+        }
+        if rule.action == WatchAction::Hook && rule.hook.is_none() {
+            problems.push(format!("{}.action is hook, expected a command in hook", key));
+        }
+    }
+
+    if settings.error_log_max_entries == 0 {
+        problems.push(String::from("app_specific.error_log_max_entries is 0, at least 1 error has to be kept"));
+    }
+
+    problems
+}
+
+/// Keys of `table` that no field of `type_name` reads, nested tables and
+/// lists of tables included. Other tables at the top are profiles, their
+/// keys are checked like the settings they override.
+fn unknown_keys(table: &Map<String, Value>, type_name: &str, prefix: &str, profiles: bool, unknown: &mut Vec<String>) {
+    let fields = struct_fields(type_name);
+
+    for (key, value) in table {
+        let path = format!("{}.{}", prefix, key);
+        let Some(field) = fields.iter().find(|field| (field.name == *key || field.aliases.contains(key)) && !field.skip) else {
+            match &value.kind {
+                ValueKind::Table(profile) if profiles => unknown_keys(profile, type_name, &path, false, unknown),
+                _ => {
+                    let suggestion = fields
+                        .iter()
+                        .filter(|field| !field.skip && edit_distance(&field.name, key) <= 2)
+                        .min_by_key(|field| edit_distance(&field.name, key))
+                        .map(|field| format!(" (did you mean {}?)", field.name))
+                        .unwrap_or_default();
+                    unknown.push(format!("{} isn't a setting{}", path, suggestion));
+                }
+            }
+            continue;
+        };
+
+        match (field.ty.inner(), &value.kind) {
+            (FieldType::Named(nested), ValueKind::Table(table)) => unknown_keys(table, nested, &path, false, unknown),
+            (FieldType::List(item), ValueKind::Array(items)) => {
+                let FieldType::Named(nested) = item.inner() else {
+                    continue;
+                };
+                if !matches!(config_types().get(nested), Some(info) if matches!(info.kind, TypeKind::Struct(_))) {
+                    continue;
+                }
+                for (index, item) in items.iter().enumerate() {
+                    if let ValueKind::Table(table) = &item.kind {
+                        unknown_keys(table, nested, &format!("{}[{}]", path, index), false, unknown);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Levenshtein distance, for suggesting the setting a typo meant
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+/// Fields without a default and what they have to hold
+const REQUIRED_FIELDS: [(&str, FieldKind, &str); 5] = [
+    ("interval_seconds", FieldKind::Integer, "seconds between supervision checks"),
+    ("monitor_path", FieldKind::Text, "directory watched for changes"),
+    ("project_path", FieldKind::Text, "directory the project is built and run in"),
+    ("changes_needed", FieldKind::Integer, "changes that trigger a restart"),
+    ("ignored_subdirs", FieldKind::TextList, "subdirectories of monitor_path to ignore, may be empty"),
+];
+
+/// Every required field that is missing or holds the wrong type, so all of
+/// them can be fixed in one go
+fn validate_fields(table: &Map<String, Value>) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    for (field, kind, purpose) in &REQUIRED_FIELDS {
+        let expected = match kind {
+            FieldKind::Integer => "a whole number",
+            FieldKind::Text => "a string",
+            FieldKind::TextList => "a list of strings",
+        };
+
+        let valid = match table.get(*field) {
+            None => {
+                problems.push(format!("app_specific.{} is missing, expected {} ({})", field, expected, purpose));
+                continue;
+            }
+            Some(value) => match kind {
+                FieldKind::Integer => value.clone().into_int().is_ok(),
+                FieldKind::Text => !matches!(value.kind, ValueKind::Table(_) | ValueKind::Array(_)),
+                FieldKind::TextList => value
+                    .clone()
+                    .into_array()
+                    .is_ok_and(|items| items.into_iter().all(|item| item.into_string().is_ok())),
+            },
+        };
+
+        if !valid {
+            problems.push(format!("app_specific.{} is invalid, expected {} ({})", field, expected, purpose));
+        }
+    }
+
+    problems
 }
 
 impl fmt::Display for AppSpecificConfig {
@@ -135,8 +1677,13 @@ impl fmt::Display for AppSpecificConfig {
              \t{}: {},\n\
              \t{}: {},\n\
              \t{}: {},\n\
+             \t{}: {},\n\
+             \t{}: {},\n\
+             \t{}: {:?},\n\
              }}",
             "AppSpecificConfig".cyan().bold(),
+            "config_version".yellow(),
+            self.config_version.to_string().green(),
             "interval_seconds".yellow(),
             self.interval_seconds.to_string().green(),
             "monitor_path".yellow(),
@@ -146,7 +1693,19 @@ impl fmt::Display for AppSpecificConfig {
             "changes_needed".yellow(),
             self.changes_needed.to_string().green(),
             "Ignored_directories".yellow(),
-            self.ignored_subdirs.join(" ").green()
+            self.ignored_subdirs.join(" ").green(),
+            "command".yellow(),
+            self.command
+                .clone()
+                .map(|program| format!("{} {}", program, self.args.join(" ")))
+                .unwrap_or_else(|| format!("{} run {}", self.package_manager(), self.start_script))
+                .split_whitespace()
+                .chain(self.extra_args.iter().map(String::as_str))
+                .collect::<Vec<&str>>()
+                .join(" ")
+                .green(),
+            "limits".yellow(),
+            self.limits
         )
     }
 }
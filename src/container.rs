@@ -0,0 +1,95 @@
+use dusa_collection_utils::log;
+use dusa_collection_utils::log::LogLevel;
+use std::{path::Path, process::Stdio};
+use tokio::process::Command;
+
+use crate::{config::AppSpecificConfig, sandbox::CgroupTarget};
+
+/// Name of the container serving `port`, fixed so a leftover can be found and
+/// removed. A zero downtime replacement runs on the other port, its name
+/// never clashes with the container still serving.
+pub fn container_name(app_name: &str, port: u16) -> String {
+    format!("{}-{}", app_name, port)
+}
+
+/// The configured image, or `<project directory>:latest` for locally built ones
+pub fn image_tag(settings: &AppSpecificConfig) -> String {
+    if let Some(image) = &settings.container.image {
+        return image.clone();
+    }
+
+    let project = Path::new(&settings.project_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .unwrap_or_else(|| String::from("app"));
+    format!("{}:latest", project)
+}
+
+/// `<engine> build` for the one shot, None when a prebuilt image is used as is
+pub fn build_command(settings: &AppSpecificConfig) -> Option<Command> {
+    if !settings.container.build {
+        return None;
+    }
+
+    let mut command = Command::new(settings.container.engine.program());
+    command.args(["build", "--tag", &image_tag(settings)]);
+    if let Some(dockerfile) = &settings.container.dockerfile {
+        command.args(["--file", dockerfile]);
+    }
+    command.arg(&settings.project_path);
+
+    Some(command)
+}
+
+/// `<engine> run` in the foreground, so the supervised process lives exactly as
+/// long as the container and signals are proxied into it. `env_keys` are
+/// forwarded from the engine's own environment, which the runner prepares as
+/// it would for a plain child.
+pub fn run_command(
+    settings: &AppSpecificConfig,
+    name: &str,
+    port: u16,
+    env_keys: &[String],
+    cgroup: Option<&CgroupTarget>,
+) -> Command {
+    let container_port = settings.container.container_port.unwrap_or(port);
+
+    let mut command = Command::new(settings.container.engine.program());
+    command.args(["run", "--rm", "--init", "--sig-proxy=true", "--name", name]);
+    command.args(["--publish", &format!("{}:{}", port, container_port)]);
+    command.args(["--env", &format!("PORT={}", container_port)]);
+
+    for key in env_keys.iter().filter(|key| *key != "PORT") {
+        command.args(["--env", key]);
+    }
+
+    // The runner's cgroup limits would only confine the engine client
+    if let Some(target) = cgroup {
+        if let Some(mb) = target.memory_mb {
+            command.arg(format!("--memory={}m", mb));
+        }
+        if let Some(percent) = target.cpu_percent {
+            command.arg(format!("--cpus={:.2}", percent as f32 / 100.0));
+        }
+    }
+
+    command.args(&settings.container.run_args);
+    command.arg(image_tag(settings));
+    command.args(&settings.args);
+    command.args(&settings.extra_args);
+    command
+}
+
+/// Removes a container left behind by a child that was killed before the engine could clean up
+pub async fn remove_container(settings: &AppSpecificConfig, name: &str) {
+    let result = Command::new(settings.container.engine.program())
+        .args(["rm", "--force", name])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .await;
+
+    if let Err(err) = result {
+        log!(LogLevel::Warn, "Couldn't run {} rm for {}: {}", settings.container.engine.program(), name, err);
+    }
+}
@@ -0,0 +1,169 @@
+use dusa_collection_utils::log;
+use dusa_collection_utils::log::LogLevel;
+use std::{
+    fs,
+    io::{BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use crate::{
+    cleanup::register_artifact,
+    config::{AppSpecificConfig, WatchRootConfig},
+    monitor::{watch_roots, WatchHandle},
+};
+
+/// Listens on `watch_socket` for commands that change the watched roots and
+/// ignores at runtime, one per line:
+///
+/// - `list` prints every root with its ignores
+/// - `add <path> [ignore...]` watches another root
+/// - `remove <path>` stops watching a root added by `watch_roots` or `add`
+/// - `ignore <pattern>` / `unignore <pattern>` change the ignores of every root
+///
+/// The child keeps running, changes last until the runner restarts or reloads.
+pub fn start_watch_socket(settings: &AppSpecificConfig, handle: WatchHandle) {
+    let path = match &settings.watch_socket {
+        Some(path) => PathBuf::from(path),
+        None => return,
+    };
+
+    // A stale socket from a previous run would make the bind fail
+    if path.exists() {
+        let _ = fs::remove_file(&path);
+    }
+
+    let listener = match UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        Err(err) => {
+            log!(LogLevel::Warn, "Couldn't bind watch socket {}: {}", path.display(), err);
+            return;
+        }
+    };
+
+    register_artifact(&path);
+    log!(LogLevel::Info, "Accepting watch commands on {}", path.display());
+
+    let settings = handle.settings();
+    thread::spawn(move || {
+        for connection in listener.incoming() {
+            match connection {
+                Ok(client) => {
+                    let settings = settings.clone();
+                    let handle = handle.clone();
+                    thread::spawn(move || serve_client(client, settings, handle));
+                }
+                Err(err) => log!(LogLevel::Warn, "Watch socket accept failed: {}", err),
+            }
+        }
+    });
+}
+
+fn serve_client(client: UnixStream, settings: Arc<Mutex<AppSpecificConfig>>, handle: WatchHandle) {
+    let mut reply = match client.try_clone() {
+        Ok(reply) => reply,
+        Err(err) => {
+            log!(LogLevel::Warn, "Couldn't set up watch client: {}", err);
+            return;
+        }
+    };
+
+    for line in BufReader::new(client).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                log!(LogLevel::Debug, "Watch client went away: {}", err);
+                break;
+            }
+        };
+
+        let answer = match settings.lock() {
+            Ok(mut settings) => run_command(&mut settings, &handle, line.trim()),
+            Err(_) => String::from("error: settings unavailable"),
+        };
+
+        if reply.write_all(format!("{}\n", answer).as_bytes()).is_err() {
+            break;
+        }
+    }
+}
+
+fn run_command(settings: &mut AppSpecificConfig, handle: &WatchHandle, line: &str) -> String {
+    let mut words = line.split_whitespace();
+    let command = words.next().unwrap_or_default();
+    let arguments: Vec<String> = words.map(String::from).collect();
+
+    match (command, arguments.as_slice()) {
+        ("list", []) => return list_roots(settings),
+        ("add", [path, ignores @ ..]) => {
+            let path = match Path::new(path).canonicalize() {
+                Ok(path) => path.to_string_lossy().into_owned(),
+                Err(err) => return format!("error: {}: {}", path, err),
+            };
+            if is_watched(settings, &path) {
+                return format!("error: {} is already watched", path);
+            }
+            settings.watch_roots.push(WatchRootConfig {
+                path,
+                ignored_subdirs: ignores.to_vec(),
+            });
+        }
+        ("remove", [path]) => {
+            let before = settings.watch_roots.len();
+            settings.watch_roots.retain(|root| !same_path(&root.path, path));
+            if settings.watch_roots.len() == before {
+                return format!("error: {} isn't a removable root", path);
+            }
+        }
+        ("ignore", [pattern]) => {
+            add_unique(&mut settings.ignored_subdirs, pattern);
+            for root in settings.watch_roots.iter_mut() {
+                add_unique(&mut root.ignored_subdirs, pattern);
+            }
+        }
+        ("unignore", [pattern]) => {
+            settings.ignored_subdirs.retain(|ignored| ignored != pattern);
+            for root in settings.watch_roots.iter_mut() {
+                root.ignored_subdirs.retain(|ignored| ignored != pattern);
+            }
+        }
+        _ => return String::from("error: expected list, add <path> [ignore...], remove <path>, ignore <pattern> or unignore <pattern>"),
+    }
+
+    log!(LogLevel::Info, "Watch command: {}", line);
+    let roots = match watch_roots(settings) {
+        Ok(roots) => roots,
+        Err(err) => return format!("error: {}", err),
+    };
+    match handle.replace_roots(roots) {
+        true => String::from("ok"),
+        false => String::from("error: the watcher isn't running"),
+    }
+}
+
+fn list_roots(settings: &AppSpecificConfig) -> String {
+    let mut lines = vec![format!("{} ignoring [{}]", settings.monitor_path, settings.ignored_subdirs.join(", "))];
+    for root in &settings.watch_roots {
+        lines.push(format!("{} ignoring [{}]", root.path, root.ignored_subdirs.join(", ")));
+    }
+    lines.join("\n")
+}
+
+fn is_watched(settings: &AppSpecificConfig, path: &str) -> bool {
+    same_path(&settings.monitor_path, path) || settings.watch_roots.iter().any(|root| same_path(&root.path, path))
+}
+
+fn same_path(a: &str, b: &str) -> bool {
+    match (Path::new(a).canonicalize(), Path::new(b).canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => Path::new(a) == Path::new(b),
+    }
+}
+
+fn add_unique(list: &mut Vec<String>, value: &str) {
+    if !list.iter().any(|existing| existing == value) {
+        list.push(value.to_owned());
+    }
+}
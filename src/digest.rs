@@ -0,0 +1,84 @@
+use dusa_collection_utils::log;
+use dusa_collection_utils::log::LogLevel;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fs::File,
+    hash::Hasher,
+    io::Read,
+    path::{Path, PathBuf},
+};
+use walkdir::WalkDir;
+
+use crate::monitor::WatchRoot;
+
+/// Remembers a digest of every watched file so rewrites with identical
+/// content (touch, formatters) can be told apart from real edits.
+pub struct ContentHashes {
+    digests: HashMap<PathBuf, u64>,
+}
+
+impl ContentHashes {
+    /// Hashes every file below the roots that their filters don't ignore
+    pub fn new(roots: &[WatchRoot]) -> Self {
+        let mut digests = HashMap::new();
+
+        for root in roots {
+            let walker = WalkDir::new(&root.path)
+                .into_iter()
+                .filter_entry(|entry| !root.filter.ignores_tree(entry.path()));
+
+            for entry in walker.flatten() {
+                if !entry.file_type().is_file() || root.filter.ignores(entry.path()) {
+                    continue;
+                }
+
+                if let Some(digest) = digest(entry.path()) {
+                    digests.insert(entry.into_path(), digest);
+                }
+            }
+        }
+
+        log!(LogLevel::Debug, "Hashed {} watched files", digests.len());
+        Self { digests }
+    }
+
+    /// Refreshes the digests of `paths`, true when none of them changed content
+    pub fn unchanged(&mut self, paths: &[PathBuf]) -> bool {
+        let mut unchanged = true;
+
+        for path in paths {
+            let current = digest(path);
+            let previous = match current {
+                Some(digest) => self.digests.insert(path.clone(), digest),
+                None => self.digests.remove(path),
+            };
+
+            if current != previous {
+                unchanged = false;
+            }
+        }
+
+        unchanged
+    }
+}
+
+/// `None` for anything that isn't a readable regular file
+fn digest(path: &Path) -> Option<u64> {
+    if !path.is_file() {
+        return None;
+    }
+
+    let mut file = File::open(path).ok()?;
+    let mut hasher = DefaultHasher::new();
+    let mut buffer = [0u8; 64 * 1024];
+
+    loop {
+        match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => hasher.write(&buffer[..read]),
+            Err(_) => return None,
+        }
+    }
+
+    Some(hasher.finish())
+}
@@ -0,0 +1,79 @@
+use dusa_collection_utils::log;
+use dusa_collection_utils::log::LogLevel;
+use nix::sys::statvfs::statvfs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::config::AppSpecificConfig;
+
+const MB: u64 = 1024 * 1024;
+
+/// Measures the monitored and project paths against `disk_usage`, the
+/// message describes every threshold crossed. `None` when all is well or
+/// no threshold is set.
+pub fn disk_usage_problem(settings: &AppSpecificConfig) -> Option<String> {
+    let limits = &settings.disk_usage;
+    if !limits.enabled() {
+        return None;
+    }
+
+    let paths = measured_paths(settings);
+    let mut problems = Vec::new();
+
+    if let Some(max_size_mb) = limits.max_size_mb {
+        let size_mb = paths.iter().map(|path| tree_size(path)).sum::<u64>() / MB;
+        log!(LogLevel::Debug, "Monitored and project paths take up {} MB", size_mb);
+        if size_mb > max_size_mb {
+            problems.push(format!("the monitored and project paths take up {} MB, over the {} MB limit", size_mb, max_size_mb));
+        }
+    }
+
+    if let Some(min_free_mb) = limits.min_free_mb {
+        for path in &paths {
+            match statvfs(path.as_path()) {
+                Ok(stats) => {
+                    let free_mb = stats.blocks_available() * stats.fragment_size() / MB;
+                    if free_mb < min_free_mb {
+                        problems.push(format!("only {} MB free on the disk holding {}, below {} MB", free_mb, path.display(), min_free_mb));
+                    }
+                }
+                Err(err) => log!(LogLevel::Debug, "Couldn't read free space for {}: {}", path.display(), err),
+            }
+        }
+    }
+
+    match problems.is_empty() {
+        true => None,
+        false => Some(format!("Disk usage: {}", problems.join(", "))),
+    }
+}
+
+/// The monitored and project paths, without one nested in the other so nothing is counted twice
+fn measured_paths(settings: &AppSpecificConfig) -> Vec<PathBuf> {
+    let monitor = settings
+        .safe_path()
+        .map(|path| path.to_path_buf())
+        .unwrap_or_else(|_| PathBuf::from(&settings.monitor_path));
+    let project = Path::new(&settings.project_path)
+        .canonicalize()
+        .unwrap_or_else(|_| PathBuf::from(&settings.project_path));
+
+    if project.starts_with(&monitor) {
+        vec![monitor]
+    } else if monitor.starts_with(&project) {
+        vec![project]
+    } else {
+        vec![monitor, project]
+    }
+}
+
+/// Bytes taken up by the files below `path`, symlinks aren't followed
+fn tree_size(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum()
+}
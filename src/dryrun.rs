@@ -0,0 +1,208 @@
+use colored::Colorize;
+use std::{ffi::OsStr, time::Duration};
+use tokio::process::Command;
+
+use crate::{
+    child::{build_commands, hook_command, install_command, instance_command, prepare_build_step},
+    config::{application_config, application_state_path, config_file, specific_configs, AppSpecificConfig, WatchAction},
+    monitor::{monitor_directory, watch_roots, WatchAlerts},
+    sandbox::child_cgroup,
+};
+
+/// Exit codes of `--dry-run`
+pub const DRY_RUN_OK: i32 = 0;
+pub const DRY_RUN_FAILED: i32 = 1;
+pub const DRY_RUN_UNLOADABLE: i32 = 2;
+
+/// How long the watchers run and print what they see
+const WATCH_WINDOW: Duration = Duration::from_secs(10);
+
+/// Loads the settings, prints every command the runner would run with its
+/// environment and starts the watchers for a short while, without building,
+/// spawning or writing state. Returns the exit code for `--dry-run`.
+pub async fn run_dry_run() -> i32 {
+    let applications = match specific_configs() {
+        Ok(applications) => applications,
+        Err(err) => {
+            println!("{} {} doesn't load: {}", "error:".red().bold(), config_file().display(), err);
+            return DRY_RUN_UNLOADABLE;
+        }
+    };
+
+    let mut failed = false;
+    for settings in &applications {
+        failed |= !describe(settings);
+    }
+
+    println!("{} watching for {}s, nothing is counted or restarted", "watch:".cyan().bold(), WATCH_WINDOW.as_secs());
+    let mut watches = Vec::new();
+    for settings in &applications {
+        let label = application_config(settings).app_name.to_string();
+        let alerts = WatchAlerts::default();
+        match monitor_directory(settings, alerts.clone()).await {
+            Ok((mut event_rx, handle)) => {
+                watches.push((handle, alerts));
+                tokio::spawn(async move {
+                    while let Some(event) = event_rx.recv().await {
+                        let paths: Vec<String> = event.event.paths.iter().map(|path| path.display().to_string()).collect();
+                        println!("  {}: {:?} {}", label, event.event.kind, paths.join(" "));
+                    }
+                });
+            }
+            Err(err) => {
+                println!("{} {}: the watcher doesn't start: {}", "error:".red().bold(), label, err);
+                failed = true;
+            }
+        }
+    }
+    tokio::time::sleep(WATCH_WINDOW).await;
+
+    for (_, alerts) in &watches {
+        for warning in alerts.take() {
+            println!("{} {}", "warning:".yellow().bold(), warning);
+        }
+        failed |= alerts.degraded();
+    }
+
+    match failed {
+        true => DRY_RUN_FAILED,
+        false => DRY_RUN_OK,
+    }
+}
+
+/// Prints what one application would run, false when its paths don't resolve
+fn describe(settings: &AppSpecificConfig) -> bool {
+    let config = application_config(settings);
+    println!("{} {}", "application:".cyan().bold(), config.app_name);
+
+    let roots = match settings.project_path().and_then(|_| watch_roots(settings)) {
+        Ok(roots) => roots,
+        Err(err) => {
+            println!("{} {}", "error:".red().bold(), err);
+            return false;
+        }
+    };
+
+    let secrets = secret_values(settings);
+    for root in &roots {
+        println!("  watches {}", root.path.display());
+    }
+    for rule in &settings.watch_rules {
+        let pattern = rule.pattern.as_ref().map(|pattern| format!(" matching {}", pattern)).unwrap_or_default();
+        println!("  rule {}: {} change(s) below {}{} run its {} action", rule.name, rule.changes_needed, rule.path, pattern, rule.action);
+        if let Some(hook) = rule.hook.as_ref().filter(|_| rule.action == WatchAction::Hook) {
+            let command = hook_command(settings, &format!("watch_rules.{}", rule.name), hook, None);
+            print_command(settings, &secrets, &format!("hook of rule {}", rule.name), &command);
+        }
+    }
+    println!("  state in {}", application_state_path(settings).display());
+
+    let builds = build_commands(settings);
+    let total = builds.len();
+    for (position, mut command) in builds.into_iter().enumerate() {
+        prepare_build_step(settings, &mut command);
+        print_command(settings, &secrets, &format!("build {}/{}", position + 1, total), &command);
+    }
+
+    let mut install = install_command(settings);
+    prepare_build_step(settings, &mut install);
+    print_command(settings, &secrets, "install, when dependencies change", &install);
+
+    for index in 0..settings.instances.max(1) {
+        let Ok(port) = settings.instance_port(index) else {
+            continue;
+        };
+        if let Some(hook) = &settings.pre_start_hook {
+            let command = hook_command(settings, "pre_start_hook", hook, Some(port));
+            print_command(settings, &secrets, &format!("pre_start_hook of instance {}", index), &command);
+        }
+
+        let cgroup = child_cgroup(&settings.limits, config.max_ram_usage as u64, index);
+        let (command, cgroup) = instance_command(settings, &config.app_name, port, cgroup);
+        print_command(settings, &secrets, &format!("instance {} on port {}", index, port), &command);
+        if let Some(cgroup) = cgroup {
+            println!("    in cgroup {}", cgroup.path.display());
+        }
+    }
+
+    if let Some(hook) = &settings.post_stop_hook {
+        print_command(settings, &secrets, "post_stop_hook", &hook_command(settings, "post_stop_hook", hook, None));
+    }
+
+    if let Some(user) = &settings.run_as_user {
+        println!("  runs as {}{}", user, settings.run_as_group.as_ref().map(|group| format!(":{}", group)).unwrap_or_default());
+    }
+    println!();
+    true
+}
+
+fn print_command(settings: &AppSpecificConfig, secrets: &[String], label: &str, command: &Command) {
+    let command = command.as_std();
+    let line: Vec<String> = std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(quoted)
+        .collect();
+    println!("  {}: {}", label.yellow(), redacted(line.join(" "), secrets));
+
+    if let Some(directory) = command.get_current_dir() {
+        println!("    in {}", directory.display());
+    }
+    if !settings.inherit_env {
+        println!("    with an empty environment plus");
+    }
+
+    for (key, value) in command.get_envs() {
+        let key = key.to_string_lossy();
+        let value = match (secret_variable(settings, &key), value) {
+            (true, _) => String::from("<secret>"),
+            (false, Some(value)) => redacted(quoted(value), secrets),
+            (false, None) => String::from("<removed>"),
+        };
+        println!("    {}={}", key, value);
+    }
+}
+
+/// The resolved values of settings that held a secret reference or an
+/// encrypted value, blanked wherever they'd be printed
+pub fn secret_values(settings: &AppSpecificConfig) -> Vec<String> {
+    let Ok(document) = serde_json::to_value(settings) else {
+        return Vec::new();
+    };
+
+    settings
+        .secret_keys
+        .iter()
+        .filter_map(|key| {
+            // Keys look like `build_steps[0].run`
+            let mut value = &document;
+            for segment in key.split('.') {
+                let mut parts = segment.split('[');
+                value = value.get(parts.next()?)?;
+                for index in parts {
+                    value = value.get(index.trim_end_matches(']').parse::<usize>().ok()?)?;
+                }
+            }
+            value.as_str().filter(|secret| !secret.is_empty()).map(str::to_owned)
+        })
+        .collect()
+}
+
+pub fn redacted(text: String, secrets: &[String]) -> String {
+    secrets.iter().fold(text, |text, secret| text.replace(secret.as_str(), "<secret>"))
+}
+
+/// Variables filled from a secret reference are never printed
+fn secret_variable(settings: &AppSpecificConfig, key: &str) -> bool {
+    settings.secret_keys.contains(&format!("env.{}", key))
+}
+
+/// Quotes a word for the shell when it needs it
+fn quoted(word: &OsStr) -> String {
+    let word = word.to_string_lossy();
+    let plain = !word.is_empty()
+        && word.chars().all(|c| c.is_ascii_alphanumeric() || "-_./=:,@%+".contains(c));
+    match plain {
+        true => word.into_owned(),
+        false => format!("'{}'", word.replace('\'', "'\\''")),
+    }
+}
@@ -0,0 +1,75 @@
+use dusa_collection_utils::errors::ErrorArrayItem;
+use dusa_collection_utils::log;
+use dusa_collection_utils::log::LogLevel;
+use std::time::{Duration, Instant};
+
+use crate::config::AppSpecificConfig;
+
+/// Keeps `state.error_log` within `error_log_max_entries` and
+/// `error_log_max_age_seconds`. Entries carry no timestamp of their own, so
+/// they are aged from the first pass that saw them.
+pub struct ErrorLogRetention {
+    max_entries: usize,
+    max_age: Option<Duration>,
+    seen_at: Vec<Instant>, // Parallel to the entries seen on the last pass
+}
+
+impl ErrorLogRetention {
+    pub fn new(settings: &AppSpecificConfig) -> Self {
+        Self {
+            max_entries: settings.error_log_max_entries,
+            max_age: settings.error_log_max_age_seconds.map(Duration::from_secs),
+            seen_at: Vec::new(),
+        }
+    }
+
+    /// Picks up reloaded limits, entries keep their age
+    pub fn configure(&mut self, settings: &AppSpecificConfig) {
+        self.max_entries = settings.error_log_max_entries;
+        self.max_age = settings.error_log_max_age_seconds.map(Duration::from_secs);
+    }
+
+    /// Drops repeated entries first, keeping the latest of each, so a noisy
+    /// error can't push distinct ones out, then expired and the oldest
+    /// entries
+    pub fn apply(&mut self, entries: &mut Vec<ErrorArrayItem>) {
+        let now = Instant::now();
+
+        // Entries are only ever appended, a shorter log was cleared in the meantime
+        if entries.len() < self.seen_at.len() {
+            self.seen_at.clear();
+        }
+        self.seen_at.resize(entries.len(), now);
+
+        let before = entries.len();
+        let mut index = entries.len();
+        while index > 0 {
+            index -= 1;
+            if entries[index + 1..].contains(&entries[index]) {
+                entries.remove(index);
+                self.seen_at.remove(index);
+            }
+        }
+
+        if let Some(max_age) = self.max_age {
+            let mut index = 0;
+            while index < entries.len() {
+                if now.duration_since(self.seen_at[index]) > max_age {
+                    entries.remove(index);
+                    self.seen_at.remove(index);
+                } else {
+                    index += 1;
+                }
+            }
+        }
+
+        let excess = entries.len().saturating_sub(self.max_entries);
+        entries.drain(..excess);
+        self.seen_at.drain(..excess);
+
+        let dropped = before - entries.len();
+        if dropped > 0 {
+            log!(LogLevel::Trace, "Dropped {} error log entries, {} left", dropped, entries.len());
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use std::{collections::BTreeMap, sync::OnceLock};
+
+/// The config structs as written, so the sample Config and the schema are
+/// derived from the same fields, defaults and comments the runner reads
+const CONFIG_SOURCE: &str = include_str!("config.rs");
+
+/// A struct or enum of config.rs
+pub struct TypeInfo {
+    pub doc: Option<String>, // Its `///` comment
+    pub kind: TypeKind,
+}
+
+pub enum TypeKind {
+    Struct(Vec<FieldInfo>),
+    Enum(Vec<VariantInfo>),
+}
+
+pub struct FieldInfo {
+    pub name: String,
+    pub ty: FieldType,
+    pub comment: Option<String>, // The trailing `//` comment
+    pub required: bool,          // No serde default on the field or its struct
+    pub skip: bool,              // Never read from the file
+    pub aliases: Vec<String>,    // Former names still read
+}
+
+pub struct VariantInfo {
+    pub name: String, // As written in the file, after `rename_all`
+    pub comment: Option<String>,
+    pub default: bool,
+}
+
+pub enum FieldType {
+    Bool,
+    Integer { unsigned: bool },
+    Text,
+    Optional(Box<FieldType>),
+    List(Box<FieldType>),
+    Map(Box<FieldType>), // Keyed by strings
+    Named(String), // Another struct or enum of config.rs
+}
+
+impl FieldType {
+    fn parse(ty: &str) -> Self {
+        let ty = ty.trim();
+        if let Some(inner) = generic(ty, "Option") {
+            return FieldType::Optional(Box::new(FieldType::parse(inner)));
+        }
+        if let Some(inner) = generic(ty, "Vec") {
+            return FieldType::List(Box::new(FieldType::parse(inner)));
+        }
+        if let Some((_, value)) = generic(ty, "BTreeMap").or_else(|| generic(ty, "HashMap")).and_then(|inner| inner.split_once(',')) {
+            return FieldType::Map(Box::new(FieldType::parse(value)));
+        }
+
+        match ty {
+            "bool" => FieldType::Bool,
+            "String" | "PathBuf" => FieldType::Text,
+            "u8" | "u16" | "u32" | "u64" | "usize" => FieldType::Integer { unsigned: true },
+            "i8" | "i16" | "i32" | "i64" | "isize" => FieldType::Integer { unsigned: false },
+            _ => FieldType::Named(ty.to_owned()),
+        }
+    }
+
+    /// The type behind `Option`
+    pub fn inner(&self) -> &FieldType {
+        match self {
+            FieldType::Optional(inner) => inner.inner(),
+            ty => ty,
+        }
+    }
+}
+
+fn generic<'a>(ty: &'a str, name: &str) -> Option<&'a str> {
+    ty.strip_prefix(name)?.strip_prefix('<')?.strip_suffix('>')
+}
+
+/// Every struct and enum of config.rs by name, parsed once
+pub fn config_types() -> &'static BTreeMap<String, TypeInfo> {
+    static TYPES: OnceLock<BTreeMap<String, TypeInfo>> = OnceLock::new();
+    TYPES.get_or_init(|| parse(CONFIG_SOURCE))
+}
+
+/// The fields of a struct, empty for enums and unknown names
+pub fn struct_fields(name: &str) -> &'static [FieldInfo] {
+    match config_types().get(name) {
+        Some(TypeInfo { kind: TypeKind::Struct(fields), .. }) => fields,
+        _ => &[],
+    }
+}
+
+/// The variants of an enum, empty for structs and unknown names
+pub fn enum_variants(name: &str) -> &'static [VariantInfo] {
+    match config_types().get(name) {
+        Some(TypeInfo { kind: TypeKind::Enum(variants), .. }) => variants,
+        _ => &[],
+    }
+}
+
+fn parse(source: &str) -> BTreeMap<String, TypeInfo> {
+    let mut types = BTreeMap::new();
+    let mut doc: Vec<&str> = Vec::new();
+    let mut all_default = false;
+    let mut rename_all: Option<String> = None;
+    let mut lines = source.lines();
+
+    while let Some(line) = lines.next() {
+        let line = line.trim();
+
+        if let Some(text) = line.strip_prefix("///") {
+            doc.push(text.trim());
+            continue;
+        }
+        if line.starts_with("#[") {
+            all_default |= line == "#[serde(default)]";
+            if let Some(case) = attribute_value(line, "rename_all") {
+                rename_all = Some(case);
+            }
+            continue;
+        }
+
+        let header = line
+            .strip_prefix("pub struct ")
+            .map(|rest| (rest, true))
+            .or_else(|| line.strip_prefix("pub enum ").map(|rest| (rest, false)));
+        if let Some((rest, is_struct)) = header.filter(|(rest, _)| rest.ends_with('{')) {
+            let name = rest.trim_end_matches('{').trim().to_owned();
+            let body: Vec<&str> = lines.by_ref().map(str::trim).take_while(|line| *line != "}").collect();
+            let kind = match is_struct {
+                true => TypeKind::Struct(parse_fields(&body, all_default)),
+                false => TypeKind::Enum(parse_variants(&body, rename_all.as_deref())),
+            };
+            let doc = Some(doc.join(" ")).filter(|doc| !doc.is_empty());
+            types.insert(name, TypeInfo { doc, kind });
+        }
+
+        doc.clear();
+        all_default = false;
+        rename_all = None;
+    }
+
+    types
+}
+
+fn parse_fields(body: &[&str], all_default: bool) -> Vec<FieldInfo> {
+    let mut fields = Vec::new();
+    let mut has_default = false;
+    let mut skip = false;
+    let mut aliases = Vec::new();
+
+    for line in body {
+        if let Some(attribute) = line.strip_prefix("#[serde(") {
+            has_default |= attribute.starts_with("default");
+            skip |= attribute.starts_with("skip");
+            aliases.extend(attribute_value(attribute, "alias"));
+            continue;
+        }
+
+        let (code, comment) = split_comment(line);
+        let Some((name, ty)) = code.strip_prefix("pub ").and_then(|field| field.trim_end_matches(',').split_once(':')) else {
+            continue;
+        };
+
+        fields.push(FieldInfo {
+            name: name.trim().to_owned(),
+            ty: FieldType::parse(ty),
+            comment,
+            required: !all_default && !has_default && !skip,
+            skip,
+            aliases: std::mem::take(&mut aliases),
+        });
+        has_default = false;
+        skip = false;
+    }
+
+    fields
+}
+
+fn parse_variants(body: &[&str], rename_all: Option<&str>) -> Vec<VariantInfo> {
+    let mut variants = Vec::new();
+    let mut default = false;
+
+    for line in body {
+        if *line == "#[default]" {
+            default = true;
+            continue;
+        }
+
+        let (code, comment) = split_comment(line);
+        let name = code.trim_end_matches(',').trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric()) {
+            continue;
+        }
+
+        variants.push(VariantInfo { name: renamed(name, rename_all), comment, default });
+        default = false;
+    }
+
+    variants
+}
+
+fn split_comment(line: &str) -> (&str, Option<String>) {
+    match line.split_once("//") {
+        Some((code, comment)) => (code.trim(), Some(comment.trim().to_owned()).filter(|comment| !comment.is_empty())),
+        None => (line.trim(), None),
+    }
+}
+
+/// `value` of `#[serde(<key> = "value")]`
+fn attribute_value(line: &str, key: &str) -> Option<String> {
+    let rest = line.split_once(key)?.1.trim_start().strip_prefix('=')?.trim_start().strip_prefix('"')?;
+    rest.split_once('"').map(|(value, _)| value.to_owned())
+}
+
+/// The variant name serde reads, for the `rename_all` cases config.rs uses
+fn renamed(name: &str, rename_all: Option<&str>) -> String {
+    match rename_all {
+        Some("lowercase") => name.to_lowercase(),
+        Some("UPPERCASE") => name.to_uppercase(),
+        Some("kebab-case") => {
+            let mut renamed = String::new();
+            for (index, c) in name.chars().enumerate() {
+                if c.is_ascii_uppercase() && index > 0 {
+                    renamed.push('-');
+                }
+                renamed.push(c.to_ascii_lowercase());
+            }
+            renamed
+        }
+        _ => name.to_owned(),
+    }
+}
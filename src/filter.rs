@@ -0,0 +1,203 @@
+use dusa_collection_utils::log;
+use dusa_collection_utils::log::LogLevel;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::{gitignore::Gitignore, WalkBuilder};
+use notify::event::{CreateKind, EventKind, ModifyKind, RenameMode};
+use regex::Regex;
+use std::path::{Path, PathBuf};
+
+use crate::config::{AppSpecificConfig, EventKindFilter};
+
+/// Decides which watcher events are noise. Built once from the settings and
+/// moved into the monitor thread.
+pub struct WatchFilter {
+    root: PathBuf,
+    ignored_dirs: Vec<PathBuf>,
+    ignored_globs: GlobSet,
+    include: Option<Regex>,
+    exclude: Option<Regex>,
+    gitignores: Vec<Gitignore>,
+    event_kinds: Vec<EventKindFilter>,
+    new_files_only: bool,
+    deploy_marker: Option<PathBuf>,
+}
+
+impl WatchFilter {
+    /// `ignored_subdirs` entries containing glob syntax (`*`, `?`, `[`, `{`)
+    /// are matched against the path relative to `root`, everything else is
+    /// treated as a literal subdirectory like before.
+    pub fn new(root: &Path, ignored_subdirs: &[String], settings: &AppSpecificConfig) -> Self {
+        let mut ignored_dirs = Vec::new();
+        let mut globs = GlobSetBuilder::new();
+
+        for entry in ignored_subdirs {
+            if !is_glob(entry) {
+                ignored_dirs.push(root.join(entry));
+                continue;
+            }
+
+            match GlobBuilder::new(entry).literal_separator(true).build() {
+                Ok(glob) => {
+                    globs.add(glob);
+                }
+                Err(err) => log!(LogLevel::Error, "Ignoring invalid glob {}: {}", entry, err),
+            }
+        }
+
+        let ignored_globs = match globs.build() {
+            Ok(set) => set,
+            Err(err) => {
+                log!(LogLevel::Error, "Failed to compile ignore globs: {}", err);
+                GlobSet::empty()
+            }
+        };
+
+        Self {
+            root: root.to_path_buf(),
+            ignored_dirs,
+            ignored_globs,
+            include: compile_regex("include_regex", settings.include_regex.as_deref()),
+            exclude: compile_regex("exclude_regex", settings.exclude_regex.as_deref()),
+            gitignores: match settings.respect_gitignore {
+                true => load_gitignores(root),
+                false => Vec::new(),
+            },
+            event_kinds: settings.event_kinds.clone(),
+            new_files_only: settings.new_files_only,
+            deploy_marker: settings.deploy_marker.as_ref().map(|marker| root.join(marker)),
+        }
+    }
+
+    pub fn ignores(&self, path: &Path) -> bool {
+        if self.ignores_tree(path) {
+            return true;
+        }
+
+        let relative = path.strip_prefix(&self.root).unwrap_or(path).to_string_lossy();
+        if let Some(exclude) = &self.exclude {
+            if exclude.is_match(&relative) {
+                return true;
+            }
+        }
+
+        match &self.include {
+            Some(include) => !include.is_match(&relative),
+            None => false,
+        }
+    }
+
+    /// The ignores that cover whole directories, i.e. everything but the
+    /// regex filters. Used to prune walks over the watched tree.
+    pub fn ignores_tree(&self, path: &Path) -> bool {
+        if self.ignored_dirs.iter().any(|ignored| path.starts_with(ignored)) {
+            return true;
+        }
+
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        if self.ignored_globs.is_match(relative) {
+            return true;
+        }
+
+        self.gitignored(path)
+    }
+
+    /// Whether events of this kind count at all. Kinds notify can't tell
+    /// apart (`Any`, `Other`) always count, except in `new_files_only` mode
+    /// where only files created or moved into the tree do.
+    pub fn accepts_kind(&self, kind: &EventKind) -> bool {
+        if self.new_files_only {
+            return matches!(
+                kind,
+                EventKind::Create(CreateKind::File | CreateKind::Any) | EventKind::Modify(ModifyKind::Name(RenameMode::To))
+            );
+        }
+
+        let filter = match kind {
+            EventKind::Create(_) => EventKindFilter::Create,
+            EventKind::Modify(ModifyKind::Metadata(_)) => EventKindFilter::Metadata,
+            EventKind::Modify(ModifyKind::Name(_)) => EventKindFilter::Rename,
+            EventKind::Modify(_) => EventKindFilter::Modify,
+            EventKind::Remove(_) => EventKindFilter::Remove,
+            EventKind::Access(_) => EventKindFilter::Access,
+            EventKind::Any | EventKind::Other => return true,
+        };
+
+        self.event_kinds.contains(&filter)
+    }
+
+    /// Whether `path` is the `deploy_marker` below this root
+    pub fn is_deploy_marker(&self, path: &Path) -> bool {
+        self.deploy_marker.as_deref() == Some(path)
+    }
+
+    /// The deepest `.gitignore` with an opinion on `path` decides, like git does
+    fn gitignored(&self, path: &Path) -> bool {
+        if self.gitignores.is_empty() {
+            return false;
+        }
+
+        if path.components().any(|component| component.as_os_str() == ".git") {
+            return true;
+        }
+
+        let is_dir = path.is_dir();
+        for gitignore in &self.gitignores {
+            if !path.starts_with(gitignore.path()) {
+                continue;
+            }
+
+            let matched = gitignore.matched_path_or_any_parents(path, is_dir);
+            if matched.is_ignore() {
+                return true;
+            }
+            if matched.is_whitelist() {
+                return false;
+            }
+        }
+
+        false
+    }
+}
+
+/// Collects every `.gitignore` below `root`, deepest first. Directories
+/// already ignored are not descended into, so `node_modules` stays cheap.
+fn load_gitignores(root: &Path) -> Vec<Gitignore> {
+    let mut gitignores = Vec::new();
+
+    let walker = WalkBuilder::new(root)
+        .hidden(false)
+        .require_git(false)
+        .filter_entry(|entry| entry.file_name() != ".git")
+        .build();
+
+    for entry in walker.flatten() {
+        if entry.file_name() != ".gitignore" {
+            continue;
+        }
+
+        let (gitignore, err) = Gitignore::new(entry.path());
+        if let Some(err) = err {
+            log!(LogLevel::Warn, "Problem reading {}: {}", entry.path().display(), err);
+        }
+        gitignores.push(gitignore);
+    }
+
+    gitignores.sort_by_key(|gitignore| std::cmp::Reverse(gitignore.path().components().count()));
+    log!(LogLevel::Debug, "Loaded {} .gitignore files below {}", gitignores.len(), root.display());
+    gitignores
+}
+
+fn compile_regex(setting: &str, pattern: Option<&str>) -> Option<Regex> {
+    let pattern = pattern?;
+    match Regex::new(pattern) {
+        Ok(regex) => Some(regex),
+        Err(err) => {
+            log!(LogLevel::Error, "Ignoring invalid {} {}: {}", setting, pattern, err);
+            None
+        }
+    }
+}
+
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '[', '{'])
+}
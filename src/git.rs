@@ -0,0 +1,113 @@
+use dusa_collection_utils::log;
+use dusa_collection_utils::log::LogLevel;
+use dusa_collection_utils::types::PathType;
+use notify::event::{EventKind, ModifyKind};
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+    sync::mpsc::channel,
+    thread,
+    time::Duration,
+};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::monitor::{WatchAlerts, WatchEvent};
+
+/// Quiet period before HEAD is resolved, a pull rewrites several refs in a row
+const REF_SETTLE: Duration = Duration::from_secs(1);
+
+/// Watches the repository `root` belongs to and sends one deploy event
+/// whenever the commit HEAD points at changes, e.g. after a pull or a
+/// branch switch. Fetches and other ref updates that leave HEAD alone
+/// don't trigger anything.
+pub fn start_git_watch(root: PathType, event_tx: UnboundedSender<WatchEvent>, alerts: WatchAlerts) {
+    let git_dir = match git(&root, &["rev-parse", "--absolute-git-dir"]) {
+        Ok(git_dir) => PathBuf::from(git_dir),
+        Err(err) => {
+            alerts.report(format!("git_head_trigger is set but {} isn't a git checkout: {}", root, err));
+            return;
+        }
+    };
+
+    let (tx, rx) = channel();
+    let mut watcher = match RecommendedWatcher::new(tx, notify::Config::default()) {
+        Ok(watcher) => watcher,
+        Err(err) => {
+            alerts.report(format!("Couldn't watch {}: {}", git_dir.display(), err));
+            return;
+        }
+    };
+
+    // HEAD and packed-refs live in the git dir itself, loose refs below refs/
+    let watched = watcher
+        .watch(&git_dir, RecursiveMode::NonRecursive)
+        .and_then(|_| watcher.watch(&git_dir.join("refs"), RecursiveMode::Recursive));
+    if let Err(err) = watched {
+        alerts.report(format!("Couldn't watch {}: {}", git_dir.display(), err));
+        return;
+    }
+
+    let mut head = git(&root, &["rev-parse", "HEAD"]).ok();
+    log!(LogLevel::Info, "Watching {} for HEAD changes, at {}", git_dir.display(), head.as_deref().unwrap_or("no commit"));
+
+    thread::spawn(move || {
+        // The watcher stops once dropped
+        let _watcher = watcher;
+
+        while let Ok(event) = rx.recv() {
+            if !matches!(event, Ok(Event { kind: EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_), .. })) {
+                continue;
+            }
+
+            while rx.recv_timeout(REF_SETTLE).is_ok() {}
+
+            let current = match git(&root, &["rev-parse", "HEAD"]) {
+                Ok(current) => Some(current),
+                Err(err) => {
+                    log!(LogLevel::Debug, "Couldn't resolve HEAD: {}", err);
+                    continue;
+                }
+            };
+            if current == head {
+                continue;
+            }
+
+            log!(
+                LogLevel::Info,
+                "HEAD moved from {} to {}",
+                head.as_deref().unwrap_or("no commit"),
+                current.as_deref().unwrap_or_default()
+            );
+            head = current;
+
+            let mut event = Event::new(EventKind::Modify(ModifyKind::Any));
+            event.paths = vec![git_dir.join("HEAD")];
+            let event = WatchEvent {
+                root: root.clone(),
+                event,
+                bulk: false,
+                deploy: true,
+            };
+            if event_tx.send(event).is_err() {
+                log!(LogLevel::Debug, "Event receiver dropped, stopping the git watch");
+                break;
+            }
+        }
+    });
+}
+
+/// Runs git in `root` and returns its trimmed stdout
+fn git(root: &Path, args: &[&str]) -> Result<String, String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(root)
+        .args(args)
+        .output()
+        .map_err(|err| err.to_string())?;
+
+    match output.status.success() {
+        true => Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned()),
+        false => Err(String::from_utf8_lossy(&output.stderr).trim().to_owned()),
+    }
+}
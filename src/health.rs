@@ -0,0 +1,122 @@
+use dusa_collection_utils::log;
+use dusa_collection_utils::log::LogLevel;
+use std::{net::TcpListener, time::Duration};
+use tokio::{
+    net::TcpStream,
+    time::{sleep, timeout, Instant},
+};
+
+use crate::{child::ChildHandle, config::HealthCheckConfig};
+
+const PROBE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long a stopped child may take to release its port
+const PORT_RELEASE_WAIT: Duration = Duration::from_secs(2);
+
+/// Whether nothing listens on `port`. Binding every interface also collides
+/// with listeners on a single address or on IPv6.
+pub fn port_free(port: u16) -> bool {
+    TcpListener::bind(("0.0.0.0", port)).is_ok()
+}
+
+/// Gives the previous child a moment to release `port`, false when it stays taken
+pub async fn wait_for_free_port(port: u16) -> bool {
+    let deadline = Instant::now() + PORT_RELEASE_WAIT;
+
+    while !port_free(port) {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        sleep(Duration::from_millis(250)).await;
+    }
+
+    true
+}
+
+/// Polls until something accepts connections on `host:port`. Gives up when the
+/// timeout passes or the child exits in the meantime.
+pub async fn wait_for_port(child: &ChildHandle, host: &str, port: u16, limit: Duration) -> bool {
+    let address = format!("{}:{}", host, port);
+    let deadline = Instant::now() + limit;
+
+    log!(LogLevel::Trace, "Waiting for {} to accept connections", address);
+
+    while Instant::now() < deadline {
+        if let Ok(Ok(_)) = timeout(PROBE_INTERVAL, TcpStream::connect(&address)).await {
+            return true;
+        }
+
+        if !child.running().await {
+            log!(LogLevel::Warn, "Child exited before {} accepted connections", address);
+            return false;
+        }
+
+        sleep(PROBE_INTERVAL).await;
+    }
+
+    false
+}
+
+/// Periodic HTTP check against the child, counting consecutive failures
+pub struct HealthChecker {
+    client: reqwest::Client,
+    url: String,
+    unhealthy_threshold: u32,
+    failures: u32,
+}
+
+impl HealthChecker {
+    /// None when no health check url is configured. A `{port}` placeholder in
+    /// the url is replaced with the port of the instance being checked.
+    pub fn new(config: &HealthCheckConfig, port: u16) -> Option<Self> {
+        let url = config.url.as_ref()?.replace("{port}", &port.to_string());
+
+        let client = match reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_seconds))
+            .build()
+        {
+            Ok(client) => client,
+            Err(err) => {
+                log!(LogLevel::Error, "Couldn't build the health check client: {}", err);
+                return None;
+            }
+        };
+
+        Some(Self {
+            client,
+            url,
+            unhealthy_threshold: config.unhealthy_threshold.max(1),
+            failures: 0,
+        })
+    }
+
+    /// Runs one check, returns true once the unhealthy threshold has been reached
+    pub async fn check(&mut self) -> bool {
+        match self.client.get(&self.url).send().await {
+            Ok(response) if response.status().is_success() => {
+                if self.failures > 0 {
+                    log!(LogLevel::Info, "Health check on {} recovered", self.url);
+                }
+                self.failures = 0;
+            }
+            Ok(response) => {
+                self.failures += 1;
+                log!(LogLevel::Warn, "Health check on {} returned {} ({} of {})", self.url, response.status(), self.failures, self.unhealthy_threshold);
+            }
+            Err(err) => {
+                self.failures += 1;
+                log!(LogLevel::Warn, "Health check on {} failed: {} ({} of {})", self.url, err, self.failures, self.unhealthy_threshold);
+            }
+        }
+
+        self.failures >= self.unhealthy_threshold
+    }
+
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    pub fn reset(&mut self) {
+        self.failures = 0;
+    }
+}
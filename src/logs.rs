@@ -0,0 +1,617 @@
+use dusa_collection_utils::log;
+use dusa_collection_utils::log::LogLevel;
+use nix::unistd::{dup2, pipe};
+use serde_json::json;
+use std::{
+    cell::Cell,
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    os::{
+        fd::{AsFd, AsRawFd, OwnedFd},
+        unix::net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::{Arc, Mutex, OnceLock},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::process::Command;
+
+use crate::{
+    cleanup::register_artifact,
+    config::{AppSpecificConfig, BuildLogConfig, ChildLogConfig, LogDestination, LogFormat, LogLevelName, LogRoute},
+};
+
+/// Clients attached to one tail socket, shared by the forwarders of its
+/// application's children
+type TailClients = Arc<Mutex<Vec<UnixStream>>>;
+
+/// The clients of every tail socket, keyed by its path
+static TAIL_SOCKETS: Mutex<BTreeMap<PathBuf, TailClients>> = Mutex::new(BTreeMap::new());
+
+/// A log file shared by everything writing to its path, so instances of one
+/// application append to the same file with one size count and rotation
+type SharedFile = Arc<Mutex<RotatingFile>>;
+
+/// Every log file opened so far, keyed by its path
+static LOG_FILES: Mutex<BTreeMap<PathBuf, SharedFile>> = Mutex::new(BTreeMap::new());
+
+/// The runner's stdout from before `route_runner_logs` took it over
+static ORIGINAL_STDOUT: OnceLock<OwnedFd> = OnceLock::new();
+
+/// Stream socket of the journal, as used by `systemd-cat`
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/stdout";
+
+thread_local! {
+    /// Set on the thread forwarding the runner's own logs, anything it logged
+    /// would come straight back to it
+    static RUNNER_FORWARDER: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Routes the child's stdout and stderr through pipes into rotating files
+/// under the configured directory and to any attached tail clients. Returns
+/// false when capture is disabled or couldn't be set up, in which case the
+/// command is left untouched.
+pub fn capture_child_output(command: &mut Command, settings: &AppSpecificConfig, app_name: &str) -> bool {
+    let config = &settings.child_logs;
+    let route = &settings.logging.child;
+    if config.directory.is_none() && config.tail_socket.is_none() && route.destination.is_none() {
+        return false;
+    }
+
+    let directory: Option<PathBuf> = config.directory.as_ref().map(PathBuf::from);
+    if let Some(directory) = &directory {
+        if let Err(err) = fs::create_dir_all(directory) {
+            log!(LogLevel::Warn, "Couldn't create child log directory {}: {}", directory.display(), err);
+            return false;
+        }
+    }
+
+    let (stdout_read, stdout_write) = match pipe() {
+        Ok(ends) => ends,
+        Err(err) => {
+            log!(LogLevel::Warn, "Couldn't create stdout pipe for the child: {}", err);
+            return false;
+        }
+    };
+    let (stderr_read, stderr_write) = match pipe() {
+        Ok(ends) => ends,
+        Err(err) => {
+            log!(LogLevel::Warn, "Couldn't create stderr pipe for the child: {}", err);
+            return false;
+        }
+    };
+
+    command
+        .stdout(Stdio::from(stdout_write))
+        .stderr(Stdio::from(stderr_write));
+
+    // Both streams share one destination, so their lines stay whole
+    let routed = route.destination.and_then(|destination| {
+        let sink = LogSink::open(destination, route, &format!("{}-child", app_name))?;
+        Some(RoutedOutput {
+            sink: Arc::new(Mutex::new(sink)),
+            format: route.format,
+            level: route.level.unwrap_or(LogLevelName::Trace),
+            app_name: app_name.to_owned(),
+        })
+    });
+
+    let tail = config.tail_socket.as_ref().and_then(|socket| tail_clients(Path::new(socket)));
+    let streams = [("stdout", File::from(stdout_read)), ("stderr", File::from(stderr_read))];
+    for (stream, reader) in streams {
+        let target = directory
+            .as_ref()
+            .map(|directory| shared_file(directory.join(format!("{}.{}.log", app_name, stream)), Rotation::from(config)));
+        spawn_forwarder(stream, reader, target, routed.clone(), tail.clone());
+    }
+
+    true
+}
+
+/// The child's output on its way to `logging.child`
+#[derive(Clone)]
+struct RoutedOutput {
+    sink: Arc<Mutex<LogSink>>,
+    format: LogFormat,
+    level: LogLevelName, // Lines below it are dropped
+    app_name: String,
+}
+
+impl RoutedOutput {
+    /// stdout counts as info and stderr as warn
+    fn write(&self, stream: &str, line: &str) {
+        let level = match stream {
+            "stderr" => LogLevelName::Warn,
+            _ => LogLevelName::Info,
+        };
+        if level > self.level {
+            return;
+        }
+
+        if let Ok(mut sink) = self.sink.lock() {
+            sink.write(level, &format_line(self.format, &self.app_name, stream, level, line));
+        }
+    }
+}
+
+/// Copies lines from the pipe into the log file, the `logging.child`
+/// destination and the clients of the application's tail socket until the
+/// child closes its end
+fn spawn_forwarder(stream: &'static str, reader: File, target: Option<SharedFile>, routed: Option<RoutedOutput>, tail: Option<TailClients>) {
+    thread::spawn(move || {
+        log!(LogLevel::Trace, "Child {} forwarder started", stream);
+
+        for line in BufReader::new(reader).lines() {
+            match line {
+                Ok(line) => {
+                    if let Some(Ok(mut target)) = target.as_ref().map(|target| target.lock()) {
+                        target.write_line(&line);
+                    }
+                    if let Some(routed) = &routed {
+                        routed.write(stream, &line);
+                    }
+                    if let Some(tail) = &tail {
+                        broadcast_line(tail, stream, &line);
+                    }
+                }
+                Err(err) => {
+                    log!(LogLevel::Warn, "Failed reading child {}: {}", stream, err);
+                    break;
+                }
+            }
+        }
+
+        log!(LogLevel::Trace, "Child {} forwarder exiting", stream);
+    });
+}
+
+/// Sends the runner's own stdout and stderr, and every log line with them,
+/// to `logging.runner` for the rest of the process. Does nothing when the
+/// route leaves them as they are.
+pub fn route_runner_logs(settings: &AppSpecificConfig, app_name: &str) {
+    let route = &settings.logging.runner;
+    if route.destination.is_none() && route.format == LogFormat::Text {
+        return;
+    }
+
+    let destination = route.destination.unwrap_or(LogDestination::Stdout);
+    let Some(mut sink) = LogSink::open(destination, route, app_name) else {
+        return;
+    };
+
+    let (read_end, write_end) = match pipe() {
+        Ok(ends) => ends,
+        Err(err) => {
+            log!(LogLevel::Warn, "Couldn't create the pipe for routing the runner's logs: {}", err);
+            return;
+        }
+    };
+
+    if let Ok(original) = io::stdout().as_fd().try_clone_to_owned() {
+        let _ = ORIGINAL_STDOUT.set(original);
+    }
+    log!(LogLevel::Info, "Sending the runner's logs to {}", describe_route(destination, route));
+    let _ = io::stdout().flush();
+
+    for target in [io::stdout().as_raw_fd(), io::stderr().as_raw_fd()] {
+        if let Err(err) = dup2(write_end.as_raw_fd(), target) {
+            log!(LogLevel::Warn, "Couldn't route the runner's logs: {}", err);
+            return;
+        }
+    }
+    drop(write_end);
+
+    // Colors only belong on a terminal
+    let plain = destination != LogDestination::Stdout || route.format == LogFormat::Json;
+    let format = route.format;
+    let app_name = app_name.to_owned();
+    thread::spawn(move || {
+        RUNNER_FORWARDER.with(|forwarder| forwarder.set(true));
+        for line in BufReader::new(File::from(read_end)).lines().map_while(Result::ok) {
+            let line = match plain {
+                true => strip_ansi(&line),
+                false => line,
+            };
+            let level = line_level(&line);
+            sink.write(level, &format_line(format, &app_name, "runner", level, &line));
+        }
+    });
+}
+
+fn describe_route(destination: LogDestination, route: &LogRoute) -> String {
+    match destination {
+        LogDestination::Stdout => String::from("stdout"),
+        LogDestination::Journald => String::from("the journal"),
+        LogDestination::File => route.file.clone().unwrap_or_default(),
+    }
+}
+
+/// Where a routed stream's lines end up
+enum LogSink {
+    Stdout(File),
+    Journald(UnixStream),
+    File(SharedFile),
+}
+
+impl LogSink {
+    /// `identifier` names the lines in the journal. None when the
+    /// destination can't be opened, the stream then stays where it was.
+    fn open(destination: LogDestination, route: &LogRoute, identifier: &str) -> Option<Self> {
+        match destination {
+            LogDestination::Stdout => {
+                let original = match ORIGINAL_STDOUT.get() {
+                    Some(original) => original.try_clone(),
+                    None => io::stdout().as_fd().try_clone_to_owned(),
+                };
+                match original {
+                    Ok(original) => Some(LogSink::Stdout(File::from(original))),
+                    Err(err) => {
+                        log!(LogLevel::Warn, "Couldn't reach stdout for {}: {}", identifier, err);
+                        None
+                    }
+                }
+            }
+            LogDestination::Journald => match journal_stream(identifier) {
+                Ok(stream) => Some(LogSink::Journald(stream)),
+                Err(err) => {
+                    log!(LogLevel::Warn, "Couldn't connect to the journal at {} for {}: {}", JOURNAL_SOCKET, identifier, err);
+                    None
+                }
+            },
+            LogDestination::File => {
+                let path = PathBuf::from(route.file.as_ref()?);
+                if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+                    if let Err(err) = fs::create_dir_all(parent) {
+                        log!(LogLevel::Warn, "Couldn't create log directory {}: {}", parent.display(), err);
+                        return None;
+                    }
+                }
+                Some(LogSink::File(shared_file(path, Rotation::from(route))))
+            }
+        }
+    }
+
+    /// Write errors are dropped, there's nowhere left to report them
+    fn write(&mut self, level: LogLevelName, line: &str) {
+        match self {
+            LogSink::Stdout(stdout) => {
+                let _ = writeln!(stdout, "{}", line);
+            }
+            LogSink::Journald(stream) => {
+                let _ = writeln!(stream, "<{}>{}", level.priority(), line);
+            }
+            LogSink::File(file) => {
+                if let Ok(mut file) = file.lock() {
+                    file.write_line(line);
+                }
+            }
+        }
+    }
+}
+
+/// A connection to the journal's stream socket, every line written to it is
+/// one entry whose `<priority>` prefix sets its level
+fn journal_stream(identifier: &str) -> io::Result<UnixStream> {
+    let mut stream = UnixStream::connect(JOURNAL_SOCKET)?;
+    // Identifier, unit, default priority, level prefix, forwarding to syslog, kmsg and console
+    write!(stream, "{}\n\n6\n1\n0\n0\n0\n", identifier)?;
+    Ok(stream)
+}
+
+fn format_line(format: LogFormat, app_name: &str, source: &str, level: LogLevelName, line: &str) -> String {
+    match format {
+        LogFormat::Text => line.to_owned(),
+        LogFormat::Json => {
+            let millis = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_millis() as u64)
+                .unwrap_or(0);
+            json!({ "time": millis, "app": app_name, "source": source, "level": level.as_str(), "message": line }).to_string()
+        }
+    }
+}
+
+/// The level tag of one of the runner's log lines, info without one
+fn line_level(line: &str) -> LogLevelName {
+    let tags = [
+        ("ERROR", LogLevelName::Error),
+        ("WARN", LogLevelName::Warn),
+        ("DEBUG", LogLevelName::Debug),
+        ("TRACE", LogLevelName::Trace),
+    ];
+    let head: String = line.chars().take(48).collect();
+    tags.into_iter()
+        .find(|(tag, _)| head.contains(tag))
+        .map_or(LogLevelName::Info, |(_, level)| level)
+}
+
+/// Drops terminal color sequences such as `\x1b[31m`
+fn strip_ansi(line: &str) -> String {
+    let mut plain = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            plain.push(c);
+            continue;
+        }
+        if chars.next() == Some('[') {
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        }
+    }
+    plain
+}
+
+/// Problems of the log files themselves. On the runner's forwarder they go
+/// to the original stdout, logging them would feed them back in.
+fn report(message: String) {
+    if !RUNNER_FORWARDER.with(Cell::get) {
+        log!(LogLevel::Warn, "{}", message);
+        return;
+    }
+
+    if let Some(Ok(original)) = ORIGINAL_STDOUT.get().map(OwnedFd::try_clone) {
+        let _ = writeln!(File::from(original), "{}", message);
+    }
+}
+
+/// Listens on the configured unix socket and attaches every connection as a
+/// tail client of the application's children, e.g.
+/// `socat - UNIX-CONNECT:<tail_socket>`.
+pub fn start_tail_socket(config: &ChildLogConfig) {
+    let path = match &config.tail_socket {
+        Some(path) => PathBuf::from(path),
+        None => return,
+    };
+
+    // A stale socket from a previous run would make the bind fail
+    if path.exists() {
+        let _ = fs::remove_file(&path);
+    }
+
+    let listener = match UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        Err(err) => {
+            log!(LogLevel::Warn, "Couldn't bind tail socket {}: {}", path.display(), err);
+            return;
+        }
+    };
+
+    register_artifact(&path);
+    log!(LogLevel::Info, "Child output available on {}", path.display());
+
+    let clients = TailClients::default();
+    if let Ok(mut sockets) = TAIL_SOCKETS.lock() {
+        sockets.insert(path.clone(), clients.clone());
+    }
+
+    thread::spawn(move || {
+        for connection in listener.incoming() {
+            match connection {
+                Ok(client) => {
+                    // A stalled client must never block the child's pipes
+                    if let Err(err) = client.set_write_timeout(Some(Duration::from_millis(100))) {
+                        log!(LogLevel::Warn, "Couldn't configure tail client: {}", err);
+                        continue;
+                    }
+                    log!(LogLevel::Debug, "Tail client attached");
+                    if let Ok(mut clients) = clients.lock() {
+                        clients.push(client);
+                    }
+                }
+                Err(err) => log!(LogLevel::Warn, "Tail socket accept failed: {}", err),
+            }
+        }
+    });
+}
+
+/// The clients of the tail socket at `path`, None when it isn't listening
+fn tail_clients(path: &Path) -> Option<TailClients> {
+    TAIL_SOCKETS.lock().ok()?.get(path).cloned()
+}
+
+/// Sends the line to every client of one tail socket, dropping the ones
+/// that went away
+fn broadcast_line(clients: &TailClients, stream: &str, line: &str) {
+    if let Ok(mut clients) = clients.lock() {
+        if clients.is_empty() {
+            return;
+        }
+
+        let message = format!("[{}] {}\n", stream, line);
+        clients.retain_mut(|client| client.write_all(message.as_bytes()).is_ok());
+    }
+}
+
+/// A log file that rotates to `<name>.1`, `<name>.2`, ... once it grows past
+/// `max_size_mb` or gets older than `max_age_hours`, keeping `retain` old files.
+struct RotatingFile {
+    path: PathBuf,
+    file: Option<File>,
+    size: u64,
+    opened_at: Instant,
+    max_size: u64,
+    max_age: Duration,
+    retain: usize,
+}
+
+/// When a log file rotates and how many old files it keeps, set by
+/// `child_logs` for its files and by each `logging` route for its own
+#[derive(Clone, Copy)]
+struct Rotation {
+    max_size_mb: u64,
+    max_age_hours: u64,
+    retain: usize,
+}
+
+impl From<&ChildLogConfig> for Rotation {
+    fn from(config: &ChildLogConfig) -> Self {
+        Self { max_size_mb: config.max_size_mb, max_age_hours: config.max_age_hours, retain: config.retain }
+    }
+}
+
+impl From<&LogRoute> for Rotation {
+    fn from(route: &LogRoute) -> Self {
+        Self { max_size_mb: route.max_size_mb, max_age_hours: route.max_age_hours, retain: route.retain }
+    }
+}
+
+/// The log file at `path`, opened once and shared from then on. The latest
+/// rotation limits apply, a reload can change them.
+fn shared_file(path: PathBuf, rotation: Rotation) -> SharedFile {
+    let mut files = LOG_FILES.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let file = files
+        .entry(path.clone())
+        .or_insert_with(|| Arc::new(Mutex::new(RotatingFile::new(path, rotation))))
+        .clone();
+
+    if let Ok(mut file) = file.lock() {
+        file.configure(rotation);
+    }
+    file
+}
+
+impl RotatingFile {
+    fn new(path: PathBuf, rotation: Rotation) -> Self {
+        let mut rotating = Self {
+            path,
+            file: None,
+            size: 0,
+            opened_at: Instant::now(),
+            max_size: 0,
+            max_age: Duration::ZERO,
+            retain: 0,
+        };
+        rotating.configure(rotation);
+        rotating.open();
+        rotating
+    }
+
+    fn configure(&mut self, rotation: Rotation) {
+        self.max_size = rotation.max_size_mb * 1024 * 1024;
+        self.max_age = Duration::from_secs(rotation.max_age_hours * 60 * 60);
+        self.retain = rotation.retain;
+    }
+
+    fn open(&mut self) {
+        match OpenOptions::new().create(true).append(true).open(&self.path) {
+            Ok(file) => {
+                self.size = file.metadata().map(|meta| meta.len()).unwrap_or(0);
+                self.file = Some(file);
+            }
+            Err(err) => {
+                report(format!("Couldn't open log file {}: {}", self.path.display(), err));
+                self.file = None;
+            }
+        }
+        self.opened_at = Instant::now();
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if self.size >= self.max_size || self.opened_at.elapsed() >= self.max_age {
+            self.rotate();
+        }
+
+        if let Some(file) = &mut self.file {
+            match writeln!(file, "{}", line) {
+                Ok(_) => self.size += line.len() as u64 + 1,
+                Err(err) => report(format!("Failed writing log file {}: {}", self.path.display(), err)),
+            }
+        }
+    }
+
+    fn rotate(&mut self) {
+        self.file = None;
+
+        if self.retain == 0 {
+            let _ = fs::remove_file(&self.path);
+        } else {
+            let _ = fs::remove_file(rotated_path(&self.path, self.retain));
+            for index in (1..self.retain).rev() {
+                let _ = fs::rename(rotated_path(&self.path, index), rotated_path(&self.path, index + 1));
+            }
+            if let Err(err) = fs::rename(&self.path, rotated_path(&self.path, 1)) {
+                report(format!("Failed rotating log file {}: {}", self.path.display(), err));
+            }
+        }
+
+        if !RUNNER_FORWARDER.with(Cell::get) {
+            log!(LogLevel::Debug, "Rotated log file {}", self.path.display());
+        }
+        self.open();
+    }
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+/// Writes the full output of a one shot build to `<directory>/build-<unix millis>.log`
+/// and prunes the oldest files beyond `retain`. Returns the path written to.
+pub fn write_build_log(config: &BuildLogConfig, success: bool, stdout: &str, stderr: &str) -> Option<PathBuf> {
+    let directory = PathBuf::from(config.directory.as_ref()?);
+    if let Err(err) = fs::create_dir_all(&directory) {
+        log!(LogLevel::Warn, "Couldn't create build log directory {}: {}", directory.display(), err);
+        return None;
+    }
+
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis())
+        .unwrap_or(0);
+    let path = directory.join(format!("build-{}.log", millis));
+
+    let contents = format!(
+        "result: {}\n\n--- stdout ---\n{}\n--- stderr ---\n{}\n",
+        if success { "success" } else { "failed" },
+        stdout,
+        stderr
+    );
+
+    if let Err(err) = fs::write(&path, contents) {
+        log!(LogLevel::Warn, "Couldn't write build log {}: {}", path.display(), err);
+        return None;
+    }
+
+    prune_build_logs(&directory, config.retain);
+    Some(path)
+}
+
+fn prune_build_logs(directory: &Path, retain: usize) {
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(err) => {
+            log!(LogLevel::Warn, "Couldn't list build logs in {}: {}", directory.display(), err);
+            return;
+        }
+    };
+
+    let mut logs: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .map(|name| name.starts_with("build-") && name.ends_with(".log"))
+                .unwrap_or(false)
+        })
+        .collect();
+
+    // The millisecond stamps share a width, so the names sort oldest first
+    logs.sort();
+
+    let excess = logs.len().saturating_sub(retain);
+    for path in logs.into_iter().take(excess) {
+        if let Err(err) = fs::remove_file(&path) {
+            log!(LogLevel::Warn, "Couldn't remove old build log {}: {}", path.display(), err);
+        }
+    }
+}
@@ -1,12 +1,16 @@
 use artisan_middleware::{
     common::{log_error, update_state, wind_down_state},
     config::AppConfig,
-    process_manager::SupervisedChild,
-    state_persistence::{AppState, StatePersistence},
+    state_persistence::AppState,
 };
 // use child::{create_child, run_one_shot_process};
-use child::{create_child, run_one_shot_process};
-use config::{generate_application_state, get_config, specific_config};
+use adopt::{adopt_child, AdoptedChild};
+use child::{create_child, pid_file, respawn_cooldown, rolling_restart, run_build, run_hook, run_install, stop_child, ChildHandle, Instance};
+use check::run_check;
+use cli::{cli_args, CliCommand};
+use cleanup::{install_panic_hook, register_artifact, remove_pid_file, remove_stale_pid_files, CleanupGuard};
+use config::{application_config, application_state_path, config_file, get_config, prepare_state_path, generate_application_state, EXIT_CONFIG, specific_config, specific_configs, AppSpecificConfig, LimitAction, PackageManager, Runtime, WatchAction};
+use control::start_watch_socket;
 use dusa_collection_utils::{
     errors::{ErrorArrayItem, Errors},
     types::PathType,
@@ -15,8 +19,23 @@ use dusa_collection_utils::{
     log,
     log::LogLevel,
 };
-use monitor::monitor_directory;
-use signals::{sighup_watch, sigusr_watch};
+use disk::disk_usage_problem;
+use dryrun::run_dry_run;
+use error_log::ErrorLogRetention;
+use health::port_free;
+use logs::{route_runner_logs, start_tail_socket};
+use monitor::{monitor_directory, WatchAlerts};
+use reload::{config_watch, record_reload, settings_diff};
+use rules::WatchRules;
+use sandbox::{oom_kill_count, system_oom_kill_count};
+use sample::run_init;
+use schema::run_schema;
+use schedule::CronSchedule;
+use secrets::run_encrypt;
+use signals::{sighup_watch, sigusr2_watch, sigusr_watch};
+use stdin::start_stdin_socket;
+use threshold::{pending_changes_path, ChangeCounter};
+use trigger::watch_restart_trigger;
 use std::{
     sync::{
         atomic::{AtomicBool, Ordering},
@@ -24,21 +43,64 @@ use std::{
     },
     time::Duration,
 };
+use tokio::time::{Interval, MissedTickBehavior};
 
+mod adopt;
+mod check;
 mod child;
+mod cli;
+mod cleanup;
 mod config;
+mod control;
+mod container;
+mod digest;
+mod disk;
+mod dryrun;
+mod error_log;
+mod fields;
+mod filter;
+mod git;
+mod health;
+mod logs;
+mod migrate;
 mod monitor;
+mod reload;
+mod remote;
+mod restart;
+mod rules;
+mod sandbox;
+mod sample;
+mod schedule;
+mod schema;
+mod secrets;
 mod signals;
+mod stdin;
+mod threshold;
+mod trigger;
 
 #[tokio::main]
 async fn main() {
+    if cli_args().command == Some(CliCommand::Check) {
+        std::process::exit(run_check());
+    }
+    if cli_args().command == Some(CliCommand::Encrypt) {
+        std::process::exit(run_encrypt());
+    }
+    if cli_args().command == Some(CliCommand::Init) {
+        std::process::exit(run_init());
+    }
+    if cli_args().command == Some(CliCommand::Schema) {
+        std::process::exit(run_schema());
+    }
+    if cli_args().dry_run {
+        std::process::exit(run_dry_run().await);
+    }
+
     // Initialization
     log!(LogLevel::Trace, "Initializing application...");
-    let mut config: AppConfig = get_config();
-    let state_path: PathType = StatePersistence::get_state_path(&config);
 
     log!(LogLevel::Trace, "Loading specific configuration...");
-    let settings = match specific_config() {
+    let applications = match specific_configs() {
         Ok(loaded_data) => {
             log!(
                 LogLevel::Trace,
@@ -47,21 +109,81 @@ async fn main() {
             loaded_data
         }
         Err(e) => {
-            log!(LogLevel::Error, "Error loading settings: {}", e);
-            std::process::exit(0)
+            log!(LogLevel::Error, "Settings in {} don't load:", config_file().display());
+            for problem in e.to_string().lines() {
+                log!(LogLevel::Error, "  {}", problem);
+            }
+            // A non-zero code keeps systemd from treating this as a clean stop
+            std::process::exit(EXIT_CONFIG)
         }
     };
 
+    // The runner's logs are process wide, the first application decides where they go
+    if let Some(settings) = applications.first() {
+        route_runner_logs(settings, &get_config().app_name);
+    }
+
+    // Pid files and sockets are removed on every way out from here on
+    install_panic_hook();
+    let _cleanup = CleanupGuard;
+
+    // Each application gets its own children, state and restart logic
+    let supervisors: Vec<_> = applications
+        .into_iter()
+        .map(|settings| tokio::spawn(supervise(settings)))
+        .collect();
+
+    // One application failing leaves the others running, the runner exits
+    // with the first failure once every application stopped
+    let mut exit_code = 0;
+    for supervisor in supervisors {
+        match supervisor.await {
+            Ok(code) if exit_code == 0 => exit_code = code,
+            Ok(_) => (),
+            Err(err) => log!(LogLevel::Error, "An application's supervisor failed: {}", err),
+        }
+    }
+    if exit_code != 0 {
+        cleanup::exit(exit_code);
+    }
+}
+
+/// Builds, starts and supervises the children of one application until the
+/// runner is told to exit or the application fails. Returns the exit code
+/// the application asks for, its failures stay out of the other supervisors.
+async fn supervise(mut settings: AppSpecificConfig) -> i32 {
+    let mut config: AppConfig = application_config(&settings);
+    let state_path: PathType = application_state_path(&settings);
+    prepare_state_path(&settings, &state_path);
+    remove_stale_pid_files(&config.app_name, settings.instances.max(1));
+
     // Setting up the state of the application
     log!(LogLevel::Trace, "Setting up the application state...");
     let mut state: AppState = generate_application_state(&state_path, &config).await;
 
+    // Nothing can be watched or built without these
+    let monitor_path = match settings.safe_path().and_then(|path| settings.project_path().map(|_| path)) {
+        Ok(path) => path,
+        Err(err) => {
+            log!(LogLevel::Error, "{}", err);
+            log_error(&mut state, err, &state_path).await;
+            wind_down_state(&mut state, &state_path).await;
+            return EXIT_CONFIG;
+        }
+    };
+
     // Listening for the sighup
     let reload: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
     let exit_graceful: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+    let monitoring_paused: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
 
     sighup_watch(reload.clone());
     sigusr_watch(exit_graceful.clone());
+    sigusr2_watch(monitoring_paused.clone());
+    config_watch(&settings, reload.clone());
+
+    start_tail_socket(&settings.child_logs);
+    start_stdin_socket(&settings.stdin, settings.name.clone());
 
     log!(LogLevel::Trace, "Setting state as active...");
     state.is_active = true;
@@ -77,127 +199,535 @@ async fn main() {
     log!(
         LogLevel::Info,
         "Directory Monitoring: {}",
-        settings.safe_path()
+        monitor_path
     );
 
+    let instance_count: u16 = settings.instances.max(1);
+
+    // Children an earlier runner left running are taken over instead of duplicated
+    let mut adopted: Vec<Option<(AdoptedChild, u16)>> = (0..instance_count)
+        .map(|index| adopt_child(&settings, &config.app_name, index))
+        .collect();
+
     // Spawn child process
-    log!(LogLevel::Trace, "Running one shot pre child");
-    // Run the one-shot process before creating the child
-    if let Err(err) = run_one_shot_process(&settings).await {
-        log!(LogLevel::Error, "One-shot process failed: {}", err);
-        let error = ErrorArrayItem::new(Errors::GeneralError, err);
-        log_error(&mut state, error, &state_path).await;
-        return;
+    if adopted.iter().all(|adoption| adoption.is_some()) {
+        log!(LogLevel::Info, "Every instance was adopted, skipping the one shot");
+    } else {
+        log!(LogLevel::Trace, "Running one shot pre child");
+        // Run the one-shot process before creating the child
+        if !run_build(&mut state, &state_path, &settings).await {
+            let mut adopted: Vec<Instance> = adopted
+                .into_iter()
+                .enumerate()
+                .filter_map(|(index, adoption)| adoption.map(|(child, port)| (index as u16, child, port)))
+                .map(|(index, child, port)| Instance::new(&settings, index, port, ChildHandle::Adopted(child)))
+                .collect();
+            return give_up(&mut state, &state_path, &settings, &mut adopted).await;
+        }
     }
 
     log!(LogLevel::Trace, "Spawning child process...");
-    let mut child: SupervisedChild = create_child(&mut state, &state_path, &settings).await;
-
-    match child.clone().await.running().await {
-        true => {
-            // * safe to call unwrap because we checked that the pid is running
-            let xid: u32 = child.clone().await.get_pid().await.unwrap();
-            log!(LogLevel::Info, "Child spawned: {}", xid);
-            state.data = format!("Child spawned: {}", xid);
-            update_state(&mut state, &state_path, None).await;
-        }
-        false => {
-            log!(LogLevel::Error, "Failed to spawn child process");
-            let error = ErrorArrayItem::new(Errors::GeneralError, "child not spawned".to_string());
-            log_error(&mut state, error, &state_path).await;
-            std::process::exit(100);
+    let mut instances: Vec<Instance> = Vec::with_capacity(instance_count as usize);
+
+    for index in 0..instance_count {
+        let (child, port): (ChildHandle, u16) = match adopted[index as usize].take() {
+            Some((child, port)) => {
+                register_artifact(pid_file(&config.app_name, index));
+                (ChildHandle::Adopted(child), port)
+            }
+            None => {
+                let spawned = match settings.instance_port(index) {
+                    Ok(port) => create_child(&mut state, &state_path, &settings, index, port).await.map(|child| (child, port)),
+                    Err(err) => Err(err),
+                };
+                match spawned {
+                    Ok(spawned) => spawned,
+                    Err(err) => return fail_application(&mut state, &state_path, &settings, &mut instances, err).await,
+                }
+            }
+        };
+
+        match child.running().await {
+            true => {
+                // * safe to call unwrap because we checked that the pid is running
+                let xid: u32 = child.get_pid().await.unwrap();
+                log!(LogLevel::Info, "Child spawned: {} (instance {} on port {})", xid, index, port);
+                state.data = format!("Child spawned: {}", xid);
+                update_state(&mut state, &state_path, None).await;
+            }
+            // Recorded already, the periodic check starts it once the port is free
+            false if matches!(child, ChildHandle::Blocked(_)) => (),
+            false => {
+                let error = ErrorArrayItem::new(Errors::GeneralError, "child not spawned".to_string());
+                return fail_application(&mut state, &state_path, &settings, &mut instances, error).await;
+            }
         }
+
+        instances.push(Instance::new(&settings, index, port, child));
     }
 
-    let mut change_count: i32 = 0;
-    let trigger_count: i32 = settings.changes_needed;
+    let mut changes = ChangeCounter::new(&settings).persisted(pending_changes_path(&state_path));
+    let mut rules = WatchRules::new(&settings);
+    let mut dependencies_changed = false;
+    let mut reported_paused = false;
+    let mut reported_degraded = false;
+    let mut settle_period = Duration::from_secs(settings.settle_seconds);
+    let mut settle_deadline: Option<tokio::time::Instant> = None;
+    let mut oom_kills: u64 = oom_kill_count(&settings.limits).unwrap_or(0);
+    let mut system_oom_kills: u64 = system_oom_kill_count().unwrap_or(0);
+    let mut supervision_timer = supervision_interval(&settings);
+    let mut error_retention = ErrorLogRetention::new(&settings);
+    let mut health_timer = tokio::time::interval(Duration::from_secs(settings.health_check.interval_seconds.max(1)));
+    let mut disk_timer = tokio::time::interval(Duration::from_secs(settings.disk_usage.interval_seconds.max(1)));
+    let mut disk_problem: Option<String> = None;
+    let mut restart_trigger = watch_restart_trigger(&settings);
+
+    let mut restart_schedule: Option<CronSchedule> = match load_restart_schedule(&settings) {
+        Ok(schedule) => schedule,
+        Err(message) => {
+            log_error(&mut state, ErrorArrayItem::new(Errors::GeneralError, message), &state_path).await;
+            None
+        }
+    };
+    let mut next_scheduled_restart = restart_schedule.as_ref().and_then(|schedule| schedule.next_instant());
 
     // Start monitoring the directory and get the asynchronous receiver
     log!(LogLevel::Trace, "Starting directory monitoring...");
-    let mut event_rx = match monitor_directory(settings.safe_path(), settings.ignored_paths()).await {
-        Ok(receiver) => {
+    let watch_alerts = WatchAlerts::default();
+    let (mut event_rx, watch_handle) = match monitor_directory(&settings, watch_alerts.clone()).await {
+        Ok((receiver, handle)) => {
             log!(LogLevel::Trace, "Successfully started directory monitoring");
-            receiver
+            start_watch_socket(&settings, handle.clone());
+            (receiver, handle)
         }
         Err(err) => {
             log!(LogLevel::Error, "Watcher error: {}", err);
+            for message in watch_alerts.take() {
+                log_error(&mut state, ErrorArrayItem::new(Errors::GeneralError, message), &state_path).await;
+            }
             wind_down_state(&mut state, &state_path).await;
-            std::process::exit(0);
+            return 0;
         }
     };
 
     log!(LogLevel::Trace, "Entering main loop...");
     loop {
+        let mut restart_due = false;
+        let mut spawn_failure: Option<ErrorArrayItem> = None;
+        let mut build_failed = false;
+
         tokio::select! {
             Some(event) = event_rx.recv() => {
                 log!(LogLevel::Trace, "Received directory change event: {:?}", event);
-                change_count += 1;
-                log!(LogLevel::Info, "Change detected: {} out of {}", change_count, trigger_count);
-                log!(LogLevel::Debug, "Event details: {:?}", event);
 
-                if change_count >= trigger_count {
-                    log!(LogLevel::Info, "Reached {} changes, handling event", trigger_count);
-                    state.event_counter += 1;
+                if monitoring_paused.load(Ordering::Relaxed) {
+                    log!(LogLevel::Debug, "Monitoring paused, not counting change under {}", event.root);
+                } else {
+                    // Deploys and storms act on the whole tree, rules don't split them up
+                    let paths = match event.deploy || event.bulk {
+                        true => event.event.paths.clone(),
+                        false => rules.record(&event.root, &event.event.paths),
+                    };
+                    if paths.len() < event.event.paths.len() {
+                        log!(LogLevel::Info, "Change detected under {} for watch rules: {}", event.root, rules.summary());
+                    }
+                    if !paths.is_empty() || event.event.paths.is_empty() {
+                        changes.record(&event.root, &paths);
+                        log!(LogLevel::Info, "Change detected under {}: {}", event.root, changes.summary());
+                    }
+                    log!(LogLevel::Debug, "Event details: {:?}", event.event);
+
+                    if paths.iter().any(|path| PackageManager::is_manifest(path)) {
+                        dependencies_changed = true;
+                    }
+
+                    if event.bulk {
+                        log!(LogLevel::Info, "Bulk change under {}, not waiting for more changes", event.root);
+                    }
+
+                    if event.deploy {
+                        log!(LogLevel::Info, "Deploy requested under {}, deploying now", event.root);
+                        settle_deadline = None;
+                        restart_due = true;
+                    } else if changes.reached() || event.bulk {
+                        match settle_period.is_zero() {
+                            true => restart_due = true,
+                            false => {
+                                // Every further change pushes the restart out again
+                                log!(LogLevel::Debug, "Waiting for {}s without changes before restarting", settle_period.as_secs());
+                                settle_deadline = Some(tokio::time::Instant::now() + settle_period);
+                            }
+                        }
+                    }
+                }
+            }
+            _ = tokio::time::sleep_until(settle_deadline.unwrap_or_else(tokio::time::Instant::now)), if settle_deadline.is_some() => {
+                log!(LogLevel::Info, "No changes for {}s, the tree has settled", settle_period.as_secs());
+                settle_deadline = None;
+                restart_due = true;
+            }
+            _ = tokio::time::sleep_until(next_scheduled_restart.unwrap_or_else(tokio::time::Instant::now)), if next_scheduled_restart.is_some() => {
+                if let Some(problem) = disk_problem.as_ref().filter(|_| settings.disk_usage.block_builds) {
+                    log!(LogLevel::Warn, "Skipping the scheduled restart, builds are blocked: {}", problem);
+                } else {
+                    log!(LogLevel::Info, "Scheduled restart due, rebuilding and recycling the child");
+                    state.data = String::from("Scheduled restart");
                     update_state(&mut state, &state_path, None).await;
-                    log!(LogLevel::Info, "Killing the child");
-
-                    match child.clone().await.kill().await {
-                        Ok(_) => {
-                            // creating new child
-                            child = create_child(&mut state, &state_path, &settings).await;
-                            log!(LogLevel::Info, "New child process spawned.");
-                        },
-                        Err(error) => {
-                            log!(LogLevel::Error, "Failed to wait for child process termination: {}", error);
-                            log_error(&mut state, error, &state_path).await;
-                        },
+
+                    if !run_build(&mut state, &state_path, &settings).await {
+                        return give_up(&mut state, &state_path, &settings, &mut instances).await;
+                    }
+                    if let Err(err) = rolling_restart(&mut state, &state_path, &settings, &mut instances).await {
+                        return fail_application(&mut state, &state_path, &settings, &mut instances, err).await;
                     }
 
-                    change_count = 0; // Reset count
+                    state.data = String::from("Restarted on schedule");
+                    update_state(&mut state, &state_path, None).await;
                 }
+                next_scheduled_restart = restart_schedule.as_ref().and_then(|schedule| schedule.next_instant());
             }
-            _ = tokio::time::sleep(Duration::from_secs(3)) => {
-                log!(LogLevel::Trace, "Periodic task triggered - checking child process status...");
+            Some(_) = restart_trigger.recv() => {
+                log!(LogLevel::Info, "Restart trigger touched, forcing a rebuild and restart");
+                state.data = String::from("Forced restart");
+                update_state(&mut state, &state_path, None).await;
+
+                for instance in instances.iter_mut() {
+                    if let Err(err) = stop_child(&mut instance.child, &settings).await {
+                        log_error(&mut state, err, &state_path).await;
+                    }
+                }
+
+                if !run_build(&mut state, &state_path, &settings).await {
+                    return give_up(&mut state, &state_path, &settings, &mut instances).await;
+                }
 
-                if !child.clone().await.running().await {
-                    log!(LogLevel::Warn, "Child process {:?} is not running. Restarting...", child.get_pid().await);
+                respawn_cooldown(&settings).await;
+                for instance in instances.iter_mut() {
+                    match create_child(&mut state, &state_path, &settings, instance.index, instance.port).await {
+                        Ok(child) => instance.child = child,
+                        Err(err) => {
+                            spawn_failure = Some(err);
+                            break;
+                        }
+                    }
+                    instance.crash_tracker.reset();
+                }
 
-                    if let Ok(_) = child.kill().await {
-                        log!(LogLevel::Info, "Executed the previous child")
+                // One touch arrives as several events, and whatever was pending is part of this build
+                while restart_trigger.try_recv().is_ok() {}
+                changes.reset();
+                settle_deadline = None;
+                state.data = String::from("Restarted by the restart trigger");
+                update_state(&mut state, &state_path, None).await;
+            }
+            _ = health_timer.tick(), if settings.health_check.url.is_some() => {
+                let startup_grace = Duration::from_secs(settings.startup_grace_seconds);
+                for instance in instances.iter_mut() {
+                    // A booting child isn't listening yet, its failures would count towards a restart
+                    if instance.crash_tracker.starting(startup_grace) {
+                        continue;
                     }
+                    let checker = match instance.health_checker.as_mut() {
+                        Some(checker) => checker,
+                        None => continue,
+                    };
 
-                    if let Err(err) = run_one_shot_process(&settings).await {
-                        log!(LogLevel::Error, "One-shot process failed: {}", err);
-                        let error = ErrorArrayItem::new(Errors::GeneralError, err);
+                    // A dead child is handled by the periodic check below
+                    if instance.child.running().await && checker.check().await {
+                        let message = format!("Instance {} failed {} health checks in a row, restarting", instance.index, checker.failures());
+                        log!(LogLevel::Error, "{}", message);
+                        let error = ErrorArrayItem::new(Errors::GeneralError, message);
                         log_error(&mut state, error, &state_path).await;
-                        return;
+                        checker.reset();
+
+                        match stop_child(&mut instance.child, &settings).await {
+                            Ok(_) => {
+                                respawn_cooldown(&settings).await;
+                                match create_child(&mut state, &state_path, &settings, instance.index, instance.port).await {
+                                    Ok(child) => instance.child = child,
+                                    Err(err) => {
+                                        spawn_failure = Some(err);
+                                        break;
+                                    }
+                                }
+                                instance.crash_tracker.record_spawn();
+                                state.data = String::from("Restarted after failing health checks");
+                                update_state(&mut state, &state_path, None).await;
+                            },
+                            Err(error) => {
+                                log!(LogLevel::Error, "Failed to stop unhealthy child: {}", error);
+                                log_error(&mut state, error, &state_path).await;
+                            },
+                        }
                     }
+                }
+            }
+            _ = disk_timer.tick(), if settings.disk_usage.enabled() => {
+                let measured = settings.clone();
+                let problem = tokio::task::spawn_blocking(move || disk_usage_problem(&measured)).await.unwrap_or(None);
 
-                    log!(LogLevel::Info, "One shot finished, Spawning new child");
+                match (&problem, &disk_problem) {
+                    (Some(message), None) => {
+                        log!(LogLevel::Warn, "{}", message);
+                        log_error(&mut state, ErrorArrayItem::new(Errors::GeneralError, message.clone()), &state_path).await;
+                    }
+                    (None, Some(_)) => {
+                        log!(LogLevel::Info, "Disk usage is back within its limits");
+                        // Changes held back while builds were blocked go through now
+                        restart_due = settings.disk_usage.block_builds && changes.reached();
+                    }
+                    _ => (),
+                }
+                disk_problem = problem;
+            }
+            _ = supervision_timer.tick() => {
+                log!(LogLevel::Trace, "Periodic task triggered - checking child process status...");
 
-                    child = create_child(&mut state, &state_path, &settings).await;
-                    let message = "New child process spawned";
-                    
-                    log!(LogLevel::Info, "{message}");
-                    state.data = message.to_string();
+                let paused = monitoring_paused.load(Ordering::Relaxed);
+                if paused != reported_paused {
+                    reported_paused = paused;
+                    state.data = match paused {
+                        true => String::from("Monitoring paused"),
+                        false => String::from("Monitoring resumed"),
+                    };
                     update_state(&mut state, &state_path, None).await;
                 }
 
-                if state.error_log.len() >= 3 { // * Change this limit dependent on the project
-                    state.error_log.remove(0);
-                    state.error_log.dedup();
+                for message in watch_alerts.take() {
+                    log_error(&mut state, ErrorArrayItem::new(Errors::GeneralError, message), &state_path).await;
+                }
+
+                let degraded = watch_alerts.degraded();
+                if degraded != reported_degraded {
+                    reported_degraded = degraded;
+                    state.data = match degraded {
+                        true => String::from("Degraded: a watched directory is missing, changes aren't seen"),
+                        false => String::from("Watched directories are back, monitoring resumed"),
+                    };
+                    update_state(&mut state, &state_path, None).await;
+
+                    // Changes held back while the tree was gone go through now
+                    restart_due = !degraded && changes.reached();
                 }
 
-                // Update state as needed
-                state.is_active = true;
-                state.data = String::from("Nominal");
-                if let Ok(metrics) = child.get_metrics().await {
-                    // Ensuring we are within the specified limits
-                    if metrics.memory_usage >= state.config.max_ram_usage as f32 {
-                        state.error_log.push(ErrorArrayItem::new(Errors::OverRamLimit, "Application has exceeded ram limit"))
+                for instance in instances.iter_mut() {
+                    // Waiting for a taken port isn't a crash, try again every tick
+                    if matches!(instance.child, ChildHandle::Blocked(_)) {
+                        if port_free(instance.port) {
+                            log!(LogLevel::Info, "Port {} is free, starting instance {}", instance.port, instance.index);
+                            match create_child(&mut state, &state_path, &settings, instance.index, instance.port).await {
+                                Ok(child) => instance.child = child,
+                                Err(err) => {
+                                    spawn_failure = Some(err);
+                                    break;
+                                }
+                            }
+                            instance.crash_tracker.record_spawn();
+                        }
+                        continue;
+                    }
+
+                    instance.observe_exit().await;
+
+                    if instance.child.running().await {
+                        continue;
                     }
 
+                    if !instance.crash_tracker.awaiting_respawn() {
+                        let exit = instance.last_exit.take();
+                        let clean_exit = exit.is_some_and(|exit| exit.is_clean());
+                        let exit_status = match exit {
+                            Some(exit) => exit.to_string(),
+                            None => String::from("unknown exit status"),
+                        };
+
+                        log!(LogLevel::Warn, "Child process {:?} (instance {}) is not running ({})", instance.child.get_pid().await, instance.index, exit_status);
+                        state.data = format!("Instance {} exited with {}", instance.index, exit_status);
+                        if !clean_exit {
+                            let error = ErrorArrayItem::new(Errors::GeneralError, format!("Instance {} exited with {}", instance.index, exit_status));
+                            log_error(&mut state, error, &state_path).await;
+                        }
+
+                        // If the cgroup recorded a new oom kill, the kernel took the child out
+                        let cgroup_kills = oom_kill_count(&settings.limits).unwrap_or(oom_kills);
+                        let system_kills = system_oom_kill_count().unwrap_or(system_oom_kills);
+
+                        if cgroup_kills > oom_kills {
+                            log!(LogLevel::Error, "Child was killed by memory.max");
+                            let error = ErrorArrayItem::new(Errors::OverRamLimit, "Child killed by memory.max".to_string());
+                            log_error(&mut state, error, &state_path).await;
+                        } else if system_kills > system_oom_kills {
+                            log!(LogLevel::Error, "Child was likely killed by the kernel OOM killer");
+                            let error = ErrorArrayItem::new(Errors::OverRamLimit, "Child killed by the kernel OOM killer".to_string());
+                            log_error(&mut state, error, &state_path).await;
+                        }
+
+                        oom_kills = cgroup_kills;
+                        system_oom_kills = system_kills;
+
+                        if !settings.restart_policy.restarts(clean_exit) {
+                            let message = format!("Instance {} stopped, restart policy {} leaves it down", instance.index, settings.restart_policy);
+                            log!(LogLevel::Info, "{}", message);
+                            let _ = stop_child(&mut instance.child, &settings).await;
+                            instance.crash_tracker.record_stop();
+                            state.data = message;
+                            update_state(&mut state, &state_path, None).await;
+                            continue;
+                        }
+
+                        // Clears out anything the dead child left behind in its process group
+                        if let Ok(_) = stop_child(&mut instance.child, &settings).await {
+                            log!(LogLevel::Info, "Executed the previous child")
+                        }
+
+                        instance.crash_tracker.record_exit();
+
+                        if instance.crash_tracker.failed() {
+                            let message = format!(
+                                "Child failed {} times within {}s, no longer restarting it",
+                                instance.crash_tracker.recent_exits(),
+                                instance.crash_tracker.restart_window().as_secs()
+                            );
+                            log!(LogLevel::Error, "{}", message);
+                            let error = ErrorArrayItem::new(Errors::GeneralError, message);
+                            log_error(&mut state, error, &state_path).await;
+                            state.is_active = false;
+                            state.data = String::from("Failed: restart limit reached");
+                            update_state(&mut state, &state_path, None).await;
+                        }
+                    }
+
+                    if instance.crash_tracker.respawn_due() {
+                        if !run_build(&mut state, &state_path, &settings).await {
+                            build_failed = true;
+                            break;
+                        }
+
+                        log!(LogLevel::Info, "One shot finished, Spawning new child");
+
+                        respawn_cooldown(&settings).await;
+                        match create_child(&mut state, &state_path, &settings, instance.index, instance.port).await {
+                            Ok(child) => instance.child = child,
+                            Err(err) => {
+                                spawn_failure = Some(err);
+                                break;
+                            }
+                        }
+                        instance.crash_tracker.record_spawn();
+                        let message = "New child process spawned";
+
+                        log!(LogLevel::Info, "{message}");
+                        state.data = message.to_string();
+                        update_state(&mut state, &state_path, None).await;
+                    } else if !instance.crash_tracker.failed() && !instance.crash_tracker.finished() {
+                        log!(LogLevel::Debug, "Backing off after {} consecutive failures", instance.crash_tracker.consecutive_failures());
+                    }
+                }
+
+                error_retention.apply(&mut state.error_log);
+                let startup_grace = Duration::from_secs(settings.startup_grace_seconds);
+
+                // Update state as needed, a failed runner keeps reporting as failed
+                let blocked_port = instances.iter().find_map(|instance| match instance.child {
+                    ChildHandle::Blocked(port) => Some(port),
+                    _ => None,
+                });
+                if instances.iter().all(|instance| instance.crash_tracker.finished()) {
+                    state.data = String::from("Stopped by restart policy");
+                } else if let Some(port) = blocked_port {
+                    state.data = format!("Waiting for port {}, another process is using it", port);
+                } else if !instances.iter().any(|instance| instance.crash_tracker.failed()) {
+                    state.is_active = true;
+                    state.data = match instances.iter().any(|instance| instance.crash_tracker.starting(startup_grace)) {
+                        true => String::from("Starting"),
+                        false => String::from("Nominal"),
+                    };
+                }
+
+                // Every instance is held to the limit, the state reports the first one's metrics
+                let mut reported_metrics = None;
+                let cpu_window = Duration::from_secs(settings.cpu_limit_window_seconds);
+                for instance in instances.iter_mut().filter(|instance| !instance.crash_tracker.finished()) {
+                    if let Ok(metrics) = instance.child.get_metrics().await {
+                        // Ensuring we are within the specified limits
+                        let mut exceeded: Option<ErrorArrayItem> = None;
+                        let starting = instance.crash_tracker.starting(startup_grace);
+                        if !starting && metrics.memory_usage >= state.config.max_ram_usage as f32 {
+                            exceeded = Some(ErrorArrayItem::new(Errors::OverRamLimit, "Application has exceeded ram limit"));
+                        }
+
+                        // Short cpu spikes are normal, only a sustained stretch above the limit counts
+                        let max_cpu_usage = state.config.max_cpu_usage as f32;
+                        if !starting && max_cpu_usage > 0.0 && metrics.cpu_usage >= max_cpu_usage {
+                            let over_since = *instance.cpu_over_since.get_or_insert_with(tokio::time::Instant::now);
+
+                            if over_since.elapsed() >= cpu_window {
+                                let message = format!(
+                                    "Instance {} used {:.1}% cpu for over {}s",
+                                    instance.index,
+                                    metrics.cpu_usage,
+                                    cpu_window.as_secs()
+                                );
+                                log!(LogLevel::Warn, "{}", message);
+                                instance.cpu_over_since = None;
+                                exceeded.get_or_insert(ErrorArrayItem::new(Errors::OverCpuLimit, message));
+                            }
+                        } else {
+                            instance.cpu_over_since = None;
+                        }
+
+                        if let Some(error) = exceeded {
+                            state.error_log.push(error);
+
+                            match settings.on_limit_exceeded {
+                                LimitAction::Log => (),
+                                LimitAction::Restart => {
+                                    match stop_child(&mut instance.child, &settings).await {
+                                        Ok(_) => {
+                                            respawn_cooldown(&settings).await;
+                                            match create_child(&mut state, &state_path, &settings, instance.index, instance.port).await {
+                                                Ok(child) => instance.child = child,
+                                                Err(err) => {
+                                                    spawn_failure = Some(err);
+                                                    break;
+                                                }
+                                            }
+                                            instance.crash_tracker.record_spawn();
+                                            log!(LogLevel::Info, "Restarted instance {} after exceeding a resource limit", instance.index);
+                                        },
+                                        Err(error) => {
+                                            log!(LogLevel::Error, "Failed to stop the child over its limits: {}", error);
+                                            log_error(&mut state, error, &state_path).await;
+                                        },
+                                    }
+                                    continue;
+                                },
+                                LimitAction::KillAndFail => {
+                                    let message = format!("Instance {} exceeded a resource limit, killing it for good", instance.index);
+                                    log!(LogLevel::Error, "{}", message);
+                                    if let Err(error) = stop_child(&mut instance.child, &settings).await {
+                                        log_error(&mut state, error, &state_path).await;
+                                    }
+                                    instance.crash_tracker.mark_failed();
+                                    log_error(&mut state, ErrorArrayItem::new(Errors::GeneralError, message), &state_path).await;
+                                    state.is_active = false;
+                                    state.data = String::from("Failed: resource limit exceeded");
+                                    continue;
+                                },
+                            }
+                        }
+
+                        if reported_metrics.is_none() {
+                            reported_metrics = Some(metrics);
+                        }
+                    }
+                }
+
+                if let Some(metrics) = reported_metrics {
                     update_state(&mut state, &state_path, Some(metrics)).await;
+                } else if instances.iter().any(|instance| matches!(instance.child, ChildHandle::Adopted(_) | ChildHandle::Blocked(_))) {
+                    // Adopted and not yet started children can't be measured, that alone isn't an error
+                    update_state(&mut state, &state_path, None).await;
                 } else {
                     state.data = String::from("Failed to get metric data");
                     state.error_log.push(ErrorArrayItem::new(Errors::GeneralError, "Failed to get metric data from the child"));
@@ -208,46 +738,336 @@ async fn main() {
             }
         }
 
+        if let Some(err) = spawn_failure.take() {
+            return fail_application(&mut state, &state_path, &settings, &mut instances, err).await;
+        }
+        if build_failed {
+            return give_up(&mut state, &state_path, &settings, &mut instances).await;
+        }
+
+        for (rule, paths) in rules.take_due() {
+            let files: Vec<String> = paths.iter().map(|path| path.display().to_string()).collect();
+            log!(LogLevel::Info, "Watch rule {} reached {} change(s) ({}), running its {} action", rule.name, rule.changes_needed, files.join(", "), rule.action);
+
+            let blocked = match disk_problem.as_ref().filter(|_| settings.disk_usage.block_builds) {
+                Some(problem) => Some(problem.clone()),
+                None => watch_alerts.degraded().then(|| String::from("a watched directory is missing")),
+            };
+
+            match rule.action {
+                WatchAction::Restart => {
+                    changes.extend_batch(&paths);
+                    dependencies_changed |= paths.iter().any(|path| PackageManager::is_manifest(path));
+                    restart_due = true;
+                }
+                WatchAction::Rebuild | WatchAction::Install if blocked.is_some() => {
+                    log!(LogLevel::Warn, "Skipping watch rule {}, builds are blocked: {}", rule.name, blocked.unwrap_or_default());
+                }
+                WatchAction::Rebuild => {
+                    state.data = format!("Rebuilding for watch rule {}", rule.name);
+                    update_state(&mut state, &state_path, None).await;
+                    if !run_build(&mut state, &state_path, &settings).await {
+                        return give_up(&mut state, &state_path, &settings, &mut instances).await;
+                    }
+                    state.data = format!("Rebuilt for watch rule {}", rule.name);
+                    update_state(&mut state, &state_path, None).await;
+                }
+                WatchAction::Install => {
+                    if run_install(&mut state, &state_path, &settings).await {
+                        state.data = format!("Reinstalled dependencies for watch rule {}", rule.name);
+                        update_state(&mut state, &state_path, None).await;
+                    }
+                }
+                WatchAction::Hook => {
+                    let name = format!("watch_rules.{}", rule.name);
+                    match run_hook(&settings, &name, &rule.hook, None).await {
+                        Ok(()) => {
+                            state.data = format!("Ran the hook of watch rule {}", rule.name);
+                            update_state(&mut state, &state_path, None).await;
+                        }
+                        Err(message) => {
+                            log!(LogLevel::Error, "{}", message);
+                            log_error(&mut state, ErrorArrayItem::new(Errors::GeneralError, message), &state_path).await;
+                        }
+                    }
+                }
+            }
+        }
+
+        while restart_due {
+            // Building from a half deleted tree would only fail, the current child keeps running
+            if watch_alerts.degraded() {
+                log!(LogLevel::Warn, "Holding back the rebuild until the watched directories are back");
+                break;
+            }
+
+            if let Some(problem) = disk_problem.as_ref().filter(|_| settings.disk_usage.block_builds) {
+                log!(LogLevel::Warn, "Holding back the rebuild until disk usage recovers: {}", problem);
+                state.data = format!("Blocked: {}", problem);
+                update_state(&mut state, &state_path, None).await;
+                break;
+            }
+
+            let batch = changes.batch_summary();
+            let breakdown = changes.batch_breakdown();
+            log!(LogLevel::Info, "Reached the change threshold ({}), handling event", changes.summary());
+            log!(LogLevel::Info, "Restarting for {}", batch);
+            log!(LogLevel::Debug, "Batch breakdown {}", breakdown);
+            state.event_counter += 1;
+            state.data = format!("Restarting: {} ({})", batch, breakdown);
+            update_state(&mut state, &state_path, None).await;
+
+            // New dependencies need an install and a fresh build before the restart
+            let install_needed = dependencies_changed
+                && settings.install_on_dependency_change
+                && settings.runtime == Runtime::Process;
+            dependencies_changed = false;
+
+            let ready = match install_needed {
+                true => {
+                    run_install(&mut state, &state_path, &settings).await
+                        && run_build(&mut state, &state_path, &settings).await
+                }
+                false => true,
+            };
+
+            if ready {
+                if let Err(err) = rolling_restart(&mut state, &state_path, &settings, &mut instances).await {
+                    return fail_application(&mut state, &state_path, &settings, &mut instances, err).await;
+                }
+            } else if !state.is_active {
+                return give_up(&mut state, &state_path, &settings, &mut instances).await;
+            }
+
+            // Changes made while building and restarting waited in the channel,
+            // they start the next window and may call for another round right away
+            changes.reset();
+            let mut queued_bulk = false;
+            let mut queued_deploy = false;
+            while let Ok(queued) = event_rx.try_recv() {
+                if monitoring_paused.load(Ordering::Relaxed) {
+                    continue;
+                }
+                queued_bulk |= queued.bulk;
+                queued_deploy |= queued.deploy;
+                let paths = match queued.deploy || queued.bulk {
+                    true => queued.event.paths.clone(),
+                    false => rules.record(&queued.root, &queued.event.paths),
+                };
+                changes.record(&queued.root, &paths);
+                if paths.iter().any(|path| PackageManager::is_manifest(path)) {
+                    dependencies_changed = true;
+                }
+            }
+
+            if changes.total() > 0 {
+                log!(LogLevel::Info, "{} change(s) arrived during the restart: {}", changes.total(), changes.summary());
+            }
+
+            let reached = changes.reached() || queued_bulk;
+            restart_due = queued_deploy || (reached && settle_period.is_zero());
+            if reached && !restart_due {
+                settle_deadline = Some(tokio::time::Instant::now() + settle_period);
+            }
+        }
+
         if reload.load(Ordering::Relaxed) {
             log!(LogLevel::Debug, "Reloading");
 
             // reload config file
-            config = get_config();
+            config = application_config(&settings);
+            let reloaded = specific_config(settings.name.as_deref())
+                .map_err(|err| err.to_string())
+                .and_then(|reloaded| match reloaded.safe_path().and_then(|_| reloaded.project_path()) {
+                    Ok(_) => Ok(reloaded),
+                    Err(err) => Err(err.to_string()),
+                });
 
             // Updating state data
             state = generate_application_state(&state_path, &config).await;
 
             // Killing and redrawing the process
-            if let Err(err) = child.kill().await {
-                log_error(&mut state, err, &state_path).await;
+            if !stop_instances(&mut state, &state_path, &settings, &mut instances).await {
                 wind_down_state(&mut state, &state_path).await;
                 // We're in a weird state kys and let systemd try again.
-                std::process::exit(100)
+                return 100;
+            }
+
+            // The settings are reloaded too, a broken file keeps the current ones
+            match reloaded {
+                Ok(reloaded) => {
+                    let diff = settings_diff(&settings, &reloaded);
+                    let message = match diff.is_empty() {
+                        true => String::from("Reloaded settings, nothing changed"),
+                        false => format!("Reloaded settings, changed {}", diff.join(", ")),
+                    };
+                    for change in &diff {
+                        log!(LogLevel::Info, "Reload changed {}", change);
+                    }
+                    // The status is overwritten by the next update, the history next to the state keeps it
+                    state.data = message;
+                    update_state(&mut state, &state_path, None).await;
+                    record_reload(&state_path, &diff);
+
+                    settings = reloaded;
+                    config = application_config(&settings);
+                    state.config.max_ram_usage = config.max_ram_usage;
+                    state.config.max_cpu_usage = config.max_cpu_usage;
+                    if !watch_handle.reload(&settings) {
+                        log!(LogLevel::Warn, "The watcher isn't running, reloaded settings don't reach it");
+                    }
+
+                    // Pending counts come back from disk against the new thresholds
+                    changes = ChangeCounter::new(&settings).persisted(pending_changes_path(&state_path));
+                    rules = WatchRules::new(&settings);
+                    settle_period = Duration::from_secs(settings.settle_seconds);
+                    settle_deadline = None;
+                    supervision_timer = supervision_interval(&settings);
+                    error_retention.configure(&settings);
+                    health_timer = tokio::time::interval(Duration::from_secs(settings.health_check.interval_seconds.max(1)));
+                    disk_timer = tokio::time::interval(Duration::from_secs(settings.disk_usage.interval_seconds.max(1)));
+                    restart_schedule = load_restart_schedule(&settings).unwrap_or(None);
+                    next_scheduled_restart = restart_schedule.as_ref().and_then(|schedule| schedule.next_instant());
+                    log!(LogLevel::Info, "Reloaded settings from {}", config_file().display());
+                }
+                Err(err) => {
+                    let message = format!("Keeping the current settings, reloading them failed: {}", err);
+                    log!(LogLevel::Error, "{}", message);
+                    log_error(&mut state, ErrorArrayItem::new(Errors::GeneralError, message), &state_path).await;
+                }
             }
 
             // running one shot again
-            if let Err(err) = run_one_shot_process(&settings).await {
-                log!(LogLevel::Error, "One-shot process failed: {}", err);
-                let error = ErrorArrayItem::new(Errors::GeneralError, err);
-                log_error(&mut state, error, &state_path).await;
-                return;
+            if !run_build(&mut state, &state_path, &settings).await {
+                return give_up(&mut state, &state_path, &settings, &mut instances).await;
+            }
+
+            // Instances beyond a lowered count are stopped already, only their pid files are left
+            let instance_count: u16 = settings.instances.max(1);
+            if instances.len() > instance_count as usize {
+                for removed in instances.split_off(instance_count as usize) {
+                    log!(LogLevel::Info, "Instance {} is no longer configured, leaving it stopped", removed.index);
+                    remove_pid_file(&config.app_name, removed.index);
+                }
             }
 
-            // creating new service
-            child = create_child(&mut state, &state_path, &settings).await;
+            // creating new service, on the reloaded ports
+            respawn_cooldown(&settings).await;
+            for instance in instances.iter_mut() {
+                let spawned = match settings.instance_port(instance.index) {
+                    Ok(port) => create_child(&mut state, &state_path, &settings, instance.index, port).await.map(|child| (child, port)),
+                    Err(err) => Err(err),
+                };
+                match spawned {
+                    Ok((child, port)) => *instance = Instance::new(&settings, instance.index, port, child),
+                    Err(err) => {
+                        spawn_failure = Some(err);
+                        break;
+                    }
+                }
+            }
+            if let Some(err) = spawn_failure.take() {
+                return fail_application(&mut state, &state_path, &settings, &mut instances, err).await;
+            }
+            for index in instances.len() as u16..instance_count {
+                let spawned = match settings.instance_port(index) {
+                    Ok(port) => create_child(&mut state, &state_path, &settings, index, port).await.map(|child| (child, port)),
+                    Err(err) => Err(err),
+                };
+                match spawned {
+                    Ok((child, port)) => instances.push(Instance::new(&settings, index, port, child)),
+                    Err(err) => return fail_application(&mut state, &state_path, &settings, &mut instances, err).await,
+                }
+            }
             log!(LogLevel::Info, "New child process spawned.");
 
+            // Blue/green starts over on the base port, the proxy has to follow
+            if let Some(port_file) = settings.zero_downtime.port_file.as_ref().filter(|_| instances.len() == 1) {
+                if let Err(err) = std::fs::write(port_file, settings.port.to_string()) {
+                    log!(LogLevel::Warn, "Failed to write the active port to {}: {}", port_file, err);
+                }
+            }
+
             reload.store(false, Ordering::Relaxed);
         }
 
         if exit_graceful.load(Ordering::Relaxed) {
             log!(LogLevel::Debug, "Exiting gracefully");
-            if let Err(err) = child.kill().await {
-                log_error(&mut state, err, &state_path).await;
+            if !stop_instances(&mut state, &state_path, &settings, &mut instances).await {
                 wind_down_state(&mut state, &state_path).await;
-                std::process::exit(100)
+                return 100;
             }
-            std::process::exit(0)
+            return 0;
+        }
+    }
+}
+
+/// Stops every instance, one that doesn't stop doesn't leave the rest
+/// running. False when any of them failed, the errors are recorded.
+async fn stop_instances(
+    state: &mut AppState,
+    state_path: &PathType,
+    settings: &AppSpecificConfig,
+    instances: &mut [Instance],
+) -> bool {
+    let mut stopped = true;
+    for instance in instances.iter_mut() {
+        if let Err(err) = stop_child(&mut instance.child, settings).await {
+            log!(LogLevel::Error, "Failed to stop instance {}: {}", instance.index, err);
+            log_error(state, err, state_path).await;
+            stopped = false;
         }
     }
+    stopped
+}
+
+/// A child that can't be spawned fails its application
+async fn fail_application(
+    state: &mut AppState,
+    state_path: &PathType,
+    settings: &AppSpecificConfig,
+    instances: &mut [Instance],
+    error: ErrorArrayItem,
+) -> i32 {
+    log!(LogLevel::Error, "Failed to spawn a child, stopping the application: {}", error);
+    log_error(state, error, state_path).await;
+    give_up(state, state_path, settings, instances).await
+}
+
+/// Ends a failed application, the children it has are stopped so none
+/// outlive their supervisor. Returns the exit code for `supervise`.
+async fn give_up(
+    state: &mut AppState,
+    state_path: &PathType,
+    settings: &AppSpecificConfig,
+    instances: &mut [Instance],
+) -> i32 {
+    for instance in instances.iter_mut() {
+        let _ = stop_child(&mut instance.child, settings).await;
+    }
+    wind_down_state(state, state_path).await;
+    100
+}
+
+/// Ticks every `interval_seconds` to check on the children. A check that ran
+/// long delays the next one instead of bunching them up.
+fn supervision_interval(settings: &AppSpecificConfig) -> Interval {
+    let mut timer = tokio::time::interval(Duration::from_secs(u64::from(settings.interval_seconds)));
+    timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    timer
+}
+
+/// Parses `restart_schedule`, an invalid expression is logged and comes back as the error
+fn load_restart_schedule(settings: &AppSpecificConfig) -> Result<Option<CronSchedule>, String> {
+    match &settings.restart_schedule {
+        Some(expression) => match CronSchedule::parse(expression) {
+            Ok(schedule) => Ok(Some(schedule)),
+            Err(err) => {
+                let message = format!("Ignoring invalid restart_schedule: {}", err);
+                log!(LogLevel::Error, "{}", message);
+                Err(message)
+            }
+        },
+        None => Ok(None),
+    }
 }
@@ -0,0 +1,268 @@
+use config::{Config, ConfigError, File, FileFormat};
+use dusa_collection_utils::log;
+use dusa_collection_utils::log::LogLevel;
+use serde_json::{Map, Value};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Schema version of `app_specific` this runner reads. Files without
+/// `config_version` predate versioning and count as version 1.
+pub const CONFIG_VERSION: u64 = 3;
+
+/// A step upgrading `app_specific` to the version it's listed with, noting
+/// every change it makes
+type Migration = fn(&mut Map<String, Value>, &mut Vec<String>);
+
+/// Applied in order to every file older than the version listed
+const MIGRATIONS: [(u64, Migration); 2] = [(2, to_v2), (3, to_v3)];
+
+/// Version 2 reloads when the file changes, older files only reloaded on SIGHUP
+fn to_v2(table: &mut Map<String, Value>, changes: &mut Vec<String>) {
+    pin(table, "reload_on_config_change", Value::Bool(false), changes);
+}
+
+/// Version 3 split the grace periods, the shutdown one was `stop_grace_seconds`
+fn to_v3(table: &mut Map<String, Value>, changes: &mut Vec<String>) {
+    rename(table, "stop_grace_seconds", "shutdown_grace_seconds", changes);
+}
+
+/// Moves a renamed field, a value under the new name wins
+fn rename(table: &mut Map<String, Value>, old: &str, new: &str, changes: &mut Vec<String>) {
+    let Some(value) = table.remove(old) else {
+        return;
+    };
+
+    if table.contains_key(new) {
+        changes.push(format!("dropped {}, {} is set", old, new));
+    } else {
+        changes.push(format!("renamed {} to {}", old, new));
+        table.insert(new.to_owned(), value);
+    }
+}
+
+/// Keeps the old default of a field whose default changed, unless it's set
+fn pin(table: &mut Map<String, Value>, field: &str, old_default: Value, changes: &mut Vec<String>) {
+    if table.contains_key(field) {
+        return;
+    }
+
+    changes.push(format!("set {} = {} to keep the previous default", field, old_default));
+    table.insert(field.to_owned(), old_default);
+}
+
+/// Upgrades `file` when it predates `CONFIG_VERSION` and writes the result
+/// next to it as `<name>.migrated.<extension>`. Returns the migrated
+/// document and its format for loading in place of the file, `None` when
+/// the file is current.
+pub fn migrate(file: &Path, format: FileFormat) -> Result<Option<(String, FileFormat)>, ConfigError> {
+    let text = fs::read_to_string(file)
+        .map_err(|err| ConfigError::Message(format!("Couldn't read {}: {}", file.display(), err)))?;
+    let mut document = parse(&text, format)?;
+
+    // A file of [[applications]] keeps its version and shared settings in app_specific all the same
+    if let Some(root) = document.as_object_mut().filter(|root| root.contains_key("applications")) {
+        root.entry("app_specific").or_insert_with(|| Value::Object(Map::new()));
+    }
+
+    let Some(table) = document.get_mut("app_specific").and_then(Value::as_object_mut) else {
+        return Ok(None);
+    };
+
+    let version = match table.get("config_version") {
+        None => 1,
+        Some(version) => version.as_u64().ok_or_else(|| {
+            ConfigError::Message(format!("app_specific.config_version is {}, expected a whole number", version))
+        })?,
+    };
+
+    if version > CONFIG_VERSION {
+        return Err(ConfigError::Message(format!(
+            "{} is config_version {}, this runner reads up to {}",
+            file.display(),
+            version,
+            CONFIG_VERSION
+        )));
+    }
+    if version == CONFIG_VERSION {
+        return Ok(None);
+    }
+
+    let mut changes = Vec::new();
+    for (target, migration) in MIGRATIONS.iter().filter(|(target, _)| *target > version) {
+        let before = changes.len();
+        migration(table, &mut changes);
+        for change in &mut changes[before..] {
+            *change = format!("{}: {}", target, change);
+        }
+    }
+
+    // Nothing the newer versions changed is used, the file reads the same
+    if changes.is_empty() {
+        return Ok(None);
+    }
+    table.insert(String::from("config_version"), Value::from(CONFIG_VERSION));
+
+    log!(LogLevel::Warn, "{} is config_version {}, migrated it to {}", file.display(), version, CONFIG_VERSION);
+    for change in &changes {
+        log!(LogLevel::Warn, "  {}", change);
+    }
+
+    let (migrated, migrated_format) = serialize(&document, format)?;
+    write_copy(file, &migrated);
+
+    Ok(Some((migrated, migrated_format)))
+}
+
+fn parse(text: &str, format: FileFormat) -> Result<Value, ConfigError> {
+    match format {
+        FileFormat::Toml => toml::from_str(text).map_err(|err| ConfigError::Message(err.to_string())),
+        FileFormat::Json => serde_json::from_str(text).map_err(|err| ConfigError::Message(err.to_string())),
+        // Without a YAML parser of our own the config crate reads it, which lowercases keys as it always does
+        _ => Config::builder()
+            .add_source(File::from_str(text, format))
+            .build()?
+            .try_deserialize(),
+    }
+}
+
+/// TOML stays TOML, everything else is written as JSON, which YAML parsers read as well
+fn serialize(document: &Value, format: FileFormat) -> Result<(String, FileFormat), ConfigError> {
+    match format {
+        FileFormat::Toml => toml::to_string_pretty(document)
+            .map(|text| (text, FileFormat::Toml))
+            .map_err(|err| ConfigError::Message(err.to_string())),
+        _ => serde_json::to_string_pretty(document)
+            .map(|text| (text, FileFormat::Json))
+            .map_err(|err| ConfigError::Message(err.to_string())),
+    }
+}
+
+/// Where the migrated copy of `file` is written
+fn migrated_copy_path(file: &Path) -> PathBuf {
+    let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+    match file.extension() {
+        Some(extension) => file.with_file_name(format!("{}.migrated.{}", stem, extension.to_string_lossy())),
+        None => file.with_file_name(format!("{}.migrated", stem)),
+    }
+}
+
+/// Written only when it differs, every load of an old file migrates it again
+fn write_copy(file: &Path, migrated: &str) {
+    let copy = migrated_copy_path(file);
+    if fs::read_to_string(&copy).is_ok_and(|existing| existing == migrated) {
+        return;
+    }
+
+    match fs::write(&copy, migrated) {
+        Ok(()) => log!(
+            LogLevel::Info,
+            "Wrote the migrated config to {}, replace {} with it to stop migrating on every load",
+            copy.display(),
+            file.display()
+        ),
+        Err(err) => log!(LogLevel::Warn, "Couldn't write the migrated config to {}: {}", copy.display(), err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn table(value: Value) -> Map<String, Value> {
+        value.as_object().cloned().unwrap()
+    }
+
+    /// A Config file of its own in the temp directory, the migrated copy lands next to it
+    fn config_file(name: &str, text: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ais_migrate_{}_{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("Config.toml");
+        fs::write(&file, text).unwrap();
+        file
+    }
+
+    #[test]
+    fn rename_moves_the_value() {
+        let mut settings = table(json!({ "stop_grace_seconds": 5 }));
+        let mut changes = Vec::new();
+        rename(&mut settings, "stop_grace_seconds", "shutdown_grace_seconds", &mut changes);
+        assert_eq!(settings, table(json!({ "shutdown_grace_seconds": 5 })));
+        assert_eq!(changes, vec!["renamed stop_grace_seconds to shutdown_grace_seconds"]);
+    }
+
+    #[test]
+    fn rename_keeps_a_value_under_the_new_name() {
+        let mut settings = table(json!({ "stop_grace_seconds": 5, "shutdown_grace_seconds": 8 }));
+        let mut changes = Vec::new();
+        rename(&mut settings, "stop_grace_seconds", "shutdown_grace_seconds", &mut changes);
+        assert_eq!(settings, table(json!({ "shutdown_grace_seconds": 8 })));
+        assert_eq!(changes, vec!["dropped stop_grace_seconds, shutdown_grace_seconds is set"]);
+
+        let mut changes = Vec::new();
+        rename(&mut settings, "stop_grace_seconds", "shutdown_grace_seconds", &mut changes);
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn pin_only_fills_unset_fields() {
+        let mut settings = table(json!({}));
+        let mut changes = Vec::new();
+        pin(&mut settings, "reload_on_config_change", Value::Bool(false), &mut changes);
+        assert_eq!(settings, table(json!({ "reload_on_config_change": false })));
+        assert_eq!(changes.len(), 1);
+
+        let mut settings = table(json!({ "reload_on_config_change": true }));
+        let mut changes = Vec::new();
+        pin(&mut settings, "reload_on_config_change", Value::Bool(false), &mut changes);
+        assert_eq!(settings, table(json!({ "reload_on_config_change": true })));
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn migrates_an_unversioned_file() {
+        let file = config_file("v1", "[app_specific]\nstop_grace_seconds = 5\n");
+        let (migrated, format) = migrate(&file, FileFormat::Toml).unwrap().unwrap();
+        assert_eq!(format, FileFormat::Toml);
+
+        let document: Value = toml::from_str(&migrated).unwrap();
+        assert_eq!(
+            document["app_specific"],
+            json!({ "config_version": CONFIG_VERSION, "shutdown_grace_seconds": 5, "reload_on_config_change": false })
+        );
+        assert_eq!(fs::read_to_string(migrated_copy_path(&file)).unwrap(), migrated);
+    }
+
+    #[test]
+    fn leaves_current_files_alone() {
+        let file = config_file("current", &format!("[app_specific]\nconfig_version = {}\n", CONFIG_VERSION));
+        assert!(migrate(&file, FileFormat::Toml).unwrap().is_none());
+
+        // Nothing an older version changed is set, the file reads the same
+        let file = config_file("unchanged", "[app_specific]\nconfig_version = 2\n");
+        assert!(migrate(&file, FileFormat::Toml).unwrap().is_none());
+    }
+
+    #[test]
+    fn refuses_newer_files() {
+        let file = config_file("newer", &format!("[app_specific]\nconfig_version = {}\n", CONFIG_VERSION + 1));
+        assert!(migrate(&file, FileFormat::Toml).is_err());
+    }
+
+    #[test]
+    fn migrates_the_shared_settings_of_applications() {
+        let file = config_file("applications", "[[applications]]\nname = \"web\"\n");
+        let (migrated, _) = migrate(&file, FileFormat::Toml).unwrap().unwrap();
+        let document: Value = toml::from_str(&migrated).unwrap();
+        assert_eq!(document["app_specific"]["reload_on_config_change"], json!(false));
+        assert_eq!(document["applications"][0]["name"], json!("web"));
+    }
+
+    #[test]
+    fn names_the_copy_after_the_file() {
+        assert_eq!(migrated_copy_path(Path::new("/etc/app/Config.toml")), Path::new("/etc/app/Config.migrated.toml"));
+        assert_eq!(migrated_copy_path(Path::new("/etc/app/Config")), Path::new("/etc/app/Config.migrated"));
+    }
+}
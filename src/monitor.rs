@@ -1,50 +1,241 @@
-use dusa_collection_utils::rwarc::LockWithTimeout;
-use dusa_collection_utils::types::PathType;
+use dusa_collection_utils::{errors::ErrorArrayItem, types::PathType};
 use dusa_collection_utils::log;
 use dusa_collection_utils::log::LogLevel;
-use notify::{Config, Event, RecommendedWatcher, RecursiveMode, Watcher};
-use std::sync::mpsc::channel;
+use nix::errno::Errno;
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+use nix::sys::statfs::{statfs, FsType, FUSE_SUPER_MAGIC, NFS_SUPER_MAGIC, SMB_SUPER_MAGIC};
+use notify::event::{CreateKind, EventKind};
+use notify::{Config, ErrorKind, Event, PollWatcher, RecommendedWatcher, RecursiveMode, Watcher};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use walkdir::WalkDir;
+
+use crate::{config::{AppSpecificConfig, RemoteWatchConfig, WatcherBackend}, digest::ContentHashes, filter::WatchFilter, git::start_git_watch, remote::start_remote_scan};
+
+const MAX_EVENT_PATHS: usize = 1000;
+const STORM_QUIET_PERIOD: Duration = Duration::from_secs(1);
+
+const ROOT_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+const EDITOR_SAVE_WINDOW: Duration = Duration::from_millis(250);
+
+/// Warnings and the degraded flag of one watcher, read by the supervision
+/// loop that owns the state
+#[derive(Clone, Default)]
+pub struct WatchAlerts {
+    warnings: Arc<Mutex<Vec<String>>>,
+    degraded: Arc<AtomicBool>,
+}
+
+impl WatchAlerts {
+    /// Queues a warning for the main loop, which owns the state
+    pub fn report(&self, message: String) {
+        log!(LogLevel::Warn, "{}", message);
+        if let Ok(mut pending) = self.warnings.lock() {
+            pending.push(message);
+        }
+    }
+
+    /// Whether a watched directory is currently missing
+    pub fn degraded(&self) -> bool {
+        self.degraded.load(Ordering::Relaxed)
+    }
+
+    /// Watcher warnings waiting to be recorded in the state
+    pub fn take(&self) -> Vec<String> {
+        match self.warnings.lock() {
+            Ok(mut pending) => pending.drain(..).collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+/// One watched directory and the filter for events below it
+pub struct WatchRoot {
+    pub path: PathType,
+    pub filter: WatchFilter,
+}
+
+/// A (coalesced) change along with the root it was seen under
+#[derive(Debug)]
+pub struct WatchEvent {
+    pub root: PathType,
+    pub event: Event,
+    pub bulk: bool, // A collapsed event storm, triggers on its own
+    pub deploy: bool, // The deploy marker was touched or HEAD moved, triggers right away
+}
+
+/// Watcher behaviour shared by every root
+#[derive(Clone)]
+pub struct WatchOptions {
+    pub debounce: Duration,
+    pub content_hash: bool,
+    pub backend: WatcherBackend,
+    pub poll_interval: Duration,
+    pub max_depth: Option<usize>,
+    pub max_dirs: Option<usize>,
+    pub follow_symlinks: bool,
+    pub storm_threshold: usize,
+    pub remote: RemoteWatchConfig,
+    pub git_head_trigger: bool,
+    pub editor_dedup: bool,
+    pub alerts: WatchAlerts,
+}
+
+impl WatchOptions {
+    pub fn new(settings: &AppSpecificConfig, alerts: WatchAlerts) -> Self {
+        Self {
+            debounce: Duration::from_millis(settings.debounce_ms),
+            content_hash: settings.content_hash,
+            backend: settings.watcher,
+            poll_interval: Duration::from_secs(settings.poll_interval_seconds.max(1)),
+            max_depth: settings.max_watch_depth,
+            max_dirs: settings.max_watched_dirs,
+            follow_symlinks: settings.follow_symlinks,
+            storm_threshold: settings.storm_threshold,
+            remote: settings.remote.clone(),
+            git_head_trigger: settings.git_head_trigger,
+            editor_dedup: settings.editor_dedup,
+            alerts,
+        }
+    }
+
+    /// With a depth or count limit every directory is watched on its own
+    /// instead of handing whole trees to the backend
+    fn limited(&self) -> bool {
+        self.max_depth.is_some() || self.max_dirs.is_some()
+    }
+}
+
+/// Builds the watch roots from `monitor_path`, every `watch_roots` entry and
+/// absolute `watch_rules` paths, skipping extra roots that don't exist. A
+/// missing `monitor_path` is an error.
+pub fn watch_roots(settings: &AppSpecificConfig) -> Result<Vec<WatchRoot>, ErrorArrayItem> {
+    let primary = settings.safe_path()?;
+    let filter = WatchFilter::new(&primary, &settings.ignored_subdirs, settings);
+    let mut roots = vec![WatchRoot { path: primary, filter }];
+
+    for root in &settings.watch_roots {
+        let path = match PathType::Content(root.path.clone()).canonicalize() {
+            Ok(path) => PathType::PathBuf(path),
+            Err(e) => {
+                log!(LogLevel::Error, "Not watching {}: {}", root.path, e);
+                continue;
+            }
+        };
+
+        let filter = WatchFilter::new(&path, &root.ignored_subdirs, settings);
+        roots.push(WatchRoot { path, filter });
+    }
+
+    // Rules for a directory outside every root get it watched on its own
+    for rule in settings.watch_rules.iter().filter(|rule| Path::new(&rule.path).is_absolute()) {
+        let path = match PathType::Content(rule.path.clone()).canonicalize() {
+            Ok(path) => PathType::PathBuf(path),
+            Err(e) => {
+                log!(LogLevel::Error, "Not watching {} for watch rule {}: {}", rule.path, rule.name, e);
+                continue;
+            }
+        };
+        if roots.iter().any(|root| path.starts_with(&root.path)) {
+            continue;
+        }
+
+        let filter = WatchFilter::new(&path, &[], settings);
+        roots.push(WatchRoot { path, filter });
+    }
+
+    Ok(roots)
+}
+
+/// New roots for the watcher thread, and new options after a reload
+struct Reconfigure {
+    roots: Vec<WatchRoot>,
+    options: Option<WatchOptions>,
+}
+
+/// Changes what the running watcher watches. The settings are shared with
+/// the watch socket, so edits made there and reloads see each other.
+#[derive(Clone)]
+pub struct WatchHandle {
+    control_tx: Sender<Reconfigure>,
+    settings: Arc<Mutex<AppSpecificConfig>>,
+    alerts: WatchAlerts,
+}
+
+impl WatchHandle {
+    /// Replaces every root and its filter without touching the child. Returns
+    /// false when the watcher thread is gone.
+    pub fn replace_roots(&self, roots: Vec<WatchRoot>) -> bool {
+        self.control_tx.send(Reconfigure { roots, options: None }).is_ok()
+    }
+
+    /// Re-arms the watcher with reloaded settings, dropping edits made
+    /// through the watch socket. `remote` and `git_head_trigger` keep the
+    /// values the runner started with. Returns false when the roots don't
+    /// resolve or the watcher thread is gone.
+    pub fn reload(&self, settings: &AppSpecificConfig) -> bool {
+        let roots = match watch_roots(settings) {
+            Ok(roots) => roots,
+            Err(err) => {
+                self.alerts.report(format!("Keeping the current watches: {}", err));
+                return false;
+            }
+        };
+
+        if let Ok(mut shared) = self.settings.lock() {
+            *shared = settings.clone();
+        }
+
+        let reconfigure = Reconfigure {
+            roots,
+            options: Some(WatchOptions::new(settings, self.alerts.clone())),
+        };
+        self.control_tx.send(reconfigure).is_ok()
+    }
+
+    pub fn settings(&self) -> Arc<Mutex<AppSpecificConfig>> {
+        self.settings.clone()
+    }
+}
+
+/// Watches the roots of `settings`, reporting warnings and missing roots
+/// through `alerts`
+pub async fn monitor_directory(settings: &AppSpecificConfig, alerts: WatchAlerts) -> notify::Result<(UnboundedReceiver<WatchEvent>, WatchHandle)> {
+    let mut roots = watch_roots(settings).map_err(|err| notify::Error::generic(&err.to_string()))?;
+    let mut options = WatchOptions::new(settings, alerts.clone());
 
-pub async fn monitor_directory(
-    dir: PathType,
-    ignored_subdirs: Option<Vec<PathType>>,
-) -> notify::Result<UnboundedReceiver<Event>> {
     log!(
         LogLevel::Trace,
-        "Initializing directory watcher for path: {}",
-        dir
+        "Initializing directory watcher for {} path(s)",
+        roots.len()
     );
 
     let (watcher_tx, watcher_rx) = channel();
     let (event_tx, event_rx) = unbounded_channel();
+    let (control_tx, control_rx) = channel::<Reconfigure>();
 
-    // Wrap the watcher in an Arc<Mutex<>> to manage its lifetime
-    let watcher = LockWithTimeout::new(RecommendedWatcher::new(watcher_tx, Config::default())?);
-
-    // Start watching the directory
-    if let Ok(mut watcher) = watcher.try_write().await {
-        watcher.watch(&dir, RecursiveMode::Recursive)?;
-    } else {
-        log!(LogLevel::Error, "Never started watching directory");
+    // The thread owns the watcher, it adds directories created later on when limits are
+    // set and swaps in the polling backend when inotify runs out of watches
+    let mut watched: usize = 0;
+    let (mut watcher, mut polling) = start_watcher(&roots, &options, watcher_tx.clone(), &mut watched)?;
+    let mut truncated_reported = false;
+    let mut limit_reached = false;
+    let mut links = match options.follow_symlinks {
+        true => watch_symlink_targets(watcher.as_mut(), &roots),
+        false => Vec::new(),
     };
 
-    log!(LogLevel::Trace, "Started watching directory: {}", dir);
-
-    // Clone the Arc to move into the thread
-    let watcher_clone = watcher.clone();
-
-    // Normalize ignored subdirectory paths for comparison
-    let ignored_subdirs: Vec<PathType> = if let Some(subdirs) = ignored_subdirs {
-        subdirs
-        .into_iter()
-        .map(|path| PathType::PathBuf(dir.join(path))) // Convert to full paths relative to the monitored directory
-        .collect()
-    } else {
-        Vec::new()
-    };
+    // Remote changes are fed into the same channel as local ones
+    start_remote_scan(options.remote.clone(), event_tx.clone(), alerts.clone());
+    if let Some(primary) = roots.first().filter(|_| options.git_head_trigger) {
+        start_git_watch(primary.path.clone(), event_tx.clone(), alerts.clone());
+    }
 
     // Spawn a thread to forward events to the async channel
     log!(
@@ -54,31 +245,191 @@ pub async fn monitor_directory(
     thread::spawn(move || {
         log!(LogLevel::Trace, "Directory event handler thread started.");
 
+        let mut hashes = options.content_hash.then(|| ContentHashes::new(&roots));
+        let mut storm = StormGuard::new(options.storm_threshold);
+        let mut last_root_check = Instant::now();
+        let mut missing: Vec<PathType> = Vec::new();
+
         loop {
-            match watcher_rx.recv() {
+            // Swap in new roots between events, the old watcher goes away with its watches
+            while let Ok(Reconfigure { roots: new_roots, options: new_options }) = control_rx.try_recv() {
+                let new_options = new_options.unwrap_or_else(|| options.clone());
+                match start_watcher(&new_roots, &new_options, watcher_tx.clone(), &mut watched) {
+                    Ok((replacement, replacement_polls)) => {
+                        watcher = replacement;
+                        polling = replacement_polls;
+                        roots = new_roots;
+                        if new_options.storm_threshold != options.storm_threshold {
+                            storm = StormGuard::new(new_options.storm_threshold);
+                        }
+                        options = new_options;
+                        truncated_reported = false;
+                        limit_reached = false;
+                        links = match options.follow_symlinks {
+                            true => watch_symlink_targets(watcher.as_mut(), &roots),
+                            false => Vec::new(),
+                        };
+                        hashes = options.content_hash.then(|| ContentHashes::new(&roots));
+                        log!(LogLevel::Info, "Now watching {} root(s)", roots.len());
+                    }
+                    Err(err) => log!(LogLevel::Error, "Keeping the current watch roots, the new ones failed: {}", err),
+                }
+            }
+
+            // A deleted root or dropped mount takes its watches along, look for it
+            // coming back and watch it again from scratch
+            if last_root_check.elapsed() >= ROOT_CHECK_INTERVAL {
+                last_root_check = Instant::now();
+                let now_missing: Vec<PathType> = roots.iter().map(|root| root.path.clone()).filter(|path| !path.exists()).collect();
+
+                for path in now_missing.iter().filter(|path| !missing.contains(path)) {
+                    options.alerts.report(format!("Watched directory {} disappeared, waiting for it to come back", path));
+                }
+
+                if now_missing.is_empty() && !missing.is_empty() {
+                    match start_watcher(&roots, &options, watcher_tx.clone(), &mut watched) {
+                        Ok((replacement, replacement_polls)) => {
+                            watcher = replacement;
+                            polling = replacement_polls;
+                            truncated_reported = false;
+                            limit_reached = false;
+                            links = match options.follow_symlinks {
+                                true => watch_symlink_targets(watcher.as_mut(), &roots),
+                                false => Vec::new(),
+                            };
+                            hashes = options.content_hash.then(|| ContentHashes::new(&roots));
+                            log!(LogLevel::Info, "Watched directories are back, watching resumed");
+                            missing = now_missing;
+                        }
+                        Err(err) => log!(LogLevel::Warn, "Watched directories are back but can't be watched yet: {}", err),
+                    }
+                } else {
+                    missing = now_missing;
+                }
+
+                options.alerts.degraded.store(!missing.is_empty(), Ordering::Relaxed);
+            }
+
+            match watcher_rx.recv_timeout(Duration::from_millis(500)) {
+                Err(RecvTimeoutError::Timeout) => continue,
                 Ok(event) => match event {
-                    Ok(event) => {
+                    Ok(mut event) => {
                         log!(
                             LogLevel::Trace,
                             "Directory change event received: {:#?}",
                             event
                         );
 
-                        // Check if the event affects ignored subdirectories
-                        let should_ignore = event.paths.iter().any(|path| {
-                            ignored_subdirs.iter().any(|ignored| path.starts_with(ignored))
-                        });
+                        translate_links(&links, &mut event);
+
+                        let new_folder = matches!(event.kind, EventKind::Create(CreateKind::Folder));
+                        let mut exhausted = false;
+
+                        if options.limited() && new_folder {
+                            for dir in &event.paths {
+                                let Some(root) = root_of(&roots, dir) else {
+                                    continue;
+                                };
+
+                                match watch_limited(watcher.as_mut(), root, dir, &options, &mut watched) {
+                                    Ok(true) => (),
+                                    Ok(false) if !truncated_reported => {
+                                        truncated_reported = true;
+                                        report_truncated(&options, watched);
+                                    }
+                                    Ok(false) => (),
+                                    Err(err) if matches!(err.kind, ErrorKind::MaxFilesWatch) => exhausted = true,
+                                    Err(err) => log!(LogLevel::Error, "Failed to watch {}: {}", dir.display(), err),
+                                }
+                            }
+                        } else if new_folder && !polling && !limit_reached {
+                            // notify silently fails to watch new directories once inotify runs out of watches
+                            exhausted = event.paths.first().is_some_and(|dir| inotify_exhausted(dir));
+                        }
+
+                        if exhausted && !limit_reached {
+                            limit_reached = true;
+                            if options.backend == WatcherBackend::Auto {
+                                match poll_watcher(&roots, &options, watcher_tx.clone(), &mut watched) {
+                                    Ok(replacement) => {
+                                        watcher = replacement;
+                                        polling = true;
+                                        if options.follow_symlinks {
+                                            links = watch_symlink_targets(watcher.as_mut(), &roots);
+                                        }
+                                        report_watch_limit(&options, true);
+                                    }
+                                    Err(err) => {
+                                        log!(LogLevel::Error, "Failed to switch to polling: {}", err);
+                                        report_watch_limit(&options, false);
+                                    }
+                                }
+                            } else {
+                                report_watch_limit(&options, false);
+                            }
+                        }
 
-                        if should_ignore {
+                        // Find the root the event belongs to and check its ignores
+                        let Some(index) = classify(&roots, &event) else {
                             log!(
                                 LogLevel::Trace,
-                                "Ignoring event for ignored subdirectory: {:#?}",
+                                "Ignoring event for ignored path: {:#?}",
                                 event
                             );
                             continue;
+                        };
+
+                        // A save through a temp file is only finished once it was renamed over the target
+                        let window = match options.editor_dedup && event.paths.iter().any(|path| editor_target(path).as_deref() != Some(path)) {
+                            true => options.debounce.max(EDITOR_SAVE_WINDOW),
+                            false => options.debounce,
+                        };
+
+                        // An editor save arrives as a burst, fold it into one logical change per root
+                        let mut events = vec![(index, event)];
+                        let absorbed = absorb(&mut events, &watcher_rx, window, &roots, &links);
+
+                        // A storm is swallowed whole until it calms down and reported as one bulk change
+                        let bulk = storm.observe(absorbed + 1);
+                        if bulk {
+                            log!(LogLevel::Warn, "Event storm above {} events per second, collapsing it into one bulk change", options.storm_threshold);
+                            let collapsed = absorb(&mut events, &watcher_rx, STORM_QUIET_PERIOD, &roots, &links);
+                            log!(LogLevel::Info, "Event storm over after {} more events", collapsed);
+                        }
+
+                        if options.editor_dedup {
+                            for (_, event) in events.iter_mut() {
+                                dedup_editor_paths(event);
+                            }
+                            events.retain(|(_, event)| !event.paths.is_empty());
+                        }
+
+                        let mut closed = false;
+                        for (index, event) in events {
+                            let deploy = is_deploy(&roots[index], &event);
+
+                            // With the git trigger, files changed by a checkout don't count on their own
+                            if options.git_head_trigger && !deploy {
+                                log!(LogLevel::Trace, "Not counting file event, waiting for HEAD to move: {:?}", event.paths);
+                                continue;
+                            }
+
+                            // Rewrites with identical content aren't a change
+                            if let Some(hashes) = hashes.as_mut().filter(|_| !bulk && !deploy) {
+                                if hashes.unchanged(&event.paths) {
+                                    log!(LogLevel::Trace, "Ignoring event without content change: {:?}", event.paths);
+                                    continue;
+                                }
+                            }
+
+                            let root = roots[index].path.clone();
+                            if event_tx.send(WatchEvent { root, event, bulk, deploy }).is_err() {
+                                closed = true;
+                                break;
+                            }
                         }
 
-                        if event_tx.send(event).is_err() {
+                        if closed {
                             log!(
                                 LogLevel::Error,
                                 "Failed to send event: Event channel closed."
@@ -112,11 +463,475 @@ pub async fn monitor_directory(
         }
 
         // Drop the watcher explicitly when done
-        drop(watcher_clone);
+        drop(watcher);
 
         log!(LogLevel::Trace, "Directory event handler thread exiting.");
     });
 
     log!(LogLevel::Trace, "Returning event receiver to caller.");
-    Ok(event_rx)
+    let handle = WatchHandle {
+        control_tx,
+        settings: Arc::new(Mutex::new(settings.clone())),
+        alerts,
+    };
+    Ok((event_rx, handle))
+}
+
+/// Picks the backend and starts watching every root, returning whether it
+/// polls. In `auto` mode roots on network or fuse filesystems are polled, as
+/// are all roots when inotify can't be set up (e.g. out of watches).
+fn start_watcher(
+    roots: &[WatchRoot],
+    options: &WatchOptions,
+    watcher_tx: Sender<notify::Result<Event>>,
+    watched: &mut usize,
+) -> notify::Result<(Box<dyn Watcher + Send + Sync>, bool)> {
+    let poll = match options.backend {
+        WatcherBackend::Native => false,
+        WatcherBackend::Poll => true,
+        WatcherBackend::Auto => roots.iter().any(|root| {
+            let remote = on_network_filesystem(&root.path);
+            if remote {
+                log!(LogLevel::Info, "{} is on a network filesystem, polling it", root.path);
+            }
+            remote
+        }),
+    };
+
+    if !poll {
+        match native_watcher(roots, options, watcher_tx.clone(), watched) {
+            Ok(watcher) => return Ok((watcher, false)),
+            Err(err) if options.backend == WatcherBackend::Auto => {
+                log!(
+                    LogLevel::Warn,
+                    "Native file watching failed ({}), falling back to polling every {}s",
+                    err,
+                    options.poll_interval.as_secs()
+                );
+                if matches!(err.kind, ErrorKind::MaxFilesWatch) {
+                    report_watch_limit(options, true);
+                }
+            }
+            Err(err) => {
+                if matches!(err.kind, ErrorKind::MaxFilesWatch) {
+                    report_watch_limit(options, false);
+                }
+                return Err(err);
+            }
+        }
+    }
+
+    Ok((poll_watcher(roots, options, watcher_tx, watched)?, true))
+}
+
+fn poll_watcher(
+    roots: &[WatchRoot],
+    options: &WatchOptions,
+    watcher_tx: Sender<notify::Result<Event>>,
+    watched: &mut usize,
+) -> notify::Result<Box<dyn Watcher + Send + Sync>> {
+    let config = Config::default().with_poll_interval(options.poll_interval);
+    let mut watcher = PollWatcher::new(watcher_tx, config)?;
+    watch_all(&mut watcher, roots, options, watched)?;
+
+    Ok(Box::new(watcher))
+}
+
+fn native_watcher(
+    roots: &[WatchRoot],
+    options: &WatchOptions,
+    watcher_tx: Sender<notify::Result<Event>>,
+    watched: &mut usize,
+) -> notify::Result<Box<dyn Watcher + Send + Sync>> {
+    let mut watcher = RecommendedWatcher::new(watcher_tx, Config::default())?;
+    watch_all(&mut watcher, roots, options, watched)?;
+
+    Ok(Box::new(watcher))
+}
+
+fn watch_all(
+    watcher: &mut dyn Watcher,
+    roots: &[WatchRoot],
+    options: &WatchOptions,
+    watched: &mut usize,
+) -> notify::Result<()> {
+    *watched = 0;
+    let mut complete = true;
+
+    for root in roots {
+        if options.limited() {
+            complete &= watch_limited(watcher, root, &root.path, options, watched)?;
+        } else {
+            watcher.watch(&root.path, RecursiveMode::Recursive)?;
+        }
+        log!(LogLevel::Trace, "Started watching directory: {}", root.path);
+    }
+
+    if options.limited() {
+        log!(LogLevel::Debug, "Watching {} directories", watched);
+    }
+    if !complete {
+        report_truncated(options, *watched);
+    }
+
+    Ok(())
+}
+
+/// Watches `dir` and the directories below it one by one, skipping ignored
+/// trees and stopping at the depth and count limits. Returns false when a
+/// limit left directories unwatched.
+fn watch_limited(
+    watcher: &mut dyn Watcher,
+    root: &WatchRoot,
+    dir: &Path,
+    options: &WatchOptions,
+    watched: &mut usize,
+) -> notify::Result<bool> {
+    let mut complete = true;
+    let mut walker = WalkDir::new(dir).into_iter();
+
+    while let Some(entry) = walker.next() {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        if !entry.file_type().is_dir() {
+            continue;
+        }
+
+        if root.filter.ignores_tree(entry.path()) {
+            walker.skip_current_dir();
+            continue;
+        }
+
+        let depth = entry
+            .path()
+            .strip_prefix(&root.path)
+            .map(|relative| relative.components().count())
+            .unwrap_or(0);
+        if options.max_depth.is_some_and(|max| depth > max) {
+            complete = false;
+            walker.skip_current_dir();
+            continue;
+        }
+
+        if options.max_dirs.is_some_and(|max| *watched >= max) {
+            complete = false;
+            break;
+        }
+
+        match watcher.watch(entry.path(), RecursiveMode::NonRecursive) {
+            Ok(()) => *watched += 1,
+            Err(err) if matches!(err.kind, ErrorKind::MaxFilesWatch) => return Err(err),
+            // Vanished or unreadable directories don't stop the rest
+            Err(err) => log!(LogLevel::Debug, "Not watching {}: {}", entry.path().display(), err),
+        }
+    }
+
+    Ok(complete)
+}
+
+fn report_truncated(options: &WatchOptions, watched: usize) {
+    options.alerts.report(format!(
+        "Watch limits (max_watch_depth = {:?}, max_watched_dirs = {:?}) left part of the tree unwatched after {} directories",
+        options.max_depth, options.max_dirs, watched
+    ));
+}
+
+/// Whether another inotify watch can't be added, probed on a throwaway instance
+fn inotify_exhausted(dir: &Path) -> bool {
+    let Ok(inotify) = Inotify::init(InitFlags::IN_CLOEXEC) else {
+        return false;
+    };
+
+    matches!(inotify.add_watch(dir, AddWatchFlags::IN_ATTRIB), Err(Errno::ENOSPC))
+}
+
+fn report_watch_limit(options: &WatchOptions, polling: bool) {
+    let limit = fs::read_to_string("/proc/sys/fs/inotify/max_user_watches")
+        .map(|limit| limit.trim().to_owned())
+        .unwrap_or_else(|_| String::from("unknown"));
+
+    let consequence = match polling {
+        true => "switched to polling",
+        false => "changes in unwatched directories are missed",
+    };
+
+    options.alerts.report(format!(
+        "inotify watch limit reached (fs.inotify.max_user_watches = {}), {}. Raise the limit or set watcher = \"poll\"",
+        limit, consequence
+    ));
+}
+
+/// inotify only sees changes made through the local kernel, so it misses
+/// writes from other NFS/SMB clients and most fuse filesystems
+fn on_network_filesystem(path: &Path) -> bool {
+    const CIFS_MAGIC_NUMBER: FsType = FsType(0xFF534D42);
+    const SMB2_MAGIC_NUMBER: FsType = FsType(0xFE534D42);
+
+    match statfs(path) {
+        Ok(stat) => [
+            NFS_SUPER_MAGIC,
+            SMB_SUPER_MAGIC,
+            CIFS_MAGIC_NUMBER,
+            SMB2_MAGIC_NUMBER,
+            FUSE_SUPER_MAGIC,
+        ]
+        .contains(&stat.filesystem_type()),
+        Err(_) => false,
+    }
+}
+
+/// A symlink inside a root pointing outside of every root
+struct SymlinkTarget {
+    target: PathBuf,
+    link: PathBuf,
+}
+
+/// Watches the targets of symlinks that lead out of the roots, e.g. shared
+/// packages linked into the project. Links are collected once at startup.
+fn watch_symlink_targets(watcher: &mut dyn Watcher, roots: &[WatchRoot]) -> Vec<SymlinkTarget> {
+    let mut links: Vec<SymlinkTarget> = Vec::new();
+
+    for root in roots {
+        let walker = WalkDir::new(&root.path)
+            .into_iter()
+            .filter_entry(|entry| !root.filter.ignores_tree(entry.path()));
+
+        for entry in walker.flatten() {
+            if !entry.path_is_symlink() {
+                continue;
+            }
+
+            let Ok(target) = fs::canonicalize(entry.path()) else {
+                log!(LogLevel::Debug, "Skipping dangling symlink {}", entry.path().display());
+                continue;
+            };
+            if roots.iter().any(|root| target.starts_with(&root.path)) {
+                continue;
+            }
+            if links.iter().any(|link| link.target == target) {
+                continue;
+            }
+
+            let mode = match target.is_dir() {
+                true => RecursiveMode::Recursive,
+                false => RecursiveMode::NonRecursive,
+            };
+            match watcher.watch(&target, mode) {
+                Ok(()) => {
+                    log!(LogLevel::Trace, "Watching symlink target {} of {}", target.display(), entry.path().display());
+                    links.push(SymlinkTarget {
+                        target,
+                        link: entry.into_path(),
+                    });
+                }
+                Err(err) => log!(LogLevel::Warn, "Failed to watch {} behind {}: {}", target.display(), entry.path().display(), err),
+            }
+        }
+    }
+
+    links
+}
+
+/// The file an editor's temp, backup or swap file stands for, `None` for
+/// scratch files without one (vim's `4913` write probe, gedit's
+/// `.goutputstream-*`). Other paths are their own target.
+fn editor_target(path: &Path) -> Option<PathBuf> {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return Some(path.to_path_buf());
+    };
+
+    if name == "4913" || name.starts_with(".goutputstream-") {
+        return None;
+    }
+
+    let target = name
+        .strip_prefix(".#")
+        .or_else(|| name.strip_prefix('#').and_then(|name| name.strip_suffix('#')))
+        .or_else(|| {
+            [".swp", ".swo", ".swx"]
+                .iter()
+                .find_map(|suffix| name.strip_suffix(suffix))
+                .and_then(|name| name.strip_prefix('.'))
+        })
+        .or_else(|| name.strip_suffix("___jb_tmp___"))
+        .or_else(|| name.strip_suffix("___jb_old___"))
+        .or_else(|| name.strip_suffix(".tmp"))
+        .or_else(|| name.strip_suffix('~'));
+
+    match target {
+        Some(target) if !target.is_empty() => Some(path.with_file_name(target)),
+        _ => Some(path.to_path_buf()),
+    }
+}
+
+/// Folds an editor's temp file dance (write `foo.tmp`, rename it over `foo`)
+/// into a change of `foo` alone
+fn dedup_editor_paths(event: &mut Event) {
+    let mut targets: Vec<PathBuf> = Vec::with_capacity(event.paths.len());
+    for path in event.paths.drain(..) {
+        if let Some(target) = editor_target(&path) {
+            if !targets.contains(&target) {
+                targets.push(target);
+            }
+        }
+    }
+    event.paths = targets;
+}
+
+/// Rewrites paths below a symlink target to the link inside the root, so
+/// filters and thresholds see them where the project sees them
+fn translate_links(links: &[SymlinkTarget], event: &mut Event) {
+    for path in event.paths.iter_mut() {
+        let Some(link) = links.iter().find(|link| path.starts_with(&link.target)) else {
+            continue;
+        };
+
+        let rest = path.strip_prefix(&link.target).unwrap_or(Path::new(""));
+        *path = match rest.as_os_str().is_empty() {
+            true => link.link.clone(),
+            false => link.link.join(rest),
+        };
+    }
+}
+
+/// The innermost root containing `path`
+fn root_of<'a>(roots: &'a [WatchRoot], path: &Path) -> Option<&'a WatchRoot> {
+    roots
+        .iter()
+        .filter(|root| path.starts_with(&root.path))
+        .max_by_key(|root| root.path.components().count())
+}
+
+/// Index of the innermost root containing the event, or `None` when the
+/// event is outside every root, of a kind that doesn't count or hits one of
+/// its ignores.
+fn classify(roots: &[WatchRoot], event: &Event) -> Option<usize> {
+    let first = event.paths.first()?;
+    let (index, root) = roots
+        .iter()
+        .enumerate()
+        .filter(|(_, root)| first.starts_with(&root.path))
+        .max_by_key(|(_, root)| root.path.components().count())?;
+
+    // The marker counts however it was touched and whatever the filters say
+    if is_deploy(root, event) {
+        return Some(index);
+    }
+
+    if !root.filter.accepts_kind(&event.kind) {
+        return None;
+    }
+
+    if event.paths.iter().any(|path| root.filter.ignores(path)) {
+        return None;
+    }
+
+    Some(index)
+}
+
+/// Whether the event creates or writes the root's `deploy_marker`
+fn is_deploy(root: &WatchRoot, event: &Event) -> bool {
+    matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_) | EventKind::Any)
+        && event.paths.iter().any(|path| root.filter.is_deploy_marker(path))
+}
+
+/// Keeps absorbing events until none arrived for `window`, merging their
+/// paths into one event per root. Ignored paths don't extend the window.
+/// Returns how many events were absorbed.
+fn absorb(
+    events: &mut Vec<(usize, Event)>,
+    watcher_rx: &Receiver<notify::Result<Event>>,
+    window: Duration,
+    roots: &[WatchRoot],
+    links: &[SymlinkTarget],
+) -> usize {
+    if window.is_zero() {
+        return 0;
+    }
+
+    let mut merged: usize = 0;
+    let mut deadline = Instant::now() + window;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+        let Ok(next) = watcher_rx.recv_timeout(remaining) else {
+            break;
+        };
+        let mut next = match next {
+            Ok(next) => next,
+            Err(e) => {
+                log!(LogLevel::Error, "Error receiving event from watcher: {:?}", e);
+                continue;
+            }
+        };
+
+        translate_links(links, &mut next);
+        let Some(index) = classify(roots, &next) else {
+            continue;
+        };
+
+        match events.iter_mut().find(|(existing, _)| *existing == index) {
+            Some((_, event)) => {
+                for path in next.paths {
+                    // A storm can touch thousands of files, a sample is enough to report
+                    if event.paths.len() >= MAX_EVENT_PATHS {
+                        break;
+                    }
+                    if !event.paths.contains(&path) {
+                        event.paths.push(path);
+                    }
+                }
+            }
+            None => events.push((index, next)),
+        }
+        merged += 1;
+        deadline = Instant::now() + window;
+    }
+
+    if merged > 0 {
+        log!(LogLevel::Trace, "Coalesced {} follow up events into {} change(s)", merged, events.len());
+    }
+
+    merged
+}
+
+/// Counts events per second to spot storms such as `rm -rf node_modules`
+struct StormGuard {
+    threshold: usize,
+    window_start: Instant,
+    seen: usize,
+}
+
+impl StormGuard {
+    fn new(threshold: usize) -> Self {
+        Self {
+            threshold,
+            window_start: Instant::now(),
+            seen: 0,
+        }
+    }
+
+    /// Adds `events` to the current second, true once they exceed the threshold
+    fn observe(&mut self, events: usize) -> bool {
+        if self.threshold == 0 {
+            return false;
+        }
+
+        if self.window_start.elapsed() >= Duration::from_secs(1) {
+            self.window_start = Instant::now();
+            self.seen = 0;
+        }
+
+        self.seen += events;
+        if self.seen > self.threshold {
+            self.seen = 0;
+            self.window_start = Instant::now();
+            return true;
+        }
+
+        false
+    }
 }
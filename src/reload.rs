@@ -0,0 +1,191 @@
+use artisan_middleware::timestamp::current_timestamp;
+use dusa_collection_utils::log;
+use dusa_collection_utils::log::LogLevel;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::channel,
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::config::{config_file, drop_in_dir, specific_config, AppSpecificConfig};
+
+/// Reloads kept in the history next to the state file
+const KEPT_RELOADS: usize = 50;
+
+/// One line of the reload history
+#[derive(Serialize)]
+struct ReloadRecord<'a> {
+    timestamp: u64,
+    changes: &'a [String],
+}
+
+/// Marks the runner for reload, like SIGHUP does, whenever the Config file
+/// or a snippet in its drop-in directory changes. Its directory is watched
+/// rather than the file so editors that save by renaming over it are still
+/// seen.
+pub fn config_watch(settings: &AppSpecificConfig, reload: Arc<AtomicBool>) {
+    if !settings.reload_on_config_change {
+        return;
+    }
+
+    let debounce = Duration::from_millis(settings.config_debounce_ms);
+    let name = settings.name.clone();
+    let config = config_file();
+    let directory = match config.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let (tx, rx) = channel();
+
+    let mut watcher = match RecommendedWatcher::new(tx, notify::Config::default()) {
+        Ok(watcher) => watcher,
+        Err(err) => {
+            log!(LogLevel::Warn, "Couldn't watch the Config file, reload with SIGHUP instead: {}", err);
+            return;
+        }
+    };
+
+    if let Err(err) = watcher.watch(&directory, RecursiveMode::NonRecursive) {
+        log!(LogLevel::Warn, "Couldn't watch the Config file, reload with SIGHUP instead: {}", err);
+        return;
+    }
+
+    // A drop-in directory created later is only picked up by a restart
+    let drop_ins = drop_in_dir(&config);
+    if drop_ins.is_dir() {
+        if let Err(err) = watcher.watch(&drop_ins, RecursiveMode::NonRecursive) {
+            log!(LogLevel::Warn, "Couldn't watch {}, reload with SIGHUP after changing it: {}", drop_ins.display(), err);
+        }
+    }
+
+    thread::spawn(move || {
+        // The watcher stops once dropped
+        let _watcher = watcher;
+
+        while let Ok(event) = rx.recv() {
+            if !touches_config(&event, &config, &drop_ins) {
+                continue;
+            }
+
+            // Wait for the writer to finish before reading the file
+            while rx.recv_timeout(debounce).is_ok() {}
+
+            if let Err(err) = specific_config(name.as_deref()) {
+                log!(LogLevel::Warn, "Config file changed but doesn't load, keeping the current settings: {}", err);
+                continue;
+            }
+
+            reload.store(true, Ordering::Relaxed);
+            log!(LogLevel::Info, "Config file changed, marked for reload");
+        }
+    });
+}
+
+fn touches_config(event: &notify::Result<Event>, config: &Path, drop_ins: &Path) -> bool {
+    let event = match event {
+        Ok(event) => event,
+        Err(err) => {
+            log!(LogLevel::Debug, "Config watcher error: {:?}", err);
+            return false;
+        }
+    };
+
+    if matches!(event.kind, EventKind::Access(_)) {
+        return false;
+    }
+
+    // Without an extension the config crate looks for the name with any supported one
+    event.paths.iter().any(|path| match config.extension() {
+        _ if path.parent() == Some(drop_ins) => true,
+        Some(_) => path.file_name() == config.file_name(),
+        None => path.file_stem() == config.file_name(),
+    })
+}
+
+/// Every field that differs between two loads of the settings, as
+/// `field: old → new` with defaults applied. Fields holding a secret only
+/// say that they changed.
+pub fn settings_diff(old: &AppSpecificConfig, new: &AppSpecificConfig) -> Vec<String> {
+    let mut old_fields = BTreeMap::new();
+    let mut new_fields = BTreeMap::new();
+    flatten(serde_json::to_value(old).unwrap_or_default(), String::new(), &mut old_fields);
+    flatten(serde_json::to_value(new).unwrap_or_default(), String::new(), &mut new_fields);
+
+    let fields: BTreeSet<&String> = old_fields.keys().chain(new_fields.keys()).collect();
+    fields
+        .into_iter()
+        .filter(|field| old_fields.get(*field) != new_fields.get(*field))
+        .map(|field| {
+            let secret = old.secret_keys.iter().chain(&new.secret_keys).any(|key| {
+                key == field || key.strip_prefix(field.as_str()).is_some_and(|rest| rest.starts_with('['))
+            });
+            match secret {
+                true => format!("{}: changed (secret)", field),
+                false => format!("{}: {} → {}", field, shown(old_fields.get(field)), shown(new_fields.get(field))),
+            }
+        })
+        .collect()
+}
+
+/// Nested tables become dotted fields, lists are compared whole
+fn flatten(value: Value, prefix: String, fields: &mut BTreeMap<String, Value>) {
+    match value {
+        Value::Object(table) => {
+            for (name, value) in table {
+                let field = match prefix.is_empty() {
+                    true => name,
+                    false => format!("{}.{}", prefix, name),
+                };
+                flatten(value, field, fields);
+            }
+        }
+        value => {
+            fields.insert(prefix, value);
+        }
+    }
+}
+
+fn shown(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => String::from("unset"),
+        Some(value) => value.to_string(),
+    }
+}
+
+/// The reload history is kept next to the state file
+pub fn reload_history_path(state_path: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.reloads", state_path.display()))
+}
+
+/// Appends a reload and the fields it changed to the reload history, one
+/// JSON object per line, keeping the last `KEPT_RELOADS`
+pub fn record_reload(state_path: &Path, changes: &[String]) {
+    let path = reload_history_path(state_path);
+    let mut lines: Vec<String> = fs::read_to_string(&path)
+        .map(|contents| contents.lines().map(str::to_owned).collect())
+        .unwrap_or_default();
+
+    let record = ReloadRecord { timestamp: current_timestamp(), changes };
+    match serde_json::to_string(&record) {
+        Ok(line) => lines.push(line),
+        Err(err) => {
+            log!(LogLevel::Warn, "Couldn't record the reload: {}", err);
+            return;
+        }
+    }
+
+    let kept = &lines[lines.len().saturating_sub(KEPT_RELOADS)..];
+    if let Err(err) = fs::write(&path, format!("{}\n", kept.join("\n"))) {
+        log!(LogLevel::Warn, "Couldn't record the reload in {}: {}", path.display(), err);
+    }
+}
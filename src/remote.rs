@@ -0,0 +1,125 @@
+use dusa_collection_utils::log;
+use dusa_collection_utils::log::LogLevel;
+use dusa_collection_utils::types::PathType;
+use notify::event::{EventKind, ModifyKind};
+use notify::Event;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::Command,
+    thread,
+    time::Duration,
+};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::{config::RemoteWatchConfig, monitor::{WatchAlerts, WatchEvent}};
+
+/// Lists every remote file as `<crc> <size> <path>`, ignored directories are pruned
+fn manifest_command(remote: &RemoteWatchConfig) -> String {
+    let prune: Vec<String> = remote
+        .ignored_subdirs
+        .iter()
+        .map(|dir| format!("-path {}", quote(&format!("./{}", dir.trim_end_matches('/')))))
+        .collect();
+
+    match prune.is_empty() {
+        true => format!("cd {} && find . -type f -exec cksum {{}} +", quote(&remote.path)),
+        false => format!(
+            "cd {} && find . \\( {} \\) -prune -o -type f -exec cksum {{}} +",
+            quote(&remote.path),
+            prune.join(" -o ")
+        ),
+    }
+}
+
+/// Compares a checksum listing of the remote tree every `interval_seconds`
+/// and sends the differences down the same channel as local events. The
+/// first listing is the baseline, a failed one is reported and retried.
+pub fn start_remote_scan(remote: RemoteWatchConfig, event_tx: UnboundedSender<WatchEvent>, alerts: WatchAlerts) {
+    let host = match &remote.host {
+        Some(host) => host.clone(),
+        None => return,
+    };
+
+    let root = PathBuf::from(&remote.path);
+    let interval = Duration::from_secs(remote.interval_seconds.max(1));
+    let command = manifest_command(&remote);
+    log!(LogLevel::Info, "Scanning {}:{} every {}s", host, remote.path, interval.as_secs());
+
+    thread::spawn(move || {
+        let mut previous: Option<HashMap<PathBuf, String>> = None;
+
+        loop {
+            match list_remote(&host, &remote.ssh_args, &command) {
+                Ok(current) => {
+                    if let Some(previous) = &previous {
+                        let changed = differences(previous, &current);
+                        if !changed.is_empty() {
+                            log!(LogLevel::Debug, "{} remote file(s) changed on {}", changed.len(), host);
+                            let mut event = Event::new(EventKind::Modify(ModifyKind::Any));
+                            event.paths = changed.iter().map(|path| root.join(path)).collect();
+                            let event = WatchEvent {
+                                root: PathType::PathBuf(root.clone()),
+                                event,
+                                bulk: false,
+                                deploy: false,
+                            };
+                            if event_tx.send(event).is_err() {
+                                log!(LogLevel::Debug, "Event receiver dropped, stopping the remote scan");
+                                break;
+                            }
+                        }
+                    }
+                    previous = Some(current);
+                }
+                Err(err) => alerts.report(format!("Remote scan of {}:{} failed: {}", host, remote.path, err)),
+            }
+
+            thread::sleep(interval);
+        }
+    });
+}
+
+fn list_remote(host: &str, ssh_args: &[String], command: &str) -> Result<HashMap<PathBuf, String>, String> {
+    let output = Command::new("ssh")
+        .args(["-o", "BatchMode=yes", "-o", "ConnectTimeout=10"])
+        .args(ssh_args)
+        .arg(host)
+        .arg(command)
+        .output()
+        .map_err(|err| err.to_string())?;
+
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_owned());
+    }
+
+    let mut manifest = HashMap::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        // cksum prints `<crc> <size> <path>`, the path may contain spaces
+        let mut fields = line.splitn(3, ' ');
+        let (Some(crc), Some(size), Some(path)) = (fields.next(), fields.next(), fields.next()) else {
+            continue;
+        };
+        let path = Path::new(path.trim_start_matches("./")).to_path_buf();
+        manifest.insert(path, format!("{} {}", crc, size));
+    }
+
+    Ok(manifest)
+}
+
+/// Paths added, removed or with a different checksum
+fn differences(previous: &HashMap<PathBuf, String>, current: &HashMap<PathBuf, String>) -> Vec<PathBuf> {
+    let mut changed: Vec<PathBuf> = current
+        .iter()
+        .filter(|(path, sum)| previous.get(*path) != Some(*sum))
+        .map(|(path, _)| path.clone())
+        .collect();
+
+    changed.extend(previous.keys().filter(|path| !current.contains_key(*path)).cloned());
+    changed.sort();
+    changed
+}
+
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
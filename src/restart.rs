@@ -0,0 +1,159 @@
+use dusa_collection_utils::log;
+use dusa_collection_utils::log::LogLevel;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use crate::config::AppSpecificConfig;
+
+/// Keeps track of how often the child dies so respawns can be backed off
+/// instead of hammering the one shot build in a tight loop, and given up on
+/// entirely once `max_restarts` is exceeded within `restart_window_seconds`.
+pub struct CrashTracker {
+    consecutive_failures: u32,
+    spawned_at: Instant,
+    respawn_at: Option<Instant>,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    reset_after: Duration,
+    exits: VecDeque<Instant>,
+    max_restarts: Option<u32>,
+    restart_window: Duration,
+    failed: bool,
+    finished: bool,
+}
+
+impl CrashTracker {
+    pub fn new(settings: &AppSpecificConfig) -> Self {
+        Self {
+            consecutive_failures: 0,
+            spawned_at: Instant::now(),
+            respawn_at: None,
+            initial_backoff: Duration::from_secs(settings.restart_backoff_seconds),
+            max_backoff: Duration::from_secs(settings.restart_backoff_max_seconds),
+            reset_after: Duration::from_secs(settings.restart_backoff_reset_seconds),
+            exits: VecDeque::new(),
+            max_restarts: settings.max_restarts,
+            restart_window: Duration::from_secs(settings.restart_window_seconds),
+            failed: false,
+            finished: false,
+        }
+    }
+
+    /// Call whenever a fresh child has been spawned
+    pub fn record_spawn(&mut self) {
+        self.spawned_at = Instant::now();
+        self.respawn_at = None;
+    }
+
+    /// Forgets all crash history, used when an operator or a deploy restarts the child on purpose
+    pub fn reset(&mut self) {
+        self.consecutive_failures = 0;
+        self.exits.clear();
+        self.failed = false;
+        self.finished = false;
+        self.record_spawn();
+    }
+
+    /// Call once when the child is found dead, returns how long to wait before respawning
+    pub fn record_exit(&mut self) -> Duration {
+        let uptime = self.spawned_at.elapsed();
+
+        let now = Instant::now();
+        self.exits.push_back(now);
+        while let Some(oldest) = self.exits.front() {
+            if now.duration_since(*oldest) > self.restart_window {
+                self.exits.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        if let Some(max) = self.max_restarts {
+            if self.exits.len() > max as usize {
+                self.failed = true;
+            }
+        }
+
+        // A child that stayed up long enough isn't crash looping
+        if uptime >= self.reset_after {
+            self.consecutive_failures = 0;
+        }
+        self.consecutive_failures += 1;
+
+        let exponent = (self.consecutive_failures - 1).min(16);
+        let delay = self
+            .initial_backoff
+            .saturating_mul(2u32.pow(exponent))
+            .min(self.max_backoff);
+
+        log!(
+            LogLevel::Info,
+            "Child exited after {}s, failure {} in a row, respawning in {}s",
+            uptime.as_secs(),
+            self.consecutive_failures,
+            delay.as_secs()
+        );
+
+        self.respawn_at = Some(Instant::now() + delay);
+        delay
+    }
+
+    /// Call when the restart policy says the dead child stays down, only
+    /// `reset` (a deploy or an operator) starts it again.
+    pub fn record_stop(&mut self) {
+        log!(LogLevel::Info, "Child exited after {}s, not restarting it", self.spawned_at.elapsed().as_secs());
+        self.finished = true;
+        self.respawn_at = Some(Instant::now());
+    }
+
+    /// Gives up on the child without it having crashed, e.g. after it broke a resource limit
+    pub fn mark_failed(&mut self) {
+        self.failed = true;
+        self.respawn_at = Some(Instant::now());
+    }
+
+    /// True between `record_exit` and the next `record_spawn`
+    pub fn awaiting_respawn(&self) -> bool {
+        self.respawn_at.is_some()
+    }
+
+    pub fn respawn_due(&self) -> bool {
+        if self.failed || self.finished {
+            return false;
+        }
+
+        match self.respawn_at {
+            Some(at) => Instant::now() >= at,
+            None => true,
+        }
+    }
+
+    /// True within `grace` of the last spawn, while the child is still booting
+    pub fn starting(&self, grace: Duration) -> bool {
+        !self.awaiting_respawn() && self.spawned_at.elapsed() < grace
+    }
+
+    pub fn consecutive_failures(&self) -> u32 {
+        self.consecutive_failures
+    }
+
+    /// True once the restart limit was hit, no more respawns happen until `reset`
+    pub fn failed(&self) -> bool {
+        self.failed
+    }
+
+    /// True after `record_stop` until the next `reset`
+    pub fn finished(&self) -> bool {
+        self.finished
+    }
+
+    pub fn recent_exits(&self) -> usize {
+        self.exits.len()
+    }
+
+    pub fn restart_window(&self) -> Duration {
+        self.restart_window
+    }
+}
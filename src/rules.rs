@@ -0,0 +1,120 @@
+use dusa_collection_utils::log;
+use dusa_collection_utils::log::LogLevel;
+use globset::{GlobBuilder, GlobMatcher};
+use std::path::{Path, PathBuf};
+
+use crate::config::{AppSpecificConfig, WatchRule};
+
+/// Paths kept per rule for the log line of its action
+const LISTED_PATHS: usize = 10;
+
+/// Counts changes for every `watch_rules` entry. A path claimed by a rule
+/// doesn't count towards `changes_needed` too.
+pub struct WatchRules {
+    rules: Vec<CountedRule>,
+}
+
+struct CountedRule {
+    rule: WatchRule,
+    glob: Option<GlobMatcher>,
+    count: i32,
+    paths: Vec<PathBuf>, // Distinct changed paths relative to their root, capped at LISTED_PATHS
+}
+
+impl WatchRules {
+    pub fn new(settings: &AppSpecificConfig) -> Self {
+        let rules = settings
+            .watch_rules
+            .iter()
+            .filter_map(|rule| {
+                let glob = match rule.pattern.as_deref().map(rule_glob) {
+                    Some(Ok(glob)) => Some(glob),
+                    Some(Err(err)) => {
+                        log!(LogLevel::Error, "Ignoring watch rule {}, its pattern is invalid: {}", rule.name, err);
+                        return None;
+                    }
+                    None => None,
+                };
+                Some(CountedRule { rule: rule.clone(), glob, count: 0, paths: Vec::new() })
+            })
+            .collect();
+
+        Self { rules }
+    }
+
+    /// Counts the change once for every rule its paths match. Returns the
+    /// paths no rule matched, they are left to `changes_needed`.
+    pub fn record(&mut self, root: &Path, paths: &[PathBuf]) -> Vec<PathBuf> {
+        let mut unclaimed = Vec::new();
+        let mut touched = vec![false; self.rules.len()];
+
+        for path in paths {
+            let mut claimed = false;
+            for (rule, touched) in self.rules.iter_mut().zip(touched.iter_mut()) {
+                if !rule.matches(root, path) {
+                    continue;
+                }
+                claimed = true;
+                *touched = true;
+
+                let relative = path.strip_prefix(root).unwrap_or(path).to_path_buf();
+                if rule.paths.len() < LISTED_PATHS && !rule.paths.contains(&relative) {
+                    rule.paths.push(relative);
+                }
+            }
+
+            if !claimed {
+                unclaimed.push(path.clone());
+            }
+        }
+
+        for (rule, touched) in self.rules.iter_mut().zip(touched) {
+            if touched {
+                rule.count += 1;
+            }
+        }
+
+        unclaimed
+    }
+
+    /// The rules that reached their threshold with the paths that got them
+    /// there, relative to their root. Their counts start over.
+    pub fn take_due(&mut self) -> Vec<(WatchRule, Vec<PathBuf>)> {
+        self.rules
+            .iter_mut()
+            .filter(|rule| rule.count >= rule.rule.changes_needed)
+            .map(|rule| {
+                rule.count = 0;
+                (rule.rule.clone(), std::mem::take(&mut rule.paths))
+            })
+            .collect()
+    }
+
+    /// e.g. `assets: 2 out of 3, migrations: 0 out of 1`
+    pub fn summary(&self) -> String {
+        self.rules
+            .iter()
+            .map(|rule| format!("{}: {} out of {}", rule.rule.name, rule.count, rule.rule.changes_needed))
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+}
+
+impl CountedRule {
+    /// Relative rule paths are resolved against the root the change was
+    /// seen under, the pattern is matched below the rule's path
+    fn matches(&self, root: &Path, path: &Path) -> bool {
+        let Ok(relative) = path.strip_prefix(root.join(&self.rule.path)) else {
+            return false;
+        };
+        match &self.glob {
+            Some(glob) => glob.is_match(relative),
+            None => true,
+        }
+    }
+}
+
+/// `*` stays within one directory, `**` crosses them, like the ignore globs
+pub fn rule_glob(pattern: &str) -> Result<GlobMatcher, globset::Error> {
+    GlobBuilder::new(pattern).literal_separator(true).build().map(|glob| glob.compile_matcher())
+}
@@ -0,0 +1,179 @@
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{fs, path::PathBuf};
+
+use crate::{
+    cli::cli_args,
+    config::AppSpecificConfig,
+    fields::{config_types, enum_variants, struct_fields, FieldInfo, FieldType, TypeKind},
+};
+
+/// Exit codes of `init`
+pub const INIT_OK: i32 = 0;
+pub const INIT_FAILED: i32 = 1;
+
+/// The default settings, with stand-ins for the fields that have no default
+pub fn sample_settings() -> AppSpecificConfig {
+    let required = json!({
+        "interval_seconds": 30,
+        "monitor_path": "/srv/app",
+        "project_path": "/srv/app",
+        "changes_needed": 10,
+        "ignored_subdirs": [".git", "node_modules"],
+    });
+    AppSpecificConfig::deserialize(required).expect("the sample settings deserialize")
+}
+
+/// Writes the sample Config to `--config <path>`, or `Config.toml` in the
+/// working directory, without replacing an existing file. Returns the exit
+/// code for `ais_generic init`.
+pub fn run_init() -> i32 {
+    let mut path = cli_args().config.clone().unwrap_or_else(|| PathBuf::from("Config"));
+    if path.extension().is_none() {
+        path.set_extension("toml");
+    }
+
+    if path.exists() {
+        eprintln!("{} already exists, move it away to write a new sample", path.display());
+        return INIT_FAILED;
+    }
+
+    match fs::write(&path, sample_config()) {
+        Ok(()) => {
+            println!("Wrote a sample Config to {}, fill in the required fields and remove what you don't need", path.display());
+            INIT_OK
+        }
+        Err(err) => {
+            eprintln!("Couldn't write {}: {}", path.display(), err);
+            INIT_FAILED
+        }
+    }
+}
+
+/// Every field of `AppSpecificConfig` with its comment and default, read off
+/// the config structs. Fields without a default are marked as required and
+/// hold placeholders, unset optional fields are commented out.
+pub fn sample_config() -> String {
+    let values = serde_json::to_value(sample_settings()).unwrap_or_default();
+
+    let mut sample = String::from(
+        "# Sample Config written by `ais_generic init`, every setting with its default.\n\
+         # Settings marked as required have to be filled in, anything else can be removed.\n\
+         # Profiles go in [app_specific.<profile>] tables and further applications in\n\
+         # [[applications]] entries, see the README.\n",
+    );
+    write_table(&mut sample, "app_specific", "AppSpecificConfig", &values, "");
+    sample
+}
+
+/// Writes the scalar fields of `type_name` first and its nested tables after
+/// them, as TOML wants. `prefix` comments everything out.
+fn write_table(sample: &mut String, path: &str, type_name: &str, values: &Value, prefix: &str) {
+    if let Some(doc) = config_types().get(type_name).and_then(|info| info.doc.as_ref()) {
+        sample.push_str(&format!("\n# {}\n", doc));
+    } else {
+        sample.push('\n');
+    }
+    sample.push_str(&format!("{}[{}]\n", prefix, path));
+
+    let mut nested: Vec<&FieldInfo> = Vec::new();
+    for field in struct_fields(type_name).iter().filter(|field| !field.skip) {
+        if table_type(&field.ty).is_some() || list_table_type(&field.ty).is_some() {
+            nested.push(field);
+            continue;
+        }
+
+        write_comments(sample, field);
+        match values.get(&field.name).filter(|value| !value.is_null()) {
+            Some(value) => sample.push_str(&format!("{}{} = {}\n", prefix, field.name, toml_value(value))),
+            None => sample.push_str(&format!("# {} = {}\n", field.name, example(&field.ty))),
+        }
+    }
+
+    for field in nested {
+        let field_path = format!("{}.{}", path, field.name);
+        if let Some(nested_type) = table_type(&field.ty) {
+            let nested_values = values.get(&field.name).cloned().unwrap_or_default();
+            let prefix = match nested_values.is_null() {
+                true => "# ",
+                false => prefix,
+            };
+            write_table(sample, &field_path, nested_type, &nested_values, prefix);
+        } else if let Some(item_type) = list_table_type(&field.ty) {
+            sample.push('\n');
+            write_comments(sample, field);
+            sample.push_str(&format!("# [[{}]]\n", field_path));
+            for item_field in struct_fields(item_type).iter().filter(|field| !field.skip) {
+                let comment = item_field.comment.as_ref().map(|comment| format!(" # {}", comment)).unwrap_or_default();
+                sample.push_str(&format!("# {} = {}{}\n", item_field.name, example(&item_field.ty), comment));
+            }
+        }
+    }
+}
+
+fn write_comments(sample: &mut String, field: &FieldInfo) {
+    if let Some(comment) = &field.comment {
+        sample.push_str(&format!("# {}\n", comment));
+    }
+
+    if let FieldType::Named(name) = field.ty.inner() {
+        let variants = enum_variants(name);
+        if !variants.is_empty() {
+            let choices: Vec<String> = variants
+                .iter()
+                .map(|variant| match &variant.comment {
+                    Some(comment) => format!("\"{}\" ({})", variant.name, comment),
+                    None => format!("\"{}\"", variant.name),
+                })
+                .collect();
+            sample.push_str(&format!("# One of {}\n", choices.join(", ")));
+        }
+    }
+
+    if field.required {
+        sample.push_str("# Required\n");
+    }
+}
+
+/// The struct behind a field that is written as its own table
+fn table_type(ty: &FieldType) -> Option<&str> {
+    match ty.inner() {
+        FieldType::Named(name) if matches!(config_types().get(name), Some(info) if matches!(info.kind, TypeKind::Struct(_))) => {
+            Some(name)
+        }
+        _ => None,
+    }
+}
+
+/// The struct behind a list written as `[[...]]` tables
+fn list_table_type(ty: &FieldType) -> Option<&str> {
+    match ty.inner() {
+        FieldType::List(item) => table_type(item),
+        _ => None,
+    }
+}
+
+fn toml_value(value: &Value) -> String {
+    toml::Value::try_from(value)
+        .map(|value| value.to_string())
+        .unwrap_or_else(|_| value.to_string())
+}
+
+/// A value of the right type for settings that are unset by default
+fn example(ty: &FieldType) -> String {
+    match ty.inner() {
+        FieldType::Bool => String::from("false"),
+        FieldType::Integer { .. } => String::from("0"),
+        FieldType::Text => String::from("\"\""),
+        FieldType::List(_) => String::from("[]"),
+        FieldType::Map(_) => String::from("{}"),
+        FieldType::Named(name) => {
+            let variants = enum_variants(name);
+            match variants.iter().find(|variant| variant.default).or(variants.first()) {
+                Some(variant) => format!("\"{}\"", variant.name),
+                None => String::from("{}"),
+            }
+        }
+        FieldType::Optional(inner) => example(inner),
+    }
+}
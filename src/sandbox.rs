@@ -0,0 +1,301 @@
+use dusa_collection_utils::log;
+use dusa_collection_utils::log::LogLevel;
+use nix::{
+    libc,
+    sys::resource::{getrlimit, setrlimit, Resource},
+    unistd::{setgid, setgroups, setuid, Group, Uid, User},
+};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+use tokio::process::Command;
+
+use crate::config::{AppSpecificConfig, IoClass, LimitsConfig, PriorityConfig};
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+const CPU_PERIOD_US: u64 = 100_000;
+
+/// Where and how tightly a single child gets confined, see `child_cgroup`
+pub struct CgroupTarget {
+    pub path: PathBuf,
+    pub memory_mb: Option<u64>,
+    pub cpu_percent: Option<u32>,
+}
+
+/// Every child gets its own leaf under the configured cgroup, `<cgroup>/instance-<n>`,
+/// so the limits hold per child and the parent only aggregates. `memory.max`
+/// falls back to the app's `max_ram_usage` when `memory_mb` isn't set.
+pub fn child_cgroup(limits: &LimitsConfig, max_ram_usage: u64, index: u16) -> Option<CgroupTarget> {
+    let name = limits.cgroup.as_ref()?;
+
+    let memory_mb = match limits.memory_mb {
+        Some(mb) => Some(mb),
+        None if max_ram_usage > 0 => Some(max_ram_usage),
+        None => None,
+    };
+
+    Some(CgroupTarget {
+        path: cgroup_path(name).join(format!("instance-{}", index)),
+        memory_mb,
+        cpu_percent: limits.cpu_percent,
+    })
+}
+
+/// Prepares the limits table and installs it on the command, the one shot
+/// build passes no cgroup. Anything the runner lacks permission to do is
+/// logged and skipped.
+pub fn apply_limits(command: &mut Command, limits: &LimitsConfig, cgroup: Option<CgroupTarget>) {
+    let rlimits = rlimit_list(limits);
+    let cgroup_procs: Option<File> = match &cgroup {
+        Some(target) => prepare_cgroup(target),
+        None => None,
+    };
+
+    // Formatted up front, nothing may allocate after the fork
+    let oom_score_adj: Option<Vec<u8>> = limits
+        .oom_score_adj
+        .map(|score| score.clamp(-1000, 1000).to_string().into_bytes());
+
+    if rlimits.is_empty() && cgroup_procs.is_none() && oom_score_adj.is_none() {
+        return;
+    }
+
+    // Only async-signal-safe work happens in here, the cgroup file is opened beforehand
+    unsafe {
+        command.pre_exec(move || {
+            if let Some(procs) = &cgroup_procs {
+                let mut procs: &File = procs;
+                // Writing 0 moves the calling process, which is the child pre exec
+                let _ = procs.write_all(b"0");
+            }
+
+            if let Some(score) = &oom_score_adj {
+                // Lowering the score needs CAP_SYS_RESOURCE, a refusal isn't worth failing the spawn over
+                let fd = libc::open(c"/proc/self/oom_score_adj".as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+                if fd >= 0 {
+                    libc::write(fd, score.as_ptr() as *const libc::c_void, score.len());
+                    libc::close(fd);
+                }
+            }
+
+            for (resource, value) in &rlimits {
+                setrlimit(*resource, *value, *value)?;
+            }
+
+            Ok(())
+        });
+    }
+}
+
+fn rlimit_list(limits: &LimitsConfig) -> Vec<(Resource, u64)> {
+    let mut rlimits = Vec::new();
+
+    if let Some(mb) = limits.address_space_mb {
+        rlimits.push((Resource::RLIMIT_AS, mb * 1024 * 1024));
+    }
+    if let Some(nofile) = limits.nofile {
+        rlimits.push((Resource::RLIMIT_NOFILE, nofile));
+    }
+    if let Some(nproc) = limits.nproc {
+        rlimits.push((Resource::RLIMIT_NPROC, nproc));
+    }
+    if let Some(core) = limits.core {
+        rlimits.push((Resource::RLIMIT_CORE, core));
+    }
+
+    rlimits
+        .into_iter()
+        .map(|(resource, value)| (resource, clamp_to_hard_limit(resource, value)))
+        .collect()
+}
+
+/// Unprivileged runners can't raise a hard limit, so requests above it are lowered
+fn clamp_to_hard_limit(resource: Resource, value: u64) -> u64 {
+    if Uid::effective().is_root() {
+        return value;
+    }
+
+    match getrlimit(resource) {
+        Ok((_, hard)) if value > hard => {
+            log!(LogLevel::Warn, "Requested {:?} of {} is above the hard limit, using {}", resource, value, hard);
+            hard
+        }
+        _ => value,
+    }
+}
+
+fn cgroup_path(name: &str) -> PathBuf {
+    PathBuf::from(CGROUP_ROOT).join(name.trim_matches('/'))
+}
+
+/// Creates (or joins) the child's cgroup, writes its limits and returns an
+/// open handle to its `cgroup.procs` file.
+fn prepare_cgroup(target: &CgroupTarget) -> Option<File> {
+    let root = PathBuf::from(CGROUP_ROOT);
+    if !root.join("cgroup.controllers").exists() {
+        log!(LogLevel::Warn, "cgroup v2 is not mounted at {}, skipping cgroup {}", CGROUP_ROOT, target.path.display());
+        return None;
+    }
+
+    let path = &target.path;
+    if let Err(err) = fs::create_dir_all(path) {
+        log!(LogLevel::Warn, "Couldn't create cgroup {}: {}, continuing without it", path.display(), err);
+        return None;
+    }
+
+    // Controllers have to be delegated down every level, usually already true at the root under systemd
+    let mut ancestors: Vec<&Path> = path.ancestors().skip(1).take_while(|ancestor| ancestor.starts_with(&root)).collect();
+    ancestors.reverse();
+    for ancestor in ancestors {
+        if let Err(err) = fs::write(ancestor.join("cgroup.subtree_control"), "+memory +cpu") {
+            log!(LogLevel::Debug, "Couldn't enable cgroup controllers in {}: {}", ancestor.display(), err);
+        }
+    }
+
+    if let Some(mb) = target.memory_mb {
+        write_cgroup_value(path, "memory.max", (mb * 1024 * 1024).to_string());
+        // Without this the kernel swaps instead of enforcing the limit
+        write_cgroup_value(path, "memory.swap.max", "0".to_string());
+    }
+
+    match target.cpu_percent {
+        Some(percent) => {
+            let quota = CPU_PERIOD_US * percent as u64 / 100;
+            write_cgroup_value(path, "cpu.max", format!("{} {}", quota, CPU_PERIOD_US));
+        }
+        // The leaf is reused across respawns, a removed setting has to be undone
+        None if path.join("cpu.max").exists() => write_cgroup_value(path, "cpu.max", format!("max {}", CPU_PERIOD_US)),
+        None => (),
+    }
+
+    match OpenOptions::new().write(true).open(path.join("cgroup.procs")) {
+        Ok(file) => {
+            log!(LogLevel::Trace, "Child will be placed into cgroup {}", path.display());
+            Some(file)
+        }
+        Err(err) => {
+            log!(LogLevel::Warn, "Couldn't open cgroup.procs in {}: {}, continuing without it", path.display(), err);
+            None
+        }
+    }
+}
+
+fn write_cgroup_value(path: &Path, file: &str, value: String) {
+    if let Err(err) = fs::write(path.join(file), &value) {
+        log!(LogLevel::Warn, "Couldn't set {} to {} for {}: {}", file, value, path.display(), err);
+    }
+}
+
+/// Reads the system wide `oom_kill` counter, which moves whenever the kernel OOM killer strikes
+pub fn system_oom_kill_count() -> Option<u64> {
+    let vmstat = fs::read_to_string("/proc/vmstat").ok()?;
+
+    vmstat
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .find(|(key, _)| *key == "oom_kill")
+        .and_then(|(_, value)| value.trim().parse::<u64>().ok())
+}
+
+/// Reads the `oom_kill` counter of the configured cgroup, if there is one. The
+/// parent's `memory.events` is hierarchical so it covers every instance.
+pub fn oom_kill_count(limits: &LimitsConfig) -> Option<u64> {
+    let name = limits.cgroup.as_ref()?;
+    let events = fs::read_to_string(cgroup_path(name).join("memory.events")).ok()?;
+
+    events
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .find(|(key, _)| *key == "oom_kill")
+        .and_then(|(_, value)| value.trim().parse::<u64>().ok())
+}
+
+/// Makes the command drop to `run_as_user` / `run_as_group` before exec, must
+/// be called after `apply_limits`. Unlike the limits this fails hard, the child
+/// must never run with more privileges than configured.
+pub fn apply_identity(command: &mut Command, settings: &AppSpecificConfig) -> Result<(), String> {
+    let user: Option<User> = match &settings.run_as_user {
+        Some(name) => match User::from_name(name) {
+            Ok(Some(user)) => Some(user),
+            Ok(None) => return Err(format!("User {} doesn't exist", name)),
+            Err(err) => return Err(format!("Failed to look up user {}: {}", name, err)),
+        },
+        None => None,
+    };
+
+    let group_id = match &settings.run_as_group {
+        Some(name) => match Group::from_name(name) {
+            Ok(Some(group)) => Some(group.gid),
+            Ok(None) => return Err(format!("Group {} doesn't exist", name)),
+            Err(err) => return Err(format!("Failed to look up group {}: {}", name, err)),
+        },
+        None => user.as_ref().map(|user| user.gid),
+    };
+
+    let user_id: Option<Uid> = user.as_ref().map(|user| user.uid);
+    if let Some(user) = &user {
+        log!(LogLevel::Trace, "Child will run as {} ({})", user.name, user.uid);
+        command.env("HOME", &user.dir).env("USER", &user.name);
+    }
+
+    if user_id.is_none() && group_id.is_none() {
+        return Ok(());
+    }
+
+    // Registered after apply_limits so the rlimits and cgroup are set while still privileged
+    unsafe {
+        command.pre_exec(move || {
+            if let Some(gid) = group_id {
+                setgroups(&[gid])?;
+                setgid(gid)?;
+            }
+            if let Some(uid) = user_id {
+                setuid(uid)?;
+            }
+            Ok(())
+        });
+    }
+
+    Ok(())
+}
+
+/// Applies the nice value and IO scheduling class to the command. Raising
+/// priority needs privileges, a failure there aborts the spawn.
+pub fn apply_priority(command: &mut Command, priority: &PriorityConfig) {
+    let nice = priority.nice;
+    let ioprio: Option<libc::c_int> = priority.io_class.map(|class| {
+        let (class_value, data) = match class {
+            IoClass::Realtime => (1, priority.io_priority.unwrap_or(4).min(7)),
+            IoClass::BestEffort => (2, priority.io_priority.unwrap_or(4).min(7)),
+            IoClass::Idle => (3, 0),
+        };
+        (class_value << 13) | data as libc::c_int
+    });
+
+    if nice.is_none() && ioprio.is_none() {
+        return;
+    }
+
+    log!(LogLevel::Trace, "Applying nice {:?} and io class {:?}", nice, priority.io_class);
+
+    unsafe {
+        command.pre_exec(move || {
+            if let Some(nice) = nice {
+                if libc::setpriority(libc::PRIO_PROCESS, 0, nice) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+
+            if let Some(ioprio) = ioprio {
+                // IOPRIO_WHO_PROCESS with pid 0 targets the calling process
+                if libc::syscall(libc::SYS_ioprio_set, 1, 0, ioprio) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,227 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::Instant;
+
+/// Furthest a schedule is searched ahead, long enough to reach the next 29th of February
+const SEARCH_LIMIT_MINUTES: u64 = 8 * 366 * 24 * 60;
+
+/// A five field cron expression (`minute hour day-of-month month day-of-week`)
+/// evaluated in UTC. Fields take `*`, numbers, `a-b` ranges, `,` lists and
+/// `/n` steps, day-of-week runs from 0 (Sunday) to 6, 7 is Sunday as well.
+#[derive(Debug, Clone)]
+pub struct CronSchedule {
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days: Vec<bool>,
+    months: Vec<bool>,
+    weekdays: Vec<bool>,
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl CronSchedule {
+    pub fn parse(expression: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!("Expected 5 fields in \"{}\", found {}", expression, fields.len()));
+        }
+
+        let mut weekdays = parse_field(fields[4], 0, 7)?;
+        // 7 is an alias for Sunday
+        if weekdays[7] {
+            weekdays[0] = true;
+        }
+
+        Ok(Self {
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            weekdays,
+            days_restricted: fields[2] != "*",
+            weekdays_restricted: fields[4] != "*",
+        })
+    }
+
+    /// The first matching minute strictly after `after`
+    pub fn next_after(&self, after: SystemTime) -> Option<SystemTime> {
+        let seconds = after.duration_since(UNIX_EPOCH).ok()?.as_secs();
+        let start = seconds / 60 + 1;
+        let mut minute = start;
+
+        // Whole days and hours are skipped at once, so even rare dates are found quickly
+        while minute < start + SEARCH_LIMIT_MINUTES {
+            if !self.matches_day(minute / (60 * 24)) {
+                minute = (minute / (60 * 24) + 1) * 60 * 24;
+            } else if !self.hours[(minute / 60 % 24) as usize] {
+                minute = (minute / 60 + 1) * 60;
+            } else if !self.minutes[(minute % 60) as usize] {
+                minute += 1;
+            } else {
+                return Some(UNIX_EPOCH + Duration::from_secs(minute * 60));
+            }
+        }
+
+        None
+    }
+
+    /// When the next restart is due on the tokio clock
+    pub fn next_instant(&self) -> Option<Instant> {
+        let now = SystemTime::now();
+        let next = self.next_after(now)?;
+        Some(Instant::now() + next.duration_since(now).unwrap_or_default())
+    }
+
+    fn matches_day(&self, epoch_day: u64) -> bool {
+        let (_, month, day) = civil_from_days(epoch_day as i64);
+        // 1970-01-01 was a Thursday
+        let weekday = ((epoch_day + 4) % 7) as usize;
+
+        if !self.months[month as usize] {
+            return false;
+        }
+
+        // Like classic cron, a restricted day-of-month and day-of-week match when either does
+        match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => self.days[day as usize] || self.weekdays[weekday],
+            _ => self.days[day as usize] && self.weekdays[weekday],
+        }
+    }
+}
+
+fn parse_field(field: &str, min: usize, max: usize) -> Result<Vec<bool>, String> {
+    let mut allowed = vec![false; max + 1];
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step: usize = step.parse().map_err(|_| format!("Invalid step in \"{}\"", part))?;
+                if step == 0 {
+                    return Err(format!("Step can't be 0 in \"{}\"", part));
+                }
+                (range, step)
+            }
+            None => (part, 1),
+        };
+
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (parse_value(start, part)?, parse_value(end, part)?),
+                // A bare value with a step runs to the end of the field, like `5/15`
+                None if step > 1 => (parse_value(range, part)?, max),
+                None => {
+                    let value = parse_value(range, part)?;
+                    (value, value)
+                }
+            },
+        };
+
+        if start < min || end > max || start > end {
+            return Err(format!("\"{}\" is outside {}-{}", part, min, max));
+        }
+
+        for value in (start..=end).step_by(step) {
+            allowed[value] = true;
+        }
+    }
+
+    Ok(allowed)
+}
+
+fn parse_value(value: &str, part: &str) -> Result<usize, String> {
+    value.parse().map_err(|_| format!("Invalid value in \"{}\"", part))
+}
+
+/// Days since the unix epoch to a (year, month, day) date, after Howard Hinnant's algorithm
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    fn next(expression: &str, after: u64) -> u64 {
+        let schedule = CronSchedule::parse(expression).unwrap();
+        schedule.next_after(at(after)).unwrap().duration_since(UNIX_EPOCH).unwrap().as_secs()
+    }
+
+    #[test]
+    fn rejects_malformed_expressions() {
+        assert!(CronSchedule::parse("0 0 * *").is_err());
+        assert!(CronSchedule::parse("0 0 * * * *").is_err());
+        assert!(CronSchedule::parse("*/0 * * * *").is_err());
+        assert!(CronSchedule::parse("60 * * * *").is_err());
+        assert!(CronSchedule::parse("0 0 0 * *").is_err());
+        assert!(CronSchedule::parse("0 5-1 * * *").is_err());
+        assert!(CronSchedule::parse("0 0 * * mon").is_err());
+    }
+
+    #[test]
+    fn parses_ranges_lists_and_steps() {
+        let minutes = parse_field("*/15", 0, 59).unwrap();
+        assert_eq!(minutes.iter().filter(|allowed| **allowed).count(), 4);
+        assert!(minutes[0] && minutes[15] && minutes[30] && minutes[45]);
+
+        // A bare value with a step runs to the end of the field
+        let minutes = parse_field("5/20", 0, 59).unwrap();
+        assert!(minutes[5] && minutes[25] && minutes[45] && !minutes[0]);
+
+        let hours = parse_field("1-3,22", 0, 23).unwrap();
+        let set: Vec<usize> = (0..24).filter(|hour| hours[*hour]).collect();
+        assert_eq!(set, vec![1, 2, 3, 22]);
+    }
+
+    #[test]
+    fn next_is_strictly_after() {
+        // 1970-01-01 00:07 to the next quarter hour
+        assert_eq!(next("*/15 * * * *", 7 * 60), 15 * 60);
+        // On a matching minute the next one is taken
+        assert_eq!(next("*/15 * * * *", 15 * 60), 30 * 60);
+        assert_eq!(next("30 2 * * *", 0), 2 * 3600 + 30 * 60);
+    }
+
+    #[test]
+    fn restricted_day_and_weekday_match_either() {
+        let day = 24 * 3600;
+        // 1970-01-01 was a Thursday, the Friday comes before the 13th
+        assert_eq!(next("0 0 13 * 5", 0), day);
+        assert_eq!(next("0 0 13 * *", 0), 12 * day);
+        assert_eq!(next("0 0 * * 5", 0), day);
+        // Sunday as 0 and as 7
+        assert_eq!(next("0 0 * * 0", 0), 3 * day);
+        assert_eq!(next("0 0 * * 7", 0), 3 * day);
+    }
+
+    #[test]
+    fn finds_the_next_leap_day() {
+        // After 2024-03-01 the next 29th of February is in 2028
+        assert_eq!(next("0 12 29 2 *", 1_709_251_200), 1_835_438_400);
+    }
+
+    #[test]
+    fn converts_epoch_days_to_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+        assert_eq!(civil_from_days(19_783), (2024, 3, 1));
+        // 2100 isn't a leap year
+        assert_eq!(civil_from_days(47_541), (2100, 3, 1));
+        assert_eq!(civil_from_days(47_540), (2100, 2, 28));
+    }
+}
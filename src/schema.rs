@@ -0,0 +1,192 @@
+use serde_json::{json, Map, Value};
+
+use crate::{
+    fields::{config_types, enum_variants, struct_fields, FieldType, TypeKind},
+    sample::sample_settings,
+};
+
+/// Exit codes of `schema`
+pub const SCHEMA_OK: i32 = 0;
+pub const SCHEMA_FAILED: i32 = 1;
+
+/// Prints the JSON Schema of the Config file. Returns the exit code for
+/// `ais_generic schema`.
+pub fn run_schema() -> i32 {
+    match serde_json::to_string_pretty(&config_schema()) {
+        Ok(schema) => {
+            println!("{}", schema);
+            SCHEMA_OK
+        }
+        Err(err) => {
+            eprintln!("Couldn't write the schema: {}", err);
+            SCHEMA_FAILED
+        }
+    }
+}
+
+/// The `app_specific` and `[[applications]]` part of the Config file, read
+/// off the config structs like the sample Config. The settings of the
+/// middleware around them are left open.
+pub fn config_schema() -> Value {
+    let defaults = serde_json::to_value(sample_settings()).unwrap_or_default();
+    let mut settings = struct_schema("AppSpecificConfig", Some(&defaults));
+    let required = settings.as_object_mut().and_then(|schema| schema.remove("required")).unwrap_or_else(|| json!([]));
+
+    // Entries override the shared settings, only their name is required
+    let mut entry = settings.clone();
+    entry["required"] = json!(["name"]);
+    entry["description"] = json!("One supervised application, layered over [app_specific]");
+
+    settings["additionalProperties"] = json!({
+        "type": "object",
+        "description": "A profile applied with --env <profile> or ARTISAN_ENV, overriding the settings above",
+    });
+
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "artisan_runner Config",
+        "type": "object",
+        "properties": {
+            "app_specific": settings,
+            "applications": {
+                "type": "array",
+                "description": "Applications supervised by one runner, each with its own state, pid files and logs",
+                "items": entry,
+                "minItems": 1,
+            },
+        },
+        // Without [[applications]] the shared settings have to be complete
+        "if": { "not": { "required": ["applications"] } },
+        "then": {
+            "required": ["app_specific"],
+            "properties": { "app_specific": { "required": required } },
+        },
+    })
+}
+
+/// `defaults` are the values of the struct when nothing is set
+fn struct_schema(type_name: &str, defaults: Option<&Value>) -> Value {
+    let mut properties = Map::new();
+    let mut required = Vec::new();
+
+    for field in struct_fields(type_name).iter().filter(|field| !field.skip) {
+        let default = defaults.and_then(|defaults| defaults.get(&field.name)).filter(|value| !value.is_null());
+        let mut schema = field_schema(&field.ty, default);
+
+        let description = [field.comment.clone(), schema.get("description").and_then(Value::as_str).map(str::to_owned)];
+        let description: Vec<String> = description.into_iter().flatten().collect();
+        if !description.is_empty() {
+            schema["description"] = json!(description.join(". "));
+        }
+
+        match (field.required, default) {
+            (true, _) => required.push(field.name.clone()),
+            (false, Some(default)) => schema["default"] = without_nulls(default),
+            (false, None) => (),
+        }
+        // Former names still load, editors flag them
+        for alias in &field.aliases {
+            let mut renamed = schema.clone();
+            if let Some(renamed) = renamed.as_object_mut() {
+                renamed.remove("default");
+            }
+            renamed["description"] = json!(format!("Renamed to {}", field.name));
+            renamed["deprecated"] = json!(true);
+            properties.insert(alias.clone(), renamed);
+        }
+        properties.insert(field.name.clone(), schema);
+    }
+
+    let mut schema = json!({ "type": "object", "properties": properties });
+    if let Some(doc) = config_types().get(type_name).and_then(|info| info.doc.as_ref()) {
+        schema["description"] = json!(doc);
+    }
+    if !required.is_empty() {
+        schema["required"] = json!(required);
+    }
+    schema
+}
+
+fn field_schema(ty: &FieldType, default: Option<&Value>) -> Value {
+    match ty {
+        FieldType::Bool => json!({ "type": "boolean" }),
+        FieldType::Integer { unsigned: true } => json!({ "type": "integer", "minimum": 0 }),
+        FieldType::Integer { unsigned: false } => json!({ "type": "integer" }),
+        FieldType::Text => json!({ "type": "string" }),
+        FieldType::Optional(inner) => field_schema(inner, default),
+        FieldType::List(item) => json!({ "type": "array", "items": field_schema(item, None) }),
+        FieldType::Map(value) => json!({ "type": "object", "additionalProperties": field_schema(value, None) }),
+        FieldType::Named(name) => match config_types().get(name).map(|info| &info.kind) {
+            Some(TypeKind::Struct(_)) => struct_schema(name, default),
+            Some(TypeKind::Enum(_)) => enum_schema(name),
+            None => json!({}),
+        },
+    }
+}
+
+fn enum_schema(name: &str) -> Value {
+    let variants = enum_variants(name);
+    let names: Vec<&str> = variants.iter().map(|variant| variant.name.as_str()).collect();
+    let comments: Vec<String> = variants
+        .iter()
+        .filter_map(|variant| variant.comment.as_ref().map(|comment| format!("\"{}\" ({})", variant.name, comment)))
+        .collect();
+
+    let mut schema = json!({ "type": "string", "enum": names });
+    let doc = config_types().get(name).and_then(|info| info.doc.clone());
+    let comments = Some(comments.join(", ")).filter(|comments| !comments.is_empty());
+    let description: Vec<String> = doc.into_iter().chain(comments).collect();
+    if !description.is_empty() {
+        schema["description"] = json!(description.join(". "));
+    }
+    schema
+}
+
+/// Unset options are left out of the file, TOML has no null
+fn without_nulls(value: &Value) -> Value {
+    match value {
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(name, value)| (name.clone(), without_nulls(value)))
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.iter().map(without_nulls).collect()),
+        value => value.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Keys of `settings` the schema doesn't describe, nested tables included
+    fn undescribed(settings: &Value, schema: &Value, path: &str, missing: &mut Vec<String>) {
+        let (Some(fields), Some(properties)) = (settings.as_object(), schema.get("properties").and_then(Value::as_object)) else {
+            return;
+        };
+
+        for (name, value) in fields {
+            match properties.get(name) {
+                Some(property) => undescribed(value, property, &format!("{}{}.", path, name), missing),
+                None => missing.push(format!("{}{}", path, name)),
+            }
+        }
+    }
+
+    #[test]
+    fn every_setting_is_in_the_schema() {
+        // Serde knows every field, the schema only what the parser read off config.rs
+        let settings = serde_json::to_value(sample_settings()).unwrap();
+        let schema = config_schema();
+
+        let mut missing = Vec::new();
+        undescribed(&settings, &schema["properties"]["app_specific"], "", &mut missing);
+        assert!(missing.is_empty(), "not in the schema: {}", missing.join(", "));
+
+        let mut missing = Vec::new();
+        undescribed(&settings, &schema["properties"]["applications"]["items"], "", &mut missing);
+        assert!(missing.is_empty(), "not in the [[applications]] schema: {}", missing.join(", "));
+    }
+}
@@ -0,0 +1,299 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use config::{Map, Value, ValueKind};
+use nix::unistd::geteuid;
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    rand::{SecureRandom, SystemRandom},
+};
+use std::{
+    env, fs,
+    io::{self, Read},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use crate::cli::cli_args;
+
+/// Keyfile used when `--keyfile` isn't given
+pub const DEFAULT_KEYFILE: &str = "/etc/artisan/secrets.key";
+
+const ENCRYPTED_PREFIX: &str = "enc:AES256:";
+
+/// Anything else in `${...}`, such as a shell variable, is kept as written
+const REFERENCES: [&str; 2] = ["${file:", "${env:"];
+
+/// Variables of the child, their references are resolved on every spawn instead
+const SPAWN_TIME_TABLE: &str = "app_specific.env";
+
+/// Loaded on the first encrypted value, files without any don't need a keyfile
+type LazyKey = Option<Result<LessSafeKey, String>>;
+
+pub fn keyfile() -> PathBuf {
+    cli_args().keyfile.clone().unwrap_or_else(|| PathBuf::from(DEFAULT_KEYFILE))
+}
+
+/// Resolves `${file:<path>}` and `${env:<name>}` references in every string
+/// below `table`, so tokens for hooks and notifications stay out of the
+/// Config file, and decrypts `enc:AES256:` values with the keyfile. Other
+/// `${...}` are left alone and `$${` writes a literal `${`. Runs on every load, a rotated secret is picked up
+/// on the next reload, except for the `env` table which is resolved on
+/// every spawn. Each value that can't be resolved is added to `problems`
+/// under its key. Returns the keys holding a secret.
+pub fn resolve_secrets(table: &mut Map<String, Value>, prefix: &str, problems: &mut Vec<String>) -> Vec<String> {
+    let mut secrets = Vec::new();
+    resolve_table(table, prefix, &mut None, &mut secrets, problems);
+    secrets
+}
+
+fn resolve_table(table: &mut Map<String, Value>, prefix: &str, key: &mut LazyKey, secrets: &mut Vec<String>, problems: &mut Vec<String>) {
+    for (name, value) in table.iter_mut() {
+        let name = format!("{}.{}", prefix, name);
+        match prefix == SPAWN_TIME_TABLE {
+            true => resolve_spawn_time(value, &name, key, secrets, problems),
+            false => resolve_value(value, &name, key, secrets, problems),
+        }
+    }
+}
+
+/// Only decrypts, the result is escaped so the spawn time interpolation keeps it as is
+fn resolve_spawn_time(value: &mut Value, name: &str, key: &mut LazyKey, secrets: &mut Vec<String>, problems: &mut Vec<String>) {
+    let ValueKind::String(text) = &mut value.kind else {
+        return;
+    };
+
+    if text.starts_with(ENCRYPTED_PREFIX) {
+        resolve_value(value, name, key, secrets, problems);
+        if let ValueKind::String(decrypted) = &mut value.kind {
+            *decrypted = decrypted.replace("${", "$${");
+        }
+    } else if has_reference(text) {
+        secrets.push(name.to_owned());
+    }
+}
+
+fn has_reference(text: &str) -> bool {
+    REFERENCES.iter().any(|reference| text.contains(reference))
+}
+
+fn resolve_value(value: &mut Value, name: &str, key: &mut LazyKey, secrets: &mut Vec<String>, problems: &mut Vec<String>) {
+    if matches!(&value.kind, ValueKind::String(text) if text.starts_with(ENCRYPTED_PREFIX) || has_reference(text)) {
+        secrets.push(name.to_owned());
+    }
+
+    match &mut value.kind {
+        // Decrypted values are taken as they are, they aren't interpolated
+        ValueKind::String(text) if text.starts_with(ENCRYPTED_PREFIX) => {
+            let decrypted = key
+                .get_or_insert_with(|| load_key(&keyfile()))
+                .as_ref()
+                .map_err(String::clone)
+                .and_then(|key| decrypt(key, &text[ENCRYPTED_PREFIX.len()..]));
+            match decrypted {
+                Ok(decrypted) => *text = decrypted,
+                Err(err) => problems.push(format!("{} can't be decrypted: {}", name, err)),
+            }
+        }
+        ValueKind::String(text) if text.contains("${") => match interpolate(text) {
+            Ok(resolved) => *text = resolved,
+            Err(err) => problems.push(format!("{} {}", name, err)),
+        },
+        ValueKind::Table(table) => resolve_table(table, name, key, secrets, problems),
+        ValueKind::Array(items) => {
+            for (index, item) in items.iter_mut().enumerate() {
+                resolve_value(item, &format!("{}[{}]", name, index), key, secrets, problems);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Replaces the references in `text` with what they point at, other `${...}` stay as they are
+pub fn interpolate(text: &str) -> Result<String, String> {
+    let mut resolved = String::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            resolved.push_str(&rest[..start - 1]);
+            resolved.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+
+        if !REFERENCES.iter().any(|reference| rest[start..].starts_with(reference)) {
+            resolved.push_str(&rest[..start + 2]);
+            rest = &rest[start + 2..];
+            continue;
+        }
+
+        resolved.push_str(&rest[..start]);
+        let Some(length) = rest[start..].find('}') else {
+            return Err(String::from("has a ${ without its closing }"));
+        };
+        resolved.push_str(&resolve_reference(&rest[start + 2..start + length])?);
+        rest = &rest[start + length + 1..];
+    }
+
+    resolved.push_str(rest);
+    Ok(resolved)
+}
+
+fn resolve_reference(reference: &str) -> Result<String, String> {
+    match reference.split_once(':') {
+        Some(("file", path)) => fs::read_to_string(path)
+            .map(|secret| secret.trim_end_matches(['\r', '\n']).to_owned())
+            .map_err(|err| format!("references {} which can't be read: {}", path, err)),
+        Some(("env", name)) => env::var(name).map_err(|err| format!("references ${} which can't be read: {}", name, err)),
+        _ => Err(format!("has ${{{}}}, expected ${{file:<path>}} or ${{env:<name>}}", reference)),
+    }
+}
+
+/// The keyfile holds 32 random bytes, base64 encoded, and may only be
+/// accessible to its owner, root or the user the runner runs as. Tenants
+/// sharing a host can't read each other's secrets that way.
+fn load_key(path: &Path) -> Result<LessSafeKey, String> {
+    let metadata = fs::metadata(path).map_err(|err| format!("keyfile {} can't be read: {}", path.display(), err))?;
+    if metadata.mode() & 0o077 != 0 || (metadata.uid() != 0 && metadata.uid() != geteuid().as_raw()) {
+        return Err(format!(
+            "keyfile {} has to be owned by root or the runner's user and not be accessible to anyone else (chmod 600)",
+            path.display()
+        ));
+    }
+
+    let text = fs::read_to_string(path).map_err(|err| format!("keyfile {} can't be read: {}", path.display(), err))?;
+    STANDARD
+        .decode(text.trim())
+        .ok()
+        .and_then(|bytes| UnboundKey::new(&AES_256_GCM, &bytes).ok())
+        .map(LessSafeKey::new)
+        .ok_or_else(|| format!("keyfile {} has to hold 32 bytes, base64 encoded", path.display()))
+}
+
+/// `sealed` is the base64 of the nonce followed by the ciphertext and its tag
+fn decrypt(key: &LessSafeKey, sealed: &str) -> Result<String, String> {
+    let mut sealed = STANDARD.decode(sealed).map_err(|err| format!("isn't base64: {}", err))?;
+    if sealed.len() < NONCE_LEN {
+        return Err(String::from("is too short to be encrypted"));
+    }
+
+    let mut ciphertext = sealed.split_off(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(&sealed).map_err(|_| String::from("has an invalid nonce"))?;
+    let plaintext = key
+        .open_in_place(nonce, Aad::empty(), &mut ciphertext)
+        .map_err(|_| String::from("it was encrypted with another key or altered"))?;
+
+    String::from_utf8(plaintext.to_vec()).map_err(|_| String::from("isn't text once decrypted"))
+}
+
+fn encrypt(key: &LessSafeKey, plaintext: &str) -> Result<String, String> {
+    let mut nonce = [0u8; NONCE_LEN];
+    SystemRandom::new().fill(&mut nonce).map_err(|_| String::from("no randomness for the nonce"))?;
+
+    let mut sealed = plaintext.as_bytes().to_vec();
+    key.seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut sealed)
+        .map_err(|_| String::from("encrypting failed"))?;
+
+    let mut encoded = nonce.to_vec();
+    encoded.append(&mut sealed);
+    Ok(format!("{}{}", ENCRYPTED_PREFIX, STANDARD.encode(encoded)))
+}
+
+/// Exit codes of `encrypt`
+pub const ENCRYPT_OK: i32 = 0;
+pub const ENCRYPT_FAILED: i32 = 1;
+
+/// Encrypts the value read from stdin with the keyfile and prints it ready
+/// to paste into the Config file. Stdin keeps the secret out of the shell
+/// history. Returns the exit code for `ais_generic encrypt`.
+pub fn run_encrypt() -> i32 {
+    let mut plaintext = String::new();
+    if let Err(err) = io::stdin().read_to_string(&mut plaintext) {
+        eprintln!("Couldn't read the value to encrypt from stdin: {}", err);
+        return ENCRYPT_FAILED;
+    }
+
+    match load_key(&keyfile()).and_then(|key| encrypt(&key, plaintext.trim_end_matches(['\r', '\n']))) {
+        Ok(encrypted) => {
+            println!("{}", encrypted);
+            ENCRYPT_OK
+        }
+        Err(err) => {
+            eprintln!("Couldn't encrypt the value: {}", err);
+            ENCRYPT_FAILED
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A secret file of its own in the temp directory
+    fn secret_file(name: &str, contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("ais_secret_{}_{}", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn text(table: &Map<String, Value>, key: &str) -> String {
+        table[key].clone().into_string().unwrap()
+    }
+
+    #[test]
+    fn interpolates_file_and_env_references() {
+        let path = secret_file("token", "s3cret\n");
+        env::set_var("AIS_SECRETS_TEST_USER", "deploy");
+
+        let resolved = interpolate(&format!("curl -u ${{env:AIS_SECRETS_TEST_USER}}:${{file:{}}} host", path.display()));
+        assert_eq!(resolved.unwrap(), "curl -u deploy:s3cret host");
+    }
+
+    #[test]
+    fn escapes_and_leaves_other_braces_alone() {
+        assert_eq!(interpolate("literal $${env:HOME}").unwrap(), "literal ${env:HOME}");
+        assert_eq!(interpolate("sh -c 'cp ${HOME}/x .'").unwrap(), "sh -c 'cp ${HOME}/x .'");
+        assert_eq!(interpolate("${").unwrap(), "${");
+        assert_eq!(interpolate("no references").unwrap(), "no references");
+    }
+
+    #[test]
+    fn reports_unresolvable_references() {
+        assert!(interpolate("${env:AIS_SECRETS_TEST_UNSET}").is_err());
+        assert!(interpolate("${file:/nonexistent/ais_secret}").is_err());
+        assert!(interpolate("${env:AIS_SECRETS_TEST_USER").is_err());
+    }
+
+    #[test]
+    fn leaves_env_values_for_the_spawn() {
+        env::set_var("AIS_SECRETS_TEST_HOOK", "token");
+
+        let mut variables = Map::new();
+        variables.insert(String::from("API_KEY"), Value::from("${env:AIS_SECRETS_TEST_HOOK}"));
+        variables.insert(String::from("DATABASE_URL"), Value::from("file:./dev.db"));
+        let mut table = Map::new();
+        table.insert(String::from("pre_start_hook"), Value::from("echo ${env:AIS_SECRETS_TEST_HOOK} ${PATH}"));
+        table.insert(String::from("env"), Value::from(variables));
+
+        let mut problems = Vec::new();
+        let mut secrets = resolve_secrets(&mut table, "app_specific", &mut problems);
+        secrets.sort();
+        assert!(problems.is_empty(), "{:?}", problems);
+        assert_eq!(secrets, vec!["app_specific.env.API_KEY", "app_specific.pre_start_hook"]);
+        assert_eq!(text(&table, "pre_start_hook"), "echo token ${PATH}");
+
+        let variables = table["env"].clone().into_table().unwrap();
+        assert_eq!(text(&variables, "API_KEY"), "${env:AIS_SECRETS_TEST_HOOK}");
+        assert_eq!(text(&variables, "DATABASE_URL"), "file:./dev.db");
+    }
+
+    #[test]
+    fn decrypts_what_it_encrypted() {
+        let key = LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &[7u8; 32]).unwrap());
+        let encrypted = encrypt(&key, "hunter2").unwrap();
+        assert!(encrypted.starts_with(ENCRYPTED_PREFIX));
+        assert_eq!(decrypt(&key, &encrypted[ENCRYPTED_PREFIX.len()..]).unwrap(), "hunter2");
+
+        let other = LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &[8u8; 32]).unwrap());
+        assert!(decrypt(&other, &encrypted[ENCRYPTED_PREFIX.len()..]).is_err());
+    }
+}
@@ -1,6 +1,6 @@
 use dusa_collection_utils::log;
 use dusa_collection_utils::log::LogLevel;
-use nix::libc::SIGUSR1;
+use nix::libc::{SIGUSR1, SIGUSR2};
 use signal_hook::{consts::signal::SIGHUP, iterator::Signals};
 use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
 use std::thread;
@@ -25,3 +25,17 @@ pub fn sigusr_watch(reload: Arc<AtomicBool>) {
         }
     });    
 }
+
+/// Every SIGUSR2 flips monitoring between paused and running
+pub fn sigusr2_watch(paused: Arc<AtomicBool>) {
+    thread::spawn(move || {
+        let mut signals = Signals::new(&[SIGUSR2]).expect("Failed to register signals");
+        for _ in signals.forever() {
+            let was_paused = paused.fetch_xor(true, Ordering::Relaxed);
+            match was_paused {
+                true => log!(LogLevel::Info, "Received SIGUSR2, resuming directory monitoring"),
+                false => log!(LogLevel::Info, "Received SIGUSR2, pausing directory monitoring"),
+            }
+        }
+    });
+}
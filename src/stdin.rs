@@ -0,0 +1,211 @@
+use dusa_collection_utils::log;
+use dusa_collection_utils::log::LogLevel;
+use nix::{
+    fcntl::{fcntl, FcntlArg, OFlag},
+    unistd::pipe2,
+};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, ErrorKind, Write},
+    os::{
+        fd::AsRawFd,
+        unix::net::{UnixListener, UnixStream},
+    },
+    path::PathBuf,
+    process::Stdio,
+    sync::Mutex,
+    thread,
+};
+use tokio::process::Command;
+
+use crate::{cleanup::register_artifact, config::StdinConfig};
+
+/// An `[[applications]]` entry's name, None for a single application
+type Application = Option<String>;
+
+/// Write ends of every child's stdin, keyed by application and instance index
+static CHILD_STDIN: Mutex<BTreeMap<(Application, u16), ChildPipe>> = Mutex::new(BTreeMap::new());
+
+/// The write end of a child's stdin, with the tail of a line the full pipe
+/// didn't take yet
+struct ChildPipe {
+    file: File,
+    unsent: Vec<u8>,
+}
+
+impl ChildPipe {
+    /// Writes as much of the unsent tail as the pipe takes, true once it's all out
+    fn flush(&mut self) -> io::Result<bool> {
+        while !self.unsent.is_empty() {
+            match self.file.write(&self.unsent) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(written) => {
+                    self.unsent.drain(..written);
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(false),
+                Err(err) if err.kind() == ErrorKind::Interrupted => (),
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+/// Gives the child a stdin pipe the runner keeps the write end of, replacing
+/// the pipe of a previous child of the same application and index. Returns
+/// false when the pipe is disabled or couldn't be set up.
+pub fn attach_stdin(command: &mut Command, config: &StdinConfig, application: Option<&str>, index: u16) -> bool {
+    if !config.enabled {
+        return false;
+    }
+
+    // Close on exec so the write end never leaks into the child, which would keep it from seeing EOF
+    let (read_end, write_end) = match pipe2(OFlag::O_CLOEXEC) {
+        Ok(ends) => ends,
+        Err(err) => {
+            log!(LogLevel::Warn, "Couldn't create stdin pipe for the child: {}", err);
+            return false;
+        }
+    };
+
+    // Only the runner's end is non-blocking, a child that doesn't read can't stall the writers
+    if let Err(err) = fcntl(write_end.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK)) {
+        log!(LogLevel::Warn, "Couldn't make the stdin pipe of the child non-blocking: {}", err);
+        return false;
+    }
+
+    command.stdin(Stdio::from(read_end));
+
+    if let Ok(mut pipes) = CHILD_STDIN.lock() {
+        let pipe = ChildPipe { file: File::from(write_end), unsent: Vec::new() };
+        pipes.insert((application.map(str::to_owned), index), pipe);
+    }
+
+    true
+}
+
+/// Writes a line to the stdin of one instance of `application`, or every
+/// instance when `index` is None. Lines only go out whole: the part a full
+/// pipe doesn't take is sent before anything else, and while it's pending the
+/// child doesn't receive new lines. Returns how many children received it.
+pub fn write_stdin(application: Option<&str>, index: Option<u16>, line: &str) -> usize {
+    let mut pipes = match CHILD_STDIN.lock() {
+        Ok(pipes) => pipes,
+        Err(_) => return 0,
+    };
+
+    let message = format!("{}\n", line.trim_end_matches('\n'));
+    let mut delivered = 0;
+
+    // A child that exited closed its end, its pipe is dropped until the next spawn
+    pipes.retain(|(owner, instance), pipe| {
+        if owner.as_deref() != application || index.is_some_and(|target| target != *instance) {
+            return true;
+        }
+
+        let sent = pipe.flush().and_then(|flushed| match flushed {
+            true => {
+                pipe.unsent.extend_from_slice(message.as_bytes());
+                pipe.flush().map(|_| true)
+            }
+            false => Ok(false),
+        });
+
+        match sent {
+            Ok(true) => {
+                delivered += 1;
+                true
+            }
+            Ok(false) => {
+                log!(LogLevel::Warn, "Stdin of instance {} is full, the child isn't reading it, line not delivered", instance);
+                true
+            }
+            Err(err) => {
+                log!(LogLevel::Debug, "Stdin of instance {} is gone: {}", instance, err);
+                false
+            }
+        }
+    });
+
+    delivered
+}
+
+/// Listens on the configured unix socket and forwards every line received to
+/// the stdin of `application`'s children. A line starting with `@<n> ` only
+/// goes to instance `n`, e.g. `echo "@1 reload" | socat - UNIX-CONNECT:<socket>`.
+pub fn start_stdin_socket(config: &StdinConfig, application: Option<String>) {
+    let path = match (config.enabled, &config.socket) {
+        (true, Some(path)) => PathBuf::from(path),
+        _ => return,
+    };
+
+    // A stale socket from a previous run would make the bind fail
+    if path.exists() {
+        let _ = fs::remove_file(&path);
+    }
+
+    let listener = match UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        Err(err) => {
+            log!(LogLevel::Warn, "Couldn't bind stdin socket {}: {}", path.display(), err);
+            return;
+        }
+    };
+
+    register_artifact(&path);
+    log!(LogLevel::Info, "Child stdin accepting input on {}", path.display());
+
+    thread::spawn(move || {
+        for connection in listener.incoming() {
+            match connection {
+                Ok(client) => {
+                    let application = application.clone();
+                    thread::spawn(move || forward_client(client, application.as_deref()));
+                }
+                Err(err) => log!(LogLevel::Warn, "Stdin socket accept failed: {}", err),
+            }
+        }
+    });
+}
+
+fn forward_client(client: UnixStream, application: Option<&str>) {
+    let mut reply = match client.try_clone() {
+        Ok(reply) => reply,
+        Err(err) => {
+            log!(LogLevel::Warn, "Couldn't set up stdin client: {}", err);
+            return;
+        }
+    };
+
+    for line in BufReader::new(client).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                log!(LogLevel::Debug, "Stdin client went away: {}", err);
+                break;
+            }
+        };
+
+        let (target, text) = parse_target(&line);
+        let delivered = write_stdin(application, target, text);
+        log!(LogLevel::Debug, "Forwarded a line to {} child stdin(s)", delivered);
+
+        if reply.write_all(format!("delivered {}\n", delivered).as_bytes()).is_err() {
+            break;
+        }
+    }
+}
+
+fn parse_target(line: &str) -> (Option<u16>, &str) {
+    if let Some(rest) = line.strip_prefix('@') {
+        if let Some((index, text)) = rest.split_once(' ') {
+            if let Ok(index) = index.parse::<u16>() {
+                return (Some(index), text);
+            }
+        }
+    }
+
+    (None, line)
+}
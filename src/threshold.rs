@@ -0,0 +1,252 @@
+use dusa_collection_utils::log;
+use dusa_collection_utils::log::LogLevel;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::config::AppSpecificConfig;
+
+/// Counts changes per subtree. Paths not covered by a `change_thresholds`
+/// entry share the default `changes_needed` counter.
+pub struct ChangeCounter {
+    subtrees: Vec<(String, i32)>,
+    counts: Vec<i32>,
+    default_needed: i32,
+    default_count: i32,
+    batch: Vec<PathBuf>,    // Distinct changed paths relative to their root, capped at MAX_BATCH
+    batch_overflow: usize,
+    persist_to: Option<PathBuf>,
+}
+
+/// What survives a runner restart, subtrees are keyed by path so counts
+/// for thresholds that were since removed are dropped
+#[derive(Serialize, Deserialize, Default)]
+struct PendingChanges {
+    default_count: i32,
+    subtrees: BTreeMap<String, i32>,
+    batch: Vec<PathBuf>,
+    batch_overflow: usize,
+}
+
+const MAX_BATCH: usize = 1000;
+const LISTED_FILES: usize = 10;
+const LISTED_DIRS: usize = 5; // Also the number of extensions listed
+
+impl ChangeCounter {
+    pub fn new(settings: &AppSpecificConfig) -> Self {
+        let subtrees: Vec<(String, i32)> = settings
+            .change_thresholds
+            .iter()
+            .map(|threshold| (threshold.path.trim_end_matches('/').to_owned(), threshold.changes_needed))
+            .collect();
+
+        Self {
+            counts: vec![0; subtrees.len()],
+            subtrees,
+            default_needed: settings.changes_needed,
+            default_count: 0,
+            batch: Vec::new(),
+            batch_overflow: 0,
+            persist_to: None,
+        }
+    }
+
+    /// Picks up the changes a previous runner left pending in `path` and
+    /// keeps writing the counts there from now on
+    pub fn persisted(mut self, path: PathBuf) -> Self {
+        if let Ok(contents) = fs::read_to_string(&path) {
+            match serde_json::from_str::<PendingChanges>(&contents) {
+                Ok(pending) => {
+                    self.default_count = pending.default_count;
+                    for ((subtree, _), count) in self.subtrees.iter().zip(self.counts.iter_mut()) {
+                        *count = pending.subtrees.get(subtree).copied().unwrap_or(0);
+                    }
+                    self.batch = pending.batch;
+                    self.batch_overflow = pending.batch_overflow;
+                    if self.total() > 0 {
+                        log!(LogLevel::Info, "Restored pending changes: {}", self.summary());
+                    }
+                }
+                Err(err) => log!(LogLevel::Warn, "Ignoring unreadable pending changes in {}: {}", path.display(), err),
+            }
+        }
+
+        self.persist_to = Some(path);
+        self
+    }
+
+    /// Counts the change once for every subtree its paths touch
+    pub fn record(&mut self, root: &Path, paths: &[PathBuf]) {
+        let mut touched: Vec<Option<usize>> = Vec::new();
+        for path in paths {
+            self.add_to_batch(path.strip_prefix(root).unwrap_or(path).to_path_buf());
+
+            let subtree = self.subtree_of(root, path);
+            if !touched.contains(&subtree) {
+                touched.push(subtree);
+            }
+        }
+
+        for subtree in touched {
+            match subtree {
+                Some(index) => self.counts[index] += 1,
+                None => self.default_count += 1,
+            }
+        }
+
+        self.save();
+    }
+
+    /// Lists paths in the batch without counting them, for changes a watch
+    /// rule counted that restart the child
+    pub fn extend_batch(&mut self, relative: &[PathBuf]) {
+        for path in relative {
+            self.add_to_batch(path.clone());
+        }
+        self.save();
+    }
+
+    fn add_to_batch(&mut self, relative: PathBuf) {
+        if !self.batch.contains(&relative) {
+            match self.batch.len() < MAX_BATCH {
+                true => self.batch.push(relative),
+                false => self.batch_overflow += 1,
+            }
+        }
+    }
+
+    /// True once any counter reached its threshold
+    pub fn reached(&self) -> bool {
+        self.default_count >= self.default_needed
+            || self
+                .subtrees
+                .iter()
+                .zip(&self.counts)
+                .any(|((_, needed), count)| count >= needed)
+    }
+
+    pub fn reset(&mut self) {
+        self.default_count = 0;
+        self.counts.iter_mut().for_each(|count| *count = 0);
+        self.batch.clear();
+        self.batch_overflow = 0;
+        self.save();
+    }
+
+    /// Changes counted across every subtree
+    pub fn total(&self) -> i32 {
+        self.default_count + self.counts.iter().sum::<i32>()
+    }
+
+    /// e.g. `2 out of 10, config: 0 out of 1`
+    pub fn summary(&self) -> String {
+        let mut parts = vec![format!("{} out of {}", self.default_count, self.default_needed)];
+        for ((path, needed), count) in self.subtrees.iter().zip(&self.counts) {
+            parts.push(format!("{}: {} out of {}", path, count, needed));
+        }
+        parts.join(", ")
+    }
+
+    /// The files changed since the last reset and how they spread over
+    /// directories, e.g. `3 files changed: src/a.ts, src/b.ts, config/app.json; src (2), config (1)`
+    pub fn batch_summary(&self) -> String {
+        let total = self.batch.len() + self.batch_overflow;
+        let mut files: Vec<String> = self
+            .batch
+            .iter()
+            .take(LISTED_FILES)
+            .map(|path| path.display().to_string())
+            .collect();
+        if total > files.len() {
+            files.push(format!("+{} more", total - files.len()));
+        }
+
+        let dirs = tally(self.batch.iter().map(|path| match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.display().to_string(),
+            _ => String::from("."),
+        }));
+
+        format!("{} files changed: {}; {}", total, files.join(", "), dirs)
+    }
+
+    /// What kind of activity the batch was, e.g.
+    /// `by extension: ts (4), json (1); by top-level dir: src (4), config (1)`
+    pub fn batch_breakdown(&self) -> String {
+        let extensions = tally(self.batch.iter().map(|path| match path.extension() {
+            Some(extension) => extension.to_string_lossy().into_owned(),
+            None => String::from("(none)"),
+        }));
+
+        let top_dirs = tally(self.batch.iter().map(|path| {
+            let mut components = path.components();
+            match (components.next(), components.next()) {
+                (Some(first), Some(_)) => first.as_os_str().to_string_lossy().into_owned(),
+                _ => String::from("."),
+            }
+        }));
+
+        format!("by extension: {}; by top-level dir: {}", extensions, top_dirs)
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.persist_to else {
+            return;
+        };
+
+        let pending = PendingChanges {
+            default_count: self.default_count,
+            subtrees: self
+                .subtrees
+                .iter()
+                .zip(&self.counts)
+                .map(|((subtree, _), count)| (subtree.clone(), *count))
+                .collect(),
+            batch: self.batch.clone(),
+            batch_overflow: self.batch_overflow,
+        };
+
+        let written = serde_json::to_string(&pending)
+            .map_err(|err| err.to_string())
+            .and_then(|contents| fs::write(path, contents).map_err(|err| err.to_string()));
+        if let Err(err) = written {
+            log!(LogLevel::Debug, "Couldn't persist pending changes to {}: {}", path.display(), err);
+        }
+    }
+
+    /// The most specific subtree containing `path`, relative subtrees are
+    /// resolved against the root the change was seen under
+    fn subtree_of(&self, root: &Path, path: &Path) -> Option<usize> {
+        self.subtrees
+            .iter()
+            .enumerate()
+            .map(|(index, (subtree, _))| (index, root.join(subtree)))
+            .filter(|(_, subtree)| path.starts_with(subtree))
+            .max_by_key(|(_, subtree)| subtree.components().count())
+            .map(|(index, _)| index)
+    }
+}
+
+/// The most common keys with their counts, e.g. `src (4), config (1)`
+fn tally(keys: impl Iterator<Item = String>) -> String {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for key in keys {
+        *counts.entry(key).or_default() += 1;
+    }
+
+    let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts
+        .iter()
+        .take(LISTED_DIRS)
+        .map(|(key, count)| format!("{} ({})", key, count))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Pending changes are kept next to the state file
+pub fn pending_changes_path(state_path: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.changes", state_path.display()))
+}
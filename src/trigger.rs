@@ -0,0 +1,76 @@
+use dusa_collection_utils::log;
+use dusa_collection_utils::log::LogLevel;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    ffi::OsStr,
+    path::PathBuf,
+    sync::mpsc::channel,
+    thread,
+};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+use crate::config::AppSpecificConfig;
+
+/// Watches `restart_trigger` and sends on the returned channel whenever the
+/// file is created or touched. The channel stays empty, and closed, when no
+/// trigger file is configured or it can't be watched.
+pub fn watch_restart_trigger(settings: &AppSpecificConfig) -> UnboundedReceiver<()> {
+    let (trigger_tx, trigger_rx) = unbounded_channel();
+
+    let trigger = match &settings.restart_trigger {
+        Some(trigger) => PathBuf::from(trigger),
+        None => return trigger_rx,
+    };
+
+    // The directory is watched so the file may be created and removed freely
+    let parent = match trigger.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let Some(name) = trigger.file_name().map(|name| name.to_os_string()) else {
+        log!(LogLevel::Warn, "restart_trigger {} isn't a file path", trigger.display());
+        return trigger_rx;
+    };
+
+    let (tx, rx) = channel();
+    let mut watcher = match RecommendedWatcher::new(tx, notify::Config::default()) {
+        Ok(watcher) => watcher,
+        Err(err) => {
+            log!(LogLevel::Warn, "Couldn't watch restart_trigger {}: {}", trigger.display(), err);
+            return trigger_rx;
+        }
+    };
+
+    if let Err(err) = watcher.watch(&parent, RecursiveMode::NonRecursive) {
+        log!(LogLevel::Warn, "Couldn't watch restart_trigger {}: {}", trigger.display(), err);
+        return trigger_rx;
+    }
+
+    log!(LogLevel::Info, "Touch {} to force a restart", trigger.display());
+    thread::spawn(move || {
+        // The watcher stops once dropped
+        let _watcher = watcher;
+
+        for event in rx {
+            let touched = match event {
+                Ok(event) => touches(&event, &name),
+                Err(err) => {
+                    log!(LogLevel::Debug, "Restart trigger watcher error: {:?}", err);
+                    false
+                }
+            };
+
+            if touched && trigger_tx.send(()).is_err() {
+                break;
+            }
+        }
+    });
+
+    trigger_rx
+}
+
+/// Creating, writing or touching the file counts, removing it doesn't
+fn touches(event: &Event, name: &OsStr) -> bool {
+    matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_))
+        && event.paths.iter().any(|path| path.file_name() == Some(name))
+}